@@ -13,9 +13,15 @@ struct VoxelMat {
 // @group(1) @binding(0)
 // var<uniform> render_distance: u32;
 
-// A GPU-suited representation of voxel materials.
+// A GPU-suited representation of voxel materials, indexed by material ID.
+// WebGL2 has no storage buffers, only the first 256 materials fit in a uniform array there.
+#ifdef VOXEL_MATERIALS_UNIFORM
 @group(1) @binding(0)
 var<uniform> voxel_materials: array<VoxelMat, 256>;
+#else
+@group(1) @binding(0)
+var<storage, read> voxel_materials: array<VoxelMat>;
+#endif
 
 @group(1) @binding(1)
 var color_texture: texture_2d<f32>;
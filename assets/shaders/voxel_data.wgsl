@@ -2,17 +2,13 @@
 // Layout of voxel information encoded into a single u32
 //
 //  00000000    00000000    00000000    00000000    
-//  XXXXXYYY    YYZZZZZ          NNN    MATERIAL
+//  ·····SSS    SSSSSNNN    MATERIAL    MATERIAL
 //
-// X: X position
-// Y: Y position
-// Z: Z position
-// ^ all unused bits?
+// S: voxel state, material specific
 // N: normal index in the VOXEL_NORMALS array
-// defined, but not used either
 // MATERIAL: material index in the palette
 // 
-// The remaining 5 free bits could be used to store UV data or additional info or even extend voxel material id size.
+// The remaining 5 free bits could be used to store UV data or additional info.
 
 // An array of voxel face normals 
 var<private> VOXEL_NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
@@ -26,7 +22,7 @@ var<private> VOXEL_NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
 
 // Extracts the normal face index from the encoded voxel data
 fn voxel_data_extract_normal(voxel_data: u32) -> vec3<f32> {
-    return VOXEL_NORMALS[voxel_data >> 8u & 7u];
+    return VOXEL_NORMALS[voxel_data >> 16u & 7u];
 }
 
 // fn voxel_data_extract_position(voxel_data: u32) -> vec3<f32> {
@@ -39,5 +35,10 @@ fn voxel_data_extract_normal(voxel_data: u32) -> vec3<f32> {
 
 // Extracts the material index from the encoded voxel data
 fn voxel_data_extract_material_index(voxel_data: u32) -> u32 {
-    return voxel_data & 255u;
+    return voxel_data & 65535u;
+}
+
+// Extracts the material specific voxel state from the encoded voxel data
+fn voxel_data_extract_state(voxel_data: u32) -> u32 {
+    return voxel_data >> 19u & 255u;
 }
\ No newline at end of file
@@ -19,7 +19,7 @@ use directories::BaseDirs;
 use crate::{voxel::{
     material::{VoxelMaterialRegistry, VoxelMaterial}, ChunkCommandQueue, ChunkEntities, ChunkLoadRadius,
     CurrentLocalPlayerChunk, DirtyChunks,
-    CHUNK_LENGTH, CHUNK_HEIGHT, player::{PlayerSettings, PlayerController}, terraingen::{self, noise::Heightmap}, CHUNK_LENGTH_U, WorldSettings, VoxelWorldPlugin, materials::Rock,
+    CHUNK_LENGTH, CHUNK_HEIGHT, player::{PlayerSettings, PlayerController}, terraingen::{self, noise::Heightmap}, CHUNK_LENGTH_U, WorldSettings, SaveMode, VoxelWorldPlugin, materials::Rock,
 }, AppState};
 
 fn display_debug_stats(mut egui: EguiContexts, diagnostics: Res<DiagnosticsStore>) {
@@ -178,7 +178,7 @@ fn display_world_info(
     player_pos: Res<CurrentLocalPlayerChunk>,
     mut chunk_loading_radius: ResMut<ChunkLoadRadius>,
     mut chunk_command_queue: ResMut<ChunkCommandQueue>,
-    world_settings: Res<WorldSettings>,
+    mut world_settings: ResMut<WorldSettings>,
     // lines: ResMut<DebugLines>,
     // shapes: ResMut<DebugShapes>,
     loaded_chunks: Res<ChunkEntities>,
@@ -189,8 +189,24 @@ fn display_world_info(
     let erosion = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&chunk_erosion);
     let chunk_peaks_valleys = terraingen::noise::get_chunk_peaks_valleys(player_pos.chunk_min, CHUNK_LENGTH_U, world_settings.seed);
     let peaks_valleys = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&chunk_peaks_valleys);
+    let chunk_humidity = terraingen::noise::get_chunk_humidity(player_pos.chunk_min, CHUNK_LENGTH_U, world_settings.seed);
+    let humidity = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&chunk_humidity);
+    let chunk_temperature = terraingen::noise::get_chunk_temperature(player_pos.chunk_min, CHUNK_LENGTH_U, world_settings.seed);
+    let temperature = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&chunk_temperature);
 
     let pos_in_chunk = player_pos.world_pos - player_pos.chunk_min.as_vec3();
+    let column_humidity = humidity.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32]);
+    let column_temperature = temperature.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32]);
+    let column_params = terraingen::spline::TerrainParams {
+        continentalness: continentalness.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32]),
+        erosion: erosion.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32]),
+        peaks_valleys: peaks_valleys.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32]),
+    };
+    let generator = terraingen::TERRAIN_GENERATOR.read().unwrap();
+    let terrain_shape = generator.terrain_splines().shape(&column_params);
+    let biome_name = generator
+        .biome_at(column_humidity, column_temperature)
+        .map_or("none", |biome| biome.name());
 
     egui::Window::new(format!("{} info", world_settings.name)).show(egui.ctx_mut(), |ui| {
         ui.heading("Chunks");
@@ -199,9 +215,17 @@ fn display_world_info(
             dirty_chunks.num_dirty()
         ));
         ui.label(format!("Loaded chunk count: {}", loaded_chunks.len()));
+        ui.label(format!("Unsaved modified chunks: {}", dirty_chunks.num_modified()));
+        ui.horizontal(|ui| {
+            ui.label("Save mode");
+            ui.radio_value(&mut world_settings.save_mode, SaveMode::Delta, "Delta");
+            ui.radio_value(&mut world_settings.save_mode, SaveMode::Full, "Full");
+        });
         ui.separator();
         ui.label("Horizontal chunk loading radius");
         ui.add(Slider::new(&mut chunk_loading_radius.horizontal, 2..=48));
+        ui.label("Vertical chunk loading radius");
+        ui.add(Slider::new(&mut chunk_loading_radius.vertical, 0..=4));
         ui.separator();
 
         if ui.button("Clear loaded chunks").clicked() {
@@ -219,7 +243,10 @@ fn display_world_info(
         ui.label(format!("Continentalness : {}", continentalness.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32])));
         ui.label(format!("Erosion : {}", erosion.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32])));
         ui.label(format!("Peaks&Valleys : {}", peaks_valleys.getf([pos_in_chunk.x as u32, pos_in_chunk.z as u32])));
-        // ui.label(format!("Current biome : {}", biome.name()));
+        ui.label(format!("Terrain offset : {}, factor : {}", terrain_shape.offset, terrain_shape.factor));
+        ui.label(format!("Humidity : {}", column_humidity));
+        ui.label(format!("Temperature : {}", column_temperature));
+        ui.label(format!("Current biome : {}", biome_name));
         ui.separator();
         ui.heading("Lighting info");
         // ui.label(format!("Time of day: {}", sky_light_entity.));
@@ -297,7 +324,7 @@ fn display_material_editor(
                     .for_each(|(mat_index, mat)| {
                         content.selectable_value(
                             &mut ui_state.selected_mat,
-                            mat_index as u8,
+                            mat_index as u16,
                             mat.name,
                         );
                     })
@@ -399,7 +426,7 @@ impl Plugin for DebugUIPlugins {
             .insert_resource(DebugUIState {
                 display_debug_info: true,
                 display_mat_debug: true,
-                selected_mat: Rock::into_voxel().0,
+                selected_mat: Rock::ID,
                 window_mode: WindowMode::Windowed,
                 use_vsync: false,
             });
@@ -412,7 +439,7 @@ pub struct DebugUIState {
     display_mat_debug: bool,
 
     // DD
-    pub selected_mat: u8,
+    pub selected_mat: u16,
     pub window_mode: WindowMode,
     pub use_vsync: bool,
 }
\ No newline at end of file
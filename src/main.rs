@@ -34,6 +34,84 @@ enum AppState {
 
 
 fn main() {
+    // `worldgen export-maps [--seed N] [--x X] [--z Z] [--width W] [--depth D] [--out DIR]`
+    // writes maps of the terrain generator instead of starting the game.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|x| x == "export-maps") {
+        export_maps_command(args.into_iter().skip(1));
+        return;
+    }
+    // `worldgen fsck <world name> [--repair]` checks the saved chunks of a world, repairing them if asked to.
+    if args.first().is_some_and(|x| x == "fsck") {
+        fsck_command(args.into_iter().skip(1));
+        return;
+    }
+    // `worldgen worlds <list | create | rename | duplicate | delete> ...` manages the saved worlds.
+    if args.first().is_some_and(|x| x == "worlds") {
+        if let Err(err) = worlds_command(&args[1..]) {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // `worldgen import-anvil <region dir> --world NAME --overwrite [--y-offset N] [--mapping FILE]` imports the region
+    // files of a Minecraft world into an existing world, replacing every chunk of the imported columns over the whole
+    // world height.
+    if args.first().is_some_and(|x| x == "import-anvil") {
+        if let Err(err) = import_anvil_command(&args[1..]) {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // `worldgen export <world name> <x> <y> <z> <x2> <y2> <z2> <file> [--mapping FILE] [--schem-version 2|3]` writes
+    // the box between two corners of a world, as a schematic (.schem), a MagicaVoxel model (.vox) or a mesh (.glb, .obj).
+    // Boxes are limited to 1024 voxels along each axis, 256 for .vox files.
+    if args.first().is_some_and(|x| x == "export") {
+        if let Err(err) = export_command(&args[1..]) {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // `worldgen import <file> --world NAME --at X Y Z [--mapping FILE] [--max-color-distance D]` pastes a schematic
+    // (.schem) or the models of a MagicaVoxel file (.vox, side by side along x) into an existing
+    // world. Model colours get the closest material, or a new one when further than the given distance.
+    if args.first().is_some_and(|x| x == "import") {
+        if let Err(err) = import_command(&args[1..]) {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // `worldgen [--world NAME] [--density]` plays the specified world, created on the fly if it doesn't exist.
+    // New worlds created with `--density` fill their terrain from a 3D density function, existing worlds keep theirs.
+    let mut world_settings = voxel::WorldSettings::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.as_slice().first()) {
+            ("--world", Some(name)) => {
+                world_settings.name = name.clone();
+                args.next();
+            }
+            ("--density", _) => {
+                world_settings.terrain_mode = voxel::terraingen::TerrainMode::Density(Default::default());
+            }
+            _ => {
+                eprintln!("usage: worldgen [--world NAME] [--density]");
+                std::process::exit(1);
+            }
+        }
+    }
+    // the metadata decides the seed, it's read before anything else uses the world settings.
+    let (world_meta, world_lock) = match voxel::saving::open_world(&mut world_settings) {
+        Ok(opened) => opened,
+        Err(err) => {
+            eprintln!("failed to open world {}: {:#}", world_settings.name, err);
+            std::process::exit(1);
+        }
+    };
+
     let mut app = App::default();
     app
         // .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
@@ -75,6 +153,9 @@ fn main() {
         .add_plugins(FrameTimeDiagnosticsPlugin)
         // .add_plugin(ProgressPlugin::new(GameState::AssetLoading).continue_to(GameState::GameRunning))
         .add_plugins(voxel::VoxelWorldPlugin)
+        .insert_resource(world_settings)
+        .insert_resource(world_meta)
+        .insert_resource(world_lock)
         .add_plugins(debug::DebugUIPlugins)
         // .add_startup_system(setup_boot_screen)
         .add_systems(Startup, setup)
@@ -86,6 +167,7 @@ fn main() {
 
 fn setup(
     settings: Res<PlayerSettings>,
+    world_meta: Res<voxel::saved_worlds::WorldMeta>,
     mut cmds: Commands
 ) {
     // basically useless here for now, might be useful when a world selection screen/menus are added.
@@ -118,7 +200,7 @@ fn setup(
         // will most likely need a complete rewrite of the app startup process because
         // it will inevitably lead to the creation of some kind of main menu and world
         // selection screen.
-        transform: Transform::from_xyz(2.0, 180.0, 2.0).looking_at(Vec3::ZERO, Vec3::Y),
+        transform: Transform::from_translation(Vec3::from(world_meta.spawn)).looking_at(Vec3::ZERO, Vec3::Y),
         ..Default::default()
     })
     .insert(voxel::player::PlayerController::default())
@@ -131,6 +213,299 @@ fn setup(
     });
 }
 
+fn export_maps_command(args: impl Iterator<Item = String>) {
+    let result = voxel::terraingen::maps::MapExportOptions::from_args(args)
+        .and_then(|options| voxel::terraingen::maps::export_maps(&options));
+
+    match result {
+        Ok(dir) => println!("maps written to {}", dir.display()),
+        Err(err) => {
+            eprintln!("failed to export maps: {:#}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn fsck_command(args: impl Iterator<Item = String>) {
+    let mut world_name = None;
+    let mut repair = false;
+    for arg in args {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            _ if world_name.is_none() => world_name = Some(arg),
+            _ => {
+                eprintln!("unexpected argument {}", arg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let Some(world_name) = world_name else {
+        eprintln!("usage: worldgen fsck <world name> [--repair]");
+        std::process::exit(1);
+    };
+
+    match voxel::fsck::fsck_world(&world_name, repair) {
+        Ok(report) => {
+            println!("{}", report);
+            if !report.is_clean() && !repair {
+                std::process::exit(2);
+            }
+        }
+        Err(err) => {
+            eprintln!("failed to check world {}: {:#}", world_name, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn worlds_command(args: &[String]) -> anyhow::Result<()> {
+    use voxel::saved_worlds;
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["list"] => {
+            for world in saved_worlds::list_worlds()? {
+                match world.meta {
+                    Some(meta) => println!(
+                        "{}: seed {}, {} version {}, last played at {}",
+                        world.name, meta.seed, meta.generator_id, meta.generator_version, meta.last_played
+                    ),
+                    None => println!("{}: no metadata", world.name),
+                }
+            }
+        }
+        ["create", name] => {
+            saved_worlds::create_world(name, 0)?;
+        }
+        ["create", name, "--seed", seed] => {
+            saved_worlds::create_world(name, seed.parse().map_err(|_| anyhow::anyhow!("invalid seed {}", seed))?)?;
+        }
+        ["rename", name, new_name] => saved_worlds::rename_world(name, new_name)?,
+        ["duplicate", name, new_name] => saved_worlds::duplicate_world(name, new_name)?,
+        ["delete", name] => saved_worlds::delete_world(name)?,
+        _ => anyhow::bail!(
+            "usage: worldgen worlds list | create <name> [--seed N] | rename <name> <new name> \
+             | duplicate <name> <new name> | delete <name>"
+        ),
+    }
+    Ok(())
+}
+
+fn import_anvil_command(args: &[String]) -> anyhow::Result<()> {
+    use voxel::{anvil, formats::block_mapping::BlockMapping, offline::OfflineWorld};
+
+    let usage = || {
+        anyhow::anyhow!(
+            "usage: worldgen import-anvil <region dir> --world NAME --overwrite [--y-offset N] [--mapping FILE]\n\
+            every chunk of the imported columns is replaced over the whole world height, --overwrite confirms it"
+        )
+    };
+    let mut region_dir = None;
+    let mut world_name = None;
+    let mut overwrite = false;
+    let mut y_offset = 0;
+    let mut mapping_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--world" => world_name = Some(args.next().ok_or_else(usage)?),
+            "--overwrite" => overwrite = true,
+            "--y-offset" => {
+                let value = args.next().ok_or_else(usage)?;
+                y_offset = value.parse().map_err(|_| anyhow::anyhow!("invalid y offset {}", value))?;
+            }
+            "--mapping" => mapping_path = Some(args.next().ok_or_else(usage)?),
+            _ if region_dir.is_none() => region_dir = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+    let (Some(region_dir), Some(world_name), true) = (region_dir, world_name, overwrite) else {
+        return Err(usage());
+    };
+
+    init_command_logging();
+    let world = OfflineWorld::open(world_name)?;
+    let mut mapping = BlockMapping::default();
+    if let Some(path) = mapping_path {
+        mapping.parse(&std::fs::read_to_string(path)?, &world.registry)?;
+    }
+
+    let count = anvil::import_anvil_world(std::path::Path::new(region_dir), &world, &mapping, y_offset)?;
+    println!("imported {} chunks into world {}", count, world_name);
+    Ok(())
+}
+
+fn export_command(args: &[String]) -> anyhow::Result<()> {
+    use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+    use voxel::{
+        formats::{
+            block_mapping::BlockMapping,
+            gltf, obj,
+            schem::{self, SchemVersion},
+            vox,
+        },
+        offline::{OfflineWorld, MAX_BOX_SIZE},
+        schematic::Schematic,
+    };
+
+    let usage = || {
+        anyhow::anyhow!(
+            "usage: worldgen export <world name> <x> <y> <z> <x2> <y2> <z2> <.schem | .vox | .glb | .obj file> \
+            [--mapping FILE] [--schem-version 2|3]"
+        )
+    };
+    let mut positional = Vec::new();
+    let mut mapping_path = None;
+    let mut schem_version = SchemVersion::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mapping" => mapping_path = Some(args.next().ok_or_else(usage)?),
+            "--schem-version" => {
+                schem_version = match args.next().ok_or_else(usage)?.as_str() {
+                    "2" => SchemVersion::V2,
+                    "3" => SchemVersion::V3,
+                    version => anyhow::bail!("unsupported schematic version {}, expected 2 or 3", version),
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let [world_name, corners @ .., path] = positional.as_slice() else {
+        return Err(usage());
+    };
+    let corners = corners
+        .iter()
+        .map(|x| x.parse::<i32>().map_err(|_| anyhow::anyhow!("invalid coordinate {}", x)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let [x, y, z, x2, y2, z2] = corners.as_slice() else {
+        return Err(usage());
+    };
+
+    // the size is checked before any chunk gets loaded or generated for the box.
+    let path = std::path::Path::new(path);
+    let format = path.extension().and_then(|x| x.to_str()).map(|x| x.to_ascii_lowercase());
+    let max_size = match format.as_deref() {
+        Some("vox") => vox::VOX_MAX_SIZE,
+        Some("schem") | Some("glb") | Some("obj") => MAX_BOX_SIZE,
+        _ => anyhow::bail!("unsupported export format {:?}, expected a .schem, .vox, .glb or .obj file", path),
+    };
+    let sizes = [(x, x2), (y, y2), (z, z2)].map(|(a, b)| (*a as i64 - *b as i64).abs() + 1);
+    if sizes.iter().any(|x| *x > max_size as i64) {
+        anyhow::bail!("can't export a box of {:?} voxels to {:?}, the limit is {} along each axis", sizes, path, max_size);
+    }
+    let (a, b) = (ILIVec3::new(*x, *y, *z), ILIVec3::new(*x2, *y2, *z2));
+    let extent = Extent::from_min_and_max(a.min(b), a.max(b));
+
+    init_command_logging();
+    let mut world = OfflineWorld::open(world_name)?;
+    world.load_box(extent)?;
+
+    match format.as_deref() {
+        Some("schem") => {
+            let mut mapping = BlockMapping::default();
+            if let Some(mapping_path) = mapping_path {
+                mapping.parse(&std::fs::read_to_string(mapping_path)?, &world.registry)?;
+            }
+            let schematic = Schematic::extract(&world.chunks, extent);
+            schem::export_schem(path, schematic.buffer(), &mapping, schem_version)?
+        }
+        Some("vox") => vox::export_world_box(path, &world.chunks, extent, &world.registry)?,
+        Some("glb") => gltf::export_world_box_glb(path, &world.chunks, extent, &world.registry)?,
+        Some("obj") => obj::export_world_box_obj(path, &world.chunks, extent, &world.registry)?,
+        _ => unreachable!(),
+    }
+    println!("exported world {} to {}", world_name, path.display());
+    Ok(())
+}
+
+fn import_command(args: &[String]) -> anyhow::Result<()> {
+    use voxel::{
+        formats::{
+            block_mapping::BlockMapping,
+            schem,
+            vox::{self, PaletteMapping},
+        },
+        offline::OfflineWorld,
+        schematic::{PasteMode, Schematic},
+    };
+
+    let usage = || {
+        anyhow::anyhow!(
+            "usage: worldgen import <.schem | .vox file> --world NAME --at X Y Z [--mapping FILE] [--max-color-distance D]"
+        )
+    };
+    let mut path = None;
+    let mut world_name = None;
+    let mut origin = None;
+    let mut mapping_path = None;
+    let mut palette_mapping = PaletteMapping::Nearest;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--world" => world_name = Some(args.next().ok_or_else(usage)?),
+            "--at" => {
+                let mut coordinate = || {
+                    let value = args.next().ok_or_else(usage)?;
+                    value.parse::<i32>().map_err(|_| anyhow::anyhow!("invalid coordinate {}", value))
+                };
+                origin = Some(IVec3::new(coordinate()?, coordinate()?, coordinate()?));
+            }
+            "--mapping" => mapping_path = Some(args.next().ok_or_else(usage)?),
+            "--max-color-distance" => {
+                let value = args.next().ok_or_else(usage)?;
+                let max_distance = value
+                    .parse::<f32>()
+                    .map_err(|_| anyhow::anyhow!("invalid colour distance {}", value))?;
+                palette_mapping = PaletteMapping::RegisterMissing { max_distance };
+            }
+            _ if path.is_none() => path = Some(std::path::Path::new(arg)),
+            _ => return Err(usage()),
+        }
+    }
+    let (Some(path), Some(world_name), Some(origin)) = (path, world_name, origin) else {
+        return Err(usage());
+    };
+
+    init_command_logging();
+    let mut world = OfflineWorld::open(world_name)?;
+    let schematics = match path.extension().and_then(|x| x.to_str()).map(|x| x.to_ascii_lowercase()).as_deref() {
+        Some("schem") => {
+            let mut mapping = BlockMapping::default();
+            if let Some(mapping_path) = mapping_path {
+                mapping.parse(&std::fs::read_to_string(mapping_path)?, &world.registry)?;
+            }
+            vec![Schematic::from_buffer(schem::import_schem(path, &mapping)?)]
+        }
+        Some("vox") => vox::import_vox(path, &mut world.registry, palette_mapping)?
+            .into_iter()
+            .map(Schematic::from_buffer)
+            .collect(),
+        _ => anyhow::bail!("unsupported import format {:?}, expected a .schem or .vox file", path),
+    };
+    // the chunks are saved with the names of the materials the models were given.
+    world.record_new_materials()?;
+
+    let mut touched = bevy::utils::HashSet::new();
+    let mut model_origin = origin;
+    for schematic in &schematics {
+        world.load_box(schematic.extent_at(model_origin))?;
+        touched.extend(schematic.paste_unrecorded(&mut world.chunks, model_origin, PasteMode::IgnoreAir));
+        model_origin.x += schematic.dims().x as i32 + 1;
+    }
+    let saved = world.save_chunks(touched)?;
+    println!("imported {} into {} chunks of world {}", path.display(), saved, world_name);
+    Ok(())
+}
+
+/// Prints the warnings of the commands which don't start the game, e.g. about the data they skip.
+fn init_command_logging() {
+    App::new().add_plugins(bevy::log::LogPlugin {
+        level: bevy::log::Level::WARN,
+        ..default()
+    });
+}
+
 #[derive(AssetCollection, Resource)]
 struct MyAssets {
     #[asset(path = "textures/uv_checker.png")]
@@ -0,0 +1,154 @@
+use anyhow::{bail, Result};
+use bevy::{log::warn, utils::HashMap};
+
+use crate::voxel::{
+    material::{VoxelMaterial, VoxelMaterialRegistry},
+    materials::{
+        Bedrock, Cactus, Dirt, Grass, Leaves, PineLeaves, PineWood, Rock, Sand, Sandstone, Snow, Void, Water, Wood,
+    },
+};
+
+/// Block written for voxels whose material has no block mapped to it.
+const DEFAULT_EXPORT_BLOCK: &str = "minecraft:stone";
+const AIR_BLOCK: &str = "minecraft:air";
+
+/// Maps the namespaced block IDs of Minecraft based tools (e.g. `minecraft:stone`) to material IDs, and back.
+/// Block states properties (`minecraft:oak_log[axis=y]`) are ignored.
+#[derive(Clone, Debug)]
+pub struct BlockMapping {
+    to_material: HashMap<String, u16>,
+    /// Block written for each material, the first one mapped to it.
+    to_block: HashMap<u16, String>,
+    /// Material used for unmapped blocks.
+    pub fallback: u16,
+}
+
+impl BlockMapping {
+    /// A mapping with no entries, unmapped blocks becoming the fallback material.
+    pub fn empty(fallback: u16) -> Self {
+        Self {
+            to_material: Default::default(),
+            to_block: Default::default(),
+            fallback,
+        }
+    }
+
+    /// Maps a block to a material type.
+    pub fn map<M: VoxelMaterial>(&mut self, block: &str) -> &mut Self {
+        self.map_id(block, M::ID)
+    }
+
+    /// Maps a block to a material ID, useful for materials registered at runtime.
+    pub fn map_id(&mut self, block: &str, material: u16) -> &mut Self {
+        let block = normalize(block);
+        self.to_block.entry(material).or_insert_with(|| block.clone());
+        self.to_material.insert(block, material);
+        self
+    }
+
+    /// Returns the material for a block ID, ignoring its properties.
+    pub fn material_for(&self, block: &str) -> u16 {
+        let block = normalize(block);
+        match self.to_material.get(&block) {
+            Some(material) => *material,
+            None => {
+                warn!("no material mapped to block {}", block);
+                self.fallback
+            }
+        }
+    }
+
+    /// Returns the block ID to write for a material.
+    pub fn block_for(&self, material: u16) -> &str {
+        match self.to_block.get(&material) {
+            Some(block) => block,
+            None if material == Void::ID => AIR_BLOCK,
+            None => DEFAULT_EXPORT_BLOCK,
+        }
+    }
+
+    /// Adds the entries of a mapping file to this mapping. Each line maps a block to a registered material name,
+    /// e.g. `minecraft:stone = Rock`. Empty lines and lines starting with `#` are ignored.
+    pub fn parse(&mut self, text: &str, registry: &VoxelMaterialRegistry) -> Result<&mut Self> {
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((block, material)) = line.split_once('=') else {
+                bail!("line {}: expected `block = material`", line_number + 1);
+            };
+            let Some(material) = registry.get_id_by_name(material.trim()) else {
+                bail!("line {}: unknown material {}", line_number + 1, material.trim());
+            };
+            self.map_id(block.trim(), material);
+        }
+
+        Ok(self)
+    }
+}
+
+impl Default for BlockMapping {
+    /// Maps the common vanilla terrain blocks to the base materials, unmapped blocks becoming rock.
+    fn default() -> Self {
+        let mut mapping = Self::empty(Rock::ID);
+        mapping
+            .map::<Void>("minecraft:air")
+            .map::<Void>("minecraft:cave_air")
+            .map::<Void>("minecraft:void_air")
+            .map::<Bedrock>("minecraft:bedrock")
+            .map::<Rock>("minecraft:stone")
+            .map::<Rock>("minecraft:cobblestone")
+            .map::<Rock>("minecraft:andesite")
+            .map::<Rock>("minecraft:diorite")
+            .map::<Rock>("minecraft:granite")
+            .map::<Rock>("minecraft:deepslate")
+            .map::<Rock>("minecraft:tuff")
+            .map::<Rock>("minecraft:gravel")
+            .map::<Dirt>("minecraft:dirt")
+            .map::<Dirt>("minecraft:coarse_dirt")
+            .map::<Dirt>("minecraft:rooted_dirt")
+            .map::<Dirt>("minecraft:podzol")
+            .map::<Dirt>("minecraft:mud")
+            .map::<Dirt>("minecraft:dirt_path")
+            .map::<Dirt>("minecraft:farmland")
+            .map::<Grass>("minecraft:grass_block")
+            .map::<Grass>("minecraft:moss_block")
+            .map::<Sand>("minecraft:sand")
+            .map::<Sand>("minecraft:red_sand")
+            .map::<Sandstone>("minecraft:sandstone")
+            .map::<Sandstone>("minecraft:smooth_sandstone")
+            .map::<Sandstone>("minecraft:cut_sandstone")
+            .map::<Sandstone>("minecraft:red_sandstone")
+            .map::<Snow>("minecraft:snow_block")
+            .map::<Snow>("minecraft:snow")
+            .map::<Snow>("minecraft:powder_snow")
+            .map::<Snow>("minecraft:packed_ice")
+            .map::<Water>("minecraft:water")
+            .map::<Cactus>("minecraft:cactus")
+            .map::<Wood>("minecraft:oak_log")
+            .map::<Wood>("minecraft:birch_log")
+            .map::<Wood>("minecraft:jungle_log")
+            .map::<Wood>("minecraft:acacia_log")
+            .map::<Wood>("minecraft:dark_oak_log")
+            .map::<Wood>("minecraft:oak_planks")
+            .map::<Leaves>("minecraft:oak_leaves")
+            .map::<Leaves>("minecraft:birch_leaves")
+            .map::<Leaves>("minecraft:jungle_leaves")
+            .map::<Leaves>("minecraft:acacia_leaves")
+            .map::<Leaves>("minecraft:dark_oak_leaves")
+            .map::<PineWood>("minecraft:spruce_log")
+            .map::<PineLeaves>("minecraft:spruce_leaves");
+        mapping
+    }
+}
+
+/// Strips the block state properties and adds the `minecraft` namespace if there's none.
+fn normalize(block: &str) -> String {
+    let block = block.split_once('[').map_or(block, |(id, _)| id).trim();
+    match block.contains(':') {
+        true => block.to_string(),
+        false => format!("minecraft:{}", block),
+    }
+}
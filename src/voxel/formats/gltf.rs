@@ -0,0 +1,179 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+use serde_json::{json, Value};
+
+use super::world_mesh::WorldMesh;
+use crate::voxel::{
+    material::VoxelMaterialRegistry,
+    storage::ChunkMap,
+    ChunkShape, Voxel,
+};
+
+const GLB_MAGIC: [u8; 4] = *b"glTF";
+const GLB_VERSION: u32 = 2;
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const COMPONENT_FLOAT: u32 = 5126;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+
+/// Writes a world mesh as a binary glTF 2.0 file (`.glb`), with one primitive and one PBR material per voxel material.
+/// Colors are written in linear space as required by glTF, one voxel being one meter.
+pub fn export_glb(path: &Path, mesh: &WorldMesh, registry: &VoxelMaterialRegistry) -> Result<()> {
+    let mut bin = Vec::<u8>::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut materials = Vec::new();
+    let mut primitives = Vec::new();
+
+    for primitive in mesh.primitives.iter().filter(|x| !x.indices.is_empty()) {
+        let (min, max) = primitive.positions.iter().fold(
+            ([f32::MAX; 3], [f32::MIN; 3]),
+            |(min, max), position| {
+                (
+                    [0, 1, 2].map(|x| min[x].min(position[x])),
+                    [0, 1, 2].map(|x| max[x].max(position[x])),
+                )
+            },
+        );
+
+        let mut push_accessor = |data: &[u8], target: u32, component: u32, count: usize, kind: &str| {
+            buffer_views.push(json!({
+                "buffer": 0,
+                "byteOffset": bin.len(),
+                "byteLength": data.len(),
+                "target": target,
+            }));
+            bin.extend_from_slice(data);
+            accessors.push(json!({
+                "bufferView": buffer_views.len() - 1,
+                "componentType": component,
+                "count": count,
+                "type": kind,
+            }));
+            accessors.len() - 1
+        };
+
+        let vertex_count = primitive.positions.len();
+        let position = push_accessor(
+            &floats_to_bytes(primitive.positions.iter().flatten()),
+            ARRAY_BUFFER,
+            COMPONENT_FLOAT,
+            vertex_count,
+            "VEC3",
+        );
+        let normal = push_accessor(
+            &floats_to_bytes(primitive.normals.iter().flatten()),
+            ARRAY_BUFFER,
+            COMPONENT_FLOAT,
+            vertex_count,
+            "VEC3",
+        );
+        let tex_coord = push_accessor(
+            &floats_to_bytes(primitive.tex_coords.iter().flatten()),
+            ARRAY_BUFFER,
+            COMPONENT_FLOAT,
+            vertex_count,
+            "VEC2",
+        );
+        let indices = push_accessor(
+            &primitive.indices.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>(),
+            ELEMENT_ARRAY_BUFFER,
+            COMPONENT_UNSIGNED_INT,
+            primitive.indices.len(),
+            "SCALAR",
+        );
+        // the position accessor must have its bounds.
+        accessors[position]["min"] = json!(min);
+        accessors[position]["max"] = json!(max);
+
+        materials.push(gltf_material(primitive.material, registry));
+        primitives.push(json!({
+            "attributes": {
+                "POSITION": position,
+                "NORMAL": normal,
+                "TEXCOORD_0": tex_coord,
+            },
+            "indices": indices,
+            "material": materials.len() - 1,
+        }));
+    }
+
+    if primitives.is_empty() {
+        bail!("the mesh is empty, there's nothing to export");
+    }
+
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "yavafg" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0, "name": "terrain" }],
+        "meshes": [{ "name": "terrain", "primitives": primitives }],
+        "materials": materials,
+        "accessors": accessors,
+        "bufferViews": buffer_views,
+        "buffers": [{ "byteLength": bin.len() }],
+    });
+
+    std::fs::write(path, write_glb(serde_json::to_vec(&document)?, bin))?;
+    Ok(())
+}
+
+/// Meshes a box of the world and writes it as a `.glb` file, see [`WorldMesh::from_world_box`] and [`export_glb`].
+pub fn export_world_box_glb(
+    path: &Path,
+    chunks: &ChunkMap<Voxel, ChunkShape>,
+    extent: Extent<ILIVec3>,
+    registry: &VoxelMaterialRegistry,
+) -> Result<()> {
+    export_glb(path, &WorldMesh::from_world_box(chunks, extent), registry)
+}
+
+fn gltf_material(material: u16, registry: &VoxelMaterialRegistry) -> Value {
+    let Some(info) = registry.get_by_id(material) else {
+        return json!({ "name": format!("Material{}", material) });
+    };
+
+    let base_color = info.base_color.as_linear_rgba_f32();
+    let [r, g, b, _] = info.emissive.as_linear_rgba_f32();
+    let emissive = [r, g, b].map(|x| x.clamp(0.0, 1.0));
+    let mut gltf_material = json!({
+        "name": info.name,
+        "pbrMetallicRoughness": {
+            "baseColorFactor": base_color,
+            "metallicFactor": info.metallic.clamp(0.0, 1.0),
+            "roughnessFactor": info.perceptual_roughness.clamp(0.0, 1.0),
+        },
+        "emissiveFactor": emissive,
+    });
+    if base_color[3] < 1.0 {
+        gltf_material["alphaMode"] = json!("BLEND");
+    }
+    gltf_material
+}
+
+fn floats_to_bytes<'a>(values: impl Iterator<Item = &'a f32>) -> Vec<u8> {
+    values.flat_map(|x| x.to_le_bytes()).collect()
+}
+
+/// Assembles the JSON and binary chunks into a `.glb` container, chunks being padded to 4 bytes.
+fn write_glb(mut json: Vec<u8>, mut bin: Vec<u8>) -> Vec<u8> {
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+    let mut bytes = Vec::with_capacity(length);
+    bytes.extend_from_slice(&GLB_MAGIC);
+    bytes.extend_from_slice(&GLB_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(length as u32).to_le_bytes());
+    for (kind, data) in [(GLB_CHUNK_JSON, json), (GLB_CHUNK_BIN, bin)] {
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.extend_from_slice(&data);
+    }
+    bytes
+}
@@ -0,0 +1,20 @@
+/// MagicaVoxel `.vox` models.
+pub mod vox;
+
+/// Named Binary Tag documents, the serialization format of Minecraft based tools.
+pub mod nbt;
+
+/// Mapping between Minecraft block IDs and voxel materials.
+pub mod block_mapping;
+
+/// Sponge `.schem` schematics.
+pub mod schem;
+
+/// Meshing of world boxes into a single mesh, for the mesh exporters.
+pub mod world_mesh;
+
+/// Binary glTF 2.0 (`.glb`) meshes.
+pub mod gltf;
+
+/// Wavefront `.obj` meshes and their `.mtl` material libraries.
+pub mod obj;
@@ -0,0 +1,288 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+
+use anyhow::{bail, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+/// Compounds are kept sorted by name so files are written deterministically.
+pub type NbtCompound = BTreeMap<String, NbtTag>;
+
+/// Nesting deeper than this is considered malformed rather than risking a stack overflow.
+const MAX_DEPTH: usize = 512;
+
+/// A Named Binary Tag value, as used by Minecraft and the tools built around it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NbtTag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<NbtTag>),
+    Compound(NbtCompound),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl NbtTag {
+    const END_ID: u8 = 0;
+
+    fn id(&self) -> u8 {
+        match self {
+            Self::Byte(_) => 1,
+            Self::Short(_) => 2,
+            Self::Int(_) => 3,
+            Self::Long(_) => 4,
+            Self::Float(_) => 5,
+            Self::Double(_) => 6,
+            Self::ByteArray(_) => 7,
+            Self::String(_) => 8,
+            Self::List(_) => 9,
+            Self::Compound(_) => 10,
+            Self::IntArray(_) => 11,
+            Self::LongArray(_) => 12,
+        }
+    }
+
+    /// Returns the value of any integer tag, widened.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Byte(x) => Some(*x as i64),
+            Self::Short(x) => Some(*x as i64),
+            Self::Int(x) => Some(*x as i64),
+            Self::Long(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[NbtTag]> {
+        match self {
+            Self::List(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&NbtCompound> {
+        match self {
+            Self::Compound(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte_array(&self) -> Option<&[i8]> {
+        match self {
+            Self::ByteArray(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        match self {
+            Self::LongArray(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+/// Reads an uncompressed NBT document, returning the name and content of its root compound.
+pub fn read_nbt(bytes: &[u8]) -> Result<(String, NbtCompound)> {
+    let mut reader = bytes;
+    let id = read_u8(&mut reader)?;
+    if id != 10 {
+        bail!("the NBT root must be a compound, found tag {}", id);
+    }
+
+    let name = read_string(&mut reader)?;
+    let NbtTag::Compound(root) = read_payload(&mut reader, id, 0)? else {
+        unreachable!();
+    };
+    Ok((name, root))
+}
+
+/// Reads a gzip compressed NBT document.
+pub fn read_gzip_nbt(bytes: &[u8]) -> Result<(String, NbtCompound)> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    read_nbt(&decompressed)
+}
+
+/// Writes an uncompressed NBT document made of the specified root compound.
+pub fn write_nbt(name: &str, root: &NbtCompound) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    bytes.push(10);
+    write_string(&mut bytes, name)?;
+    write_compound(&mut bytes, root)?;
+    Ok(bytes)
+}
+
+/// Writes a gzip compressed NBT document made of the specified root compound.
+pub fn write_gzip_nbt(name: &str, root: &NbtCompound) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&write_nbt(name, root)?)?;
+    Ok(encoder.finish()?)
+}
+
+fn read_payload(reader: &mut &[u8], id: u8, depth: usize) -> Result<NbtTag> {
+    if depth > MAX_DEPTH {
+        bail!("NBT nesting is too deep");
+    }
+
+    Ok(match id {
+        1 => NbtTag::Byte(read_array::<1>(reader)?[0] as i8),
+        2 => NbtTag::Short(i16::from_be_bytes(read_array(reader)?)),
+        3 => NbtTag::Int(i32::from_be_bytes(read_array(reader)?)),
+        4 => NbtTag::Long(i64::from_be_bytes(read_array(reader)?)),
+        5 => NbtTag::Float(f32::from_be_bytes(read_array(reader)?)),
+        6 => NbtTag::Double(f64::from_be_bytes(read_array(reader)?)),
+        7 => {
+            let length = read_length(reader)?;
+            NbtTag::ByteArray(read_bytes(reader, length)?.iter().map(|x| *x as i8).collect())
+        }
+        8 => NbtTag::String(read_string(reader)?),
+        9 => {
+            let element_id = read_u8(reader)?;
+            let length = read_length(reader)?;
+            let mut list = Vec::with_capacity(length.min(reader.len()));
+            for _ in 0..length {
+                list.push(read_payload(reader, element_id, depth + 1)?);
+            }
+            NbtTag::List(list)
+        }
+        10 => {
+            let mut compound = NbtCompound::new();
+            loop {
+                let id = read_u8(reader)?;
+                if id == NbtTag::END_ID {
+                    break;
+                }
+                let name = read_string(reader)?;
+                compound.insert(name, read_payload(reader, id, depth + 1)?);
+            }
+            NbtTag::Compound(compound)
+        }
+        11 => {
+            let length = read_length(reader)?;
+            NbtTag::IntArray(
+                read_bytes(reader, length * 4)?
+                    .chunks_exact(4)
+                    .map(|x| i32::from_be_bytes(x.try_into().unwrap()))
+                    .collect(),
+            )
+        }
+        12 => {
+            let length = read_length(reader)?;
+            NbtTag::LongArray(
+                read_bytes(reader, length * 8)?
+                    .chunks_exact(8)
+                    .map(|x| i64::from_be_bytes(x.try_into().unwrap()))
+                    .collect(),
+            )
+        }
+        _ => bail!("unknown NBT tag {}", id),
+    })
+}
+
+fn write_payload(bytes: &mut Vec<u8>, tag: &NbtTag) -> Result<()> {
+    match tag {
+        NbtTag::Byte(x) => bytes.push(*x as u8),
+        NbtTag::Short(x) => bytes.extend_from_slice(&x.to_be_bytes()),
+        NbtTag::Int(x) => bytes.extend_from_slice(&x.to_be_bytes()),
+        NbtTag::Long(x) => bytes.extend_from_slice(&x.to_be_bytes()),
+        NbtTag::Float(x) => bytes.extend_from_slice(&x.to_be_bytes()),
+        NbtTag::Double(x) => bytes.extend_from_slice(&x.to_be_bytes()),
+        NbtTag::ByteArray(x) => {
+            write_length(bytes, x.len())?;
+            bytes.extend(x.iter().map(|x| *x as u8));
+        }
+        NbtTag::String(x) => write_string(bytes, x)?,
+        NbtTag::List(x) => {
+            let element_id = x.first().map_or(NbtTag::END_ID, |x| x.id());
+            if x.iter().any(|x| x.id() != element_id) {
+                bail!("NBT list elements must all have the same type");
+            }
+            bytes.push(element_id);
+            write_length(bytes, x.len())?;
+            for element in x {
+                write_payload(bytes, element)?;
+            }
+        }
+        NbtTag::Compound(x) => write_compound(bytes, x)?,
+        NbtTag::IntArray(x) => {
+            write_length(bytes, x.len())?;
+            x.iter().for_each(|x| bytes.extend_from_slice(&x.to_be_bytes()));
+        }
+        NbtTag::LongArray(x) => {
+            write_length(bytes, x.len())?;
+            x.iter().for_each(|x| bytes.extend_from_slice(&x.to_be_bytes()));
+        }
+    }
+    Ok(())
+}
+
+fn write_compound(bytes: &mut Vec<u8>, compound: &NbtCompound) -> Result<()> {
+    for (name, tag) in compound {
+        bytes.push(tag.id());
+        write_string(bytes, name)?;
+        write_payload(bytes, tag)?;
+    }
+    bytes.push(NbtTag::END_ID);
+    Ok(())
+}
+
+fn read_bytes<'a>(reader: &mut &'a [u8], count: usize) -> Result<&'a [u8]> {
+    if reader.len() < count {
+        bail!("unexpected end of NBT data");
+    }
+    let (bytes, rest) = reader.split_at(count);
+    *reader = rest;
+    Ok(bytes)
+}
+
+fn read_array<const N: usize>(reader: &mut &[u8]) -> Result<[u8; N]> {
+    Ok(read_bytes(reader, N)?.try_into().unwrap())
+}
+
+fn read_u8(reader: &mut &[u8]) -> Result<u8> {
+    Ok(read_array::<1>(reader)?[0])
+}
+
+fn read_length(reader: &mut &[u8]) -> Result<usize> {
+    let length = i32::from_be_bytes(read_array(reader)?);
+    // negative lengths are written by some tools for empty lists.
+    Ok(length.max(0) as usize)
+}
+
+fn write_length(bytes: &mut Vec<u8>, length: usize) -> Result<()> {
+    let Ok(length) = i32::try_from(length) else {
+        bail!("NBT array of {} elements is too long", length);
+    };
+    bytes.extend_from_slice(&length.to_be_bytes());
+    Ok(())
+}
+
+// strings are "modified UTF-8", which only differs from UTF-8 for null and supplementary characters.
+fn read_string(reader: &mut &[u8]) -> Result<String> {
+    let length = u16::from_be_bytes(read_array(reader)?) as usize;
+    Ok(String::from_utf8_lossy(read_bytes(reader, length)?).into_owned())
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) -> Result<()> {
+    let Ok(length) = u16::try_from(string.len()) else {
+        bail!("NBT string of {} bytes is too long", string.len());
+    };
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+    Ok(())
+}
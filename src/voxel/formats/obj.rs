@@ -0,0 +1,93 @@
+use std::{fmt::Write as _, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+
+use super::world_mesh::WorldMesh;
+use crate::voxel::{
+    material::VoxelMaterialRegistry,
+    storage::ChunkMap,
+    ChunkShape, Voxel,
+};
+
+/// Writes a world mesh as a Wavefront `.obj` file, along with a `.mtl` material library next to it.
+/// Materials use the PBR extension of the MTL format (`Pr`, `Pm`, `Ke`), which Blender and most slicers understand.
+pub fn export_obj(path: &Path, mesh: &WorldMesh, registry: &VoxelMaterialRegistry) -> Result<()> {
+    if mesh.is_empty() {
+        bail!("the mesh is empty, there's nothing to export");
+    }
+
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| anyhow!("invalid path {:?}", path))?;
+
+    let mut obj = String::new();
+    let mut mtl = String::new();
+    writeln!(obj, "mtllib {}", mtl_name)?;
+    writeln!(obj, "o terrain")?;
+
+    // indices are global and start at 1.
+    let mut first_vertex = 1;
+    for primitive in mesh.primitives.iter().filter(|x| !x.indices.is_empty()) {
+        let name = material_name(primitive.material, registry);
+        write_mtl_material(&mut mtl, &name, primitive.material, registry)?;
+
+        for [x, y, z] in &primitive.positions {
+            writeln!(obj, "v {} {} {}", x, y, z)?;
+        }
+        for [u, v] in &primitive.tex_coords {
+            writeln!(obj, "vt {} {}", u, v)?;
+        }
+        for [x, y, z] in &primitive.normals {
+            writeln!(obj, "vn {} {} {}", x, y, z)?;
+        }
+
+        writeln!(obj, "usemtl {}", name)?;
+        for triangle in primitive.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|x| triangle[x] + first_vertex);
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        first_vertex += primitive.positions.len() as u32;
+    }
+
+    std::fs::write(path, obj)?;
+    std::fs::write(mtl_path, mtl)?;
+    Ok(())
+}
+
+/// Meshes a box of the world and writes it as `.obj` and `.mtl` files,
+/// see [`WorldMesh::from_world_box`] and [`export_obj`].
+pub fn export_world_box_obj(
+    path: &Path,
+    chunks: &ChunkMap<Voxel, ChunkShape>,
+    extent: Extent<ILIVec3>,
+    registry: &VoxelMaterialRegistry,
+) -> Result<()> {
+    export_obj(path, &WorldMesh::from_world_box(chunks, extent), registry)
+}
+
+fn material_name(material: u16, registry: &VoxelMaterialRegistry) -> String {
+    match registry.get_by_id(material) {
+        // names can't hold whitespaces.
+        Some(info) => info.name.split_whitespace().collect::<Vec<_>>().join("_"),
+        None => format!("Material{}", material),
+    }
+}
+
+fn write_mtl_material(mtl: &mut String, name: &str, material: u16, registry: &VoxelMaterialRegistry) -> Result<()> {
+    writeln!(mtl, "newmtl {}", name)?;
+    if let Some(info) = registry.get_by_id(material) {
+        let [r, g, b, a] = info.base_color.as_linear_rgba_f32();
+        let [er, eg, eb, _] = info.emissive.as_linear_rgba_f32();
+        writeln!(mtl, "Kd {} {} {}", r, g, b)?;
+        writeln!(mtl, "Ke {} {} {}", er, eg, eb)?;
+        writeln!(mtl, "Pr {}", info.perceptual_roughness)?;
+        writeln!(mtl, "Pm {}", info.metallic)?;
+        writeln!(mtl, "d {}", a)?;
+    }
+    writeln!(mtl, "illum 2")?;
+    writeln!(mtl)?;
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use ndshape::{RuntimeShape, Shape};
+
+use super::{
+    block_mapping::BlockMapping,
+    nbt::{self, NbtCompound, NbtTag},
+};
+use crate::voxel::{storage::VoxelBuffer, Voxel};
+
+/// Minecraft data version written to exported schematics (1.20.1).
+const DATA_VERSION: i32 = 3465;
+
+/// Version of the Sponge schematic format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SchemVersion {
+    V2,
+    #[default]
+    V3,
+}
+
+/// Reads a Sponge schematic (`.schem`, version 2 or 3) into a voxel buffer, mapping its blocks to materials.
+/// Block entities, entities and biomes are ignored.
+pub fn import_schem(path: &Path, mapping: &BlockMapping) -> Result<VoxelBuffer<Voxel, RuntimeShape<u32, 3>>> {
+    let (_, root) = nbt::read_gzip_nbt(&std::fs::read(path)?)?;
+
+    // version 3 nests everything in a `Schematic` compound, version 2 has it at the root.
+    let schematic = match root.get("Schematic").and_then(|x| x.as_compound()) {
+        Some(schematic) => schematic,
+        None => &root,
+    };
+
+    let version = get_int(schematic, "Version")?;
+    let (palette, block_data) = match version {
+        1 | 2 => (
+            get(schematic, "Palette")?.as_compound(),
+            get(schematic, "BlockData")?.as_byte_array(),
+        ),
+        3 => {
+            let blocks = get(schematic, "Blocks")?
+                .as_compound()
+                .ok_or_else(|| anyhow!("Blocks isn't a compound"))?;
+            (get(blocks, "Palette")?.as_compound(), get(blocks, "Data")?.as_byte_array())
+        }
+        _ => bail!("unsupported schematic version {}", version),
+    };
+    let palette = palette.ok_or_else(|| anyhow!("the block palette isn't a compound"))?;
+    let block_data = block_data.ok_or_else(|| anyhow!("the block data isn't a byte array"))?;
+
+    // dimensions are unsigned shorts.
+    let width = get_int(schematic, "Width")? as u16 as u32;
+    let height = get_int(schematic, "Height")? as u16 as u32;
+    let length = get_int(schematic, "Length")? as u16 as u32;
+    // each block takes at least a byte, which bounds the volume before anything is allocated for it.
+    let volume = width as u64 * height as u64 * length as u64;
+    if volume > block_data.len() as u64 {
+        bail!(
+            "the block data holds {} bytes, too few for a {}x{}x{} schematic",
+            block_data.len(),
+            width,
+            height,
+            length
+        );
+    }
+
+    let mut materials = vec![Voxel::EMPTY_VOXEL; palette.len()];
+    for (block, index) in palette {
+        let index = index.as_i64().ok_or_else(|| anyhow!("invalid palette index for {}", block))?;
+        match materials.get_mut(index as usize) {
+            Some(material) => *material = Voxel::new(mapping.material_for(block)),
+            None => bail!("palette index {} of {} is out of range", index, block),
+        }
+    }
+
+    // blocks are ordered by y, then z, then x.
+    let shape = RuntimeShape::<u32, 3>::new([width, height, length]);
+    let mut buffer = VoxelBuffer::new_empty(shape);
+    let mut data = block_data.iter().map(|x| *x as u8);
+    for index in 0..volume as u32 {
+        let palette_index = read_varint(&mut data)?;
+        let (x, z, y) = (index % width, index / width % length, index / (width * length));
+        *buffer.voxel_at_mut([x, y, z].into()) = materials
+            .get(palette_index as usize)
+            .copied()
+            .ok_or_else(|| anyhow!("block data references palette index {}", palette_index))?;
+    }
+
+    Ok(buffer)
+}
+
+/// Writes a voxel buffer as a Sponge schematic, mapping materials to blocks.
+pub fn export_schem(
+    path: &Path,
+    buffer: &VoxelBuffer<Voxel, RuntimeShape<u32, 3>>,
+    mapping: &BlockMapping,
+    version: SchemVersion,
+) -> Result<()> {
+    let [width, height, length] = buffer.shape().as_array();
+    if [width, height, length].iter().any(|x| *x > u16::MAX as u32) {
+        bail!("schematics can't be larger than {} blocks along any axis", u16::MAX);
+    }
+
+    let mut palette = NbtCompound::new();
+    let mut block_data = Vec::new();
+    for y in 0..height {
+        for z in 0..length {
+            for x in 0..width {
+                let block = mapping.block_for(buffer.voxel_at([x, y, z].into()).material);
+                let next_index = palette.len() as i32;
+                let index = match palette.get(block) {
+                    Some(NbtTag::Int(index)) => *index,
+                    _ => {
+                        palette.insert(block.to_string(), NbtTag::Int(next_index));
+                        next_index
+                    }
+                };
+                write_varint(&mut block_data, index as u32);
+            }
+        }
+    }
+
+    let mut schematic = NbtCompound::new();
+    schematic.insert("DataVersion".into(), NbtTag::Int(DATA_VERSION));
+    schematic.insert("Width".into(), NbtTag::Short(width as u16 as i16));
+    schematic.insert("Height".into(), NbtTag::Short(height as u16 as i16));
+    schematic.insert("Length".into(), NbtTag::Short(length as u16 as i16));
+    schematic.insert("Offset".into(), NbtTag::IntArray(vec![0; 3]));
+
+    let bytes = match version {
+        SchemVersion::V2 => {
+            schematic.insert("Version".into(), NbtTag::Int(2));
+            schematic.insert("PaletteMax".into(), NbtTag::Int(palette.len() as i32));
+            schematic.insert("Palette".into(), NbtTag::Compound(palette));
+            schematic.insert("BlockData".into(), NbtTag::ByteArray(block_data));
+            schematic.insert("BlockEntities".into(), NbtTag::List(Vec::new()));
+            nbt::write_gzip_nbt("Schematic", &schematic)?
+        }
+        SchemVersion::V3 => {
+            let mut blocks = NbtCompound::new();
+            blocks.insert("Palette".into(), NbtTag::Compound(palette));
+            blocks.insert("Data".into(), NbtTag::ByteArray(block_data));
+            blocks.insert("BlockEntities".into(), NbtTag::List(Vec::new()));
+
+            schematic.insert("Version".into(), NbtTag::Int(3));
+            schematic.insert("Blocks".into(), NbtTag::Compound(blocks));
+
+            let mut root = NbtCompound::new();
+            root.insert("Schematic".into(), NbtTag::Compound(schematic));
+            nbt::write_gzip_nbt("", &root)?
+        }
+    };
+
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+fn get<'a>(compound: &'a NbtCompound, name: &str) -> Result<&'a NbtTag> {
+    compound.get(name).ok_or_else(|| anyhow!("missing {} tag", name))
+}
+
+fn get_int(compound: &NbtCompound, name: &str) -> Result<i64> {
+    get(compound, name)?
+        .as_i64()
+        .ok_or_else(|| anyhow!("{} isn't an integer", name))
+}
+
+/// Reads an unsigned LEB128 varint, as used for block data.
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let Some(byte) = bytes.next() else {
+            bail!("block data is shorter than the schematic volume");
+        };
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("block data varint is too long")
+}
+
+fn write_varint(bytes: &mut Vec<i8>, mut value: u32) {
+    while value >= 0x80 {
+        bytes.push(((value & 0x7f) as u8 | 0x80) as i8);
+        value >>= 7;
+    }
+    bytes.push(value as i8);
+}
@@ -0,0 +1,362 @@
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use bevy::prelude::Color;
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+use ndshape::{RuntimeShape, Shape};
+
+use crate::voxel::{
+    material::{MaterialRegistryInfo, VoxelMaterialFlags, VoxelMaterialRegistry},
+    schematic::Schematic,
+    storage::{ChunkMap, VoxelBuffer},
+    ChunkShape, Voxel,
+};
+
+const VOX_MAGIC: [u8; 4] = *b"VOX ";
+const VOX_VERSION: u32 = 150;
+/// Models can't be larger than this along any axis.
+pub const VOX_MAX_SIZE: u32 = 256;
+
+/// A model of a `.vox` file, in MagicaVoxel coordinates (z up).
+#[derive(Clone, Debug, Default)]
+pub struct VoxModel {
+    pub size: [u32; 3],
+    /// x, y, z and colour index of each voxel.
+    pub voxels: Vec<[u8; 4]>,
+}
+
+/// The models and palette of a MagicaVoxel `.vox` file. Scene graph, layers and material chunks are ignored.
+#[derive(Clone, Debug)]
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    /// RGBA colours indexed by colour index, index 0 is empty.
+    pub palette: [[u8; 4]; 256],
+}
+
+impl Default for VoxFile {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            palette: default_palette(),
+        }
+    }
+}
+
+impl VoxFile {
+    pub fn read(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(bytes);
+        if read_id(&mut reader)? != VOX_MAGIC {
+            bail!("not a MagicaVoxel file");
+        }
+        let _version = read_u32(&mut reader)?;
+
+        let (id, content_size, children_size) = read_chunk_header(&mut reader)?;
+        if id != *b"MAIN" {
+            bail!("expected the MAIN chunk, found {:?}", String::from_utf8_lossy(&id));
+        }
+        check_chunk_sizes(&reader, content_size, children_size)?;
+        skip(&mut reader, content_size)?;
+
+        let end = reader.position() + children_size as u64;
+        let mut file = Self::default();
+        let mut size = None;
+
+        while reader.position() < end {
+            let (id, content_size, children_size) = read_chunk_header(&mut reader)?;
+            // sizes are checked before allocating anything, they're read from the file.
+            check_chunk_sizes(&reader, content_size, children_size)?;
+            let mut content = vec![0u8; content_size as usize];
+            reader.read_exact(&mut content)?;
+            skip(&mut reader, children_size)?;
+            let mut content = Cursor::new(content.as_slice());
+
+            match &id {
+                b"SIZE" => {
+                    let model_size = [
+                        read_u32(&mut content)?,
+                        read_u32(&mut content)?,
+                        read_u32(&mut content)?,
+                    ];
+                    if model_size.iter().any(|x| *x == 0 || *x > VOX_MAX_SIZE) {
+                        bail!("model size {:?} is out of the 1..={} range", model_size, VOX_MAX_SIZE);
+                    }
+                    size = Some(model_size);
+                }
+                b"XYZI" => {
+                    let Some(size) = size.take() else {
+                        bail!("XYZI chunk without a preceding SIZE chunk");
+                    };
+                    let count = read_u32(&mut content)?;
+                    if count as u64 * 4 > content_size as u64 - content.position() {
+                        bail!("XYZI chunk of {} bytes can't hold {} voxels", content_size, count);
+                    }
+                    let mut voxels = vec![[0u8; 4]; count as usize];
+                    for voxel in voxels.iter_mut() {
+                        content.read_exact(voxel)?;
+                    }
+                    file.models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // the colour of index i + 1 is stored i-th, the last entry is unused.
+                    for index in 1..256 {
+                        content.read_exact(&mut file.palette[index])?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(file)
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>> {
+        let mut children = Vec::new();
+
+        if self.models.len() > 1 {
+            write_chunk(&mut children, b"PACK", &(self.models.len() as u32).to_le_bytes());
+        }
+
+        for model in &self.models {
+            if model.size.iter().any(|x| *x == 0 || *x > VOX_MAX_SIZE) {
+                bail!("model size {:?} is out of the 1..={} range", model.size, VOX_MAX_SIZE);
+            }
+
+            let size: Vec<u8> = model.size.iter().flat_map(|x| x.to_le_bytes()).collect();
+            write_chunk(&mut children, b"SIZE", &size);
+
+            let mut xyzi = Vec::with_capacity(4 + model.voxels.len() * 4);
+            xyzi.extend_from_slice(&(model.voxels.len() as u32).to_le_bytes());
+            model.voxels.iter().for_each(|x| xyzi.extend_from_slice(x));
+            write_chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        let mut rgba: Vec<u8> = self.palette[1..].iter().flatten().copied().collect();
+        rgba.extend_from_slice(&[0; 4]);
+        write_chunk(&mut children, b"RGBA", &rgba);
+
+        let mut bytes = Vec::with_capacity(20 + children.len());
+        bytes.extend_from_slice(&VOX_MAGIC);
+        bytes.extend_from_slice(&VOX_VERSION.to_le_bytes());
+        bytes.extend_from_slice(b"MAIN");
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&children);
+        Ok(bytes)
+    }
+}
+
+/// How palette colours are turned into materials when importing a `.vox` file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaletteMapping {
+    /// Every colour becomes the registered material with the closest base colour.
+    Nearest,
+    /// Colours further than `max_distance` (euclidian distance between 8 bit RGB values) from every registered material
+    /// get a new material registered, named after the colour.
+    RegisterMissing { max_distance: f32 },
+}
+
+/// Reads all the models of a `.vox` file into voxel buffers (y up), mapping the palette colours to materials.
+pub fn import_vox(
+    path: &Path,
+    registry: &mut VoxelMaterialRegistry,
+    mapping: PaletteMapping,
+) -> Result<Vec<VoxelBuffer<Voxel, RuntimeShape<u32, 3>>>> {
+    let file = VoxFile::read(&std::fs::read(path)?)?;
+
+    let mut materials = [Voxel::EMPTY_VOXEL; 256];
+    for index in file.models.iter().flat_map(|x| x.voxels.iter().map(|x| x[3] as usize)) {
+        if index != 0 && materials[index] == Voxel::EMPTY_VOXEL {
+            materials[index] = Voxel::new(material_for_color(registry, file.palette[index], mapping));
+        }
+    }
+
+    Ok(file
+        .models
+        .iter()
+        .map(|model| {
+            let [size_x, size_y, size_z] = model.size;
+            let shape = RuntimeShape::<u32, 3>::new([size_x, size_z, size_y]);
+            let mut buffer = VoxelBuffer::new_empty(shape);
+
+            for [x, y, z, index] in model.voxels.iter().copied() {
+                let (x, y, z) = (x as u32, y as u32, z as u32);
+                if x >= size_x || y >= size_y || z >= size_z {
+                    continue;
+                }
+                *buffer.voxel_at_mut([x, z, size_y - 1 - y].into()) = materials[index as usize];
+            }
+
+            buffer
+        })
+        .collect())
+}
+
+/// Writes a voxel buffer as a single model `.vox` file, using the base colour of the materials as the palette.
+pub fn export_vox(
+    path: &Path,
+    buffer: &VoxelBuffer<Voxel, RuntimeShape<u32, 3>>,
+    registry: &VoxelMaterialRegistry,
+) -> Result<()> {
+    let [size_x, size_y, size_z] = buffer.shape().as_array();
+    if [size_x, size_y, size_z].iter().any(|x| *x > VOX_MAX_SIZE) {
+        bail!("models can't be larger than {} voxels along any axis", VOX_MAX_SIZE);
+    }
+    let mut file = VoxFile::default();
+    let mut model = VoxModel {
+        size: [size_x, size_z, size_y],
+        voxels: Vec::new(),
+    };
+
+    // palette indices are handed out to materials in the order they're met.
+    let mut indices: Vec<u16> = Vec::new();
+    for (index, voxel) in buffer.slice().iter().enumerate() {
+        if *voxel == Voxel::EMPTY_VOXEL {
+            continue;
+        }
+
+        let color_index = match indices.iter().position(|x| *x == voxel.material) {
+            Some(position) => position + 1,
+            None => {
+                if indices.len() == 255 {
+                    bail!("the exported box uses more than 255 materials");
+                }
+                indices.push(voxel.material);
+                let color = registry
+                    .get_by_id(voxel.material)
+                    .map(|x| x.base_color)
+                    .unwrap_or(Color::WHITE);
+                file.palette[indices.len()] = color.as_rgba_u8();
+                indices.len()
+            }
+        };
+
+        let [x, y, z] = buffer.shape().delinearize(index as u32);
+        model
+            .voxels
+            .push([x as u8, (size_z - 1 - z) as u8, y as u8, color_index as u8]);
+    }
+
+    file.models.push(model);
+    std::fs::write(path, file.write()?)?;
+    Ok(())
+}
+
+/// Writes a world-space box as a `.vox` file. Voxels in chunks which aren't loaded are exported as empty.
+pub fn export_world_box(
+    path: &Path,
+    chunks: &ChunkMap<Voxel, ChunkShape>,
+    extent: Extent<ILIVec3>,
+    registry: &VoxelMaterialRegistry,
+) -> Result<()> {
+    export_vox(path, Schematic::extract(chunks, extent).buffer(), registry)
+}
+
+fn material_for_color(registry: &mut VoxelMaterialRegistry, color: [u8; 4], mapping: PaletteMapping) -> u16 {
+    let distance = |other: Color| {
+        let other = other.as_rgba_u8();
+        (0..3)
+            .map(|x| (color[x] as f32 - other[x] as f32).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    };
+
+    // material 0 is void, it can't be picked.
+    let nearest = registry
+        .iter_mats()
+        .enumerate()
+        .skip(1)
+        .map(|(id, material)| (id as u16, distance(material.base_color)))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+
+    match (mapping, nearest) {
+        (PaletteMapping::Nearest, Some((id, _))) => id,
+        (PaletteMapping::RegisterMissing { max_distance }, Some((id, distance))) if distance <= max_distance => id,
+        _ => register_color_material(registry, color),
+    }
+}
+
+/// Returns the material made for a palette colour, registering it if it's not registered yet.
+pub fn register_color_material(registry: &mut VoxelMaterialRegistry, color: [u8; 4]) -> u16 {
+    let name = format!("Vox{:02X}{:02X}{:02X}", color[0], color[1], color[2]);
+    registry.get_id_by_name(&name).unwrap_or_else(|| {
+        registry.register_dynamic_material(MaterialRegistryInfo {
+            // registered materials live for the whole program.
+            name: Box::leak(name.into_boxed_str()),
+            base_color: Color::rgba_u8(color[0], color[1], color[2], color[3]),
+            flags: VoxelMaterialFlags::SOLID,
+            emissive: Color::BLACK,
+            ..Default::default()
+        })
+    })
+}
+
+/// The palette used by MagicaVoxel for files without an RGBA chunk: a 6x6x6 colour cube
+/// followed by blue, green, red and gray ramps.
+fn default_palette() -> [[u8; 4]; 256] {
+    const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let cube = CUBE
+        .iter()
+        .flat_map(|r| CUBE.iter().flat_map(move |g| CUBE.iter().map(move |b| [*r, *g, *b, 0xff])))
+        .take(215);
+    let ramps = (0..4).flat_map(|channel| {
+        RAMP.iter().map(move |x| match channel {
+            0 => [0, 0, *x, 0xff],
+            1 => [0, *x, 0, 0xff],
+            2 => [*x, 0, 0, 0xff],
+            _ => [*x, *x, *x, 0xff],
+        })
+    });
+
+    let mut palette = [[0u8; 4]; 256];
+    palette[1..]
+        .iter_mut()
+        .zip(cube.chain(ramps))
+        .for_each(|(entry, color)| *entry = color);
+    palette
+}
+
+fn read_chunk_header(reader: &mut impl Read) -> Result<([u8; 4], u32, u32)> {
+    Ok((read_id(reader)?, read_u32(reader)?, read_u32(reader)?))
+}
+
+fn read_id(reader: &mut impl Read) -> Result<[u8; 4]> {
+    let mut id = [0u8; 4];
+    reader.read_exact(&mut id)?;
+    Ok(id)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Checks that a chunk fits in what's left of the file.
+fn check_chunk_sizes(reader: &Cursor<&[u8]>, content_size: u32, children_size: u32) -> Result<()> {
+    let remaining = reader.get_ref().len() as u64 - reader.position();
+    if content_size as u64 + children_size as u64 > remaining {
+        bail!("truncated chunk");
+    }
+    Ok(())
+}
+
+fn skip(reader: &mut Cursor<&[u8]>, count: u32) -> Result<()> {
+    let position = reader.position() + count as u64;
+    if position > reader.get_ref().len() as u64 {
+        bail!("truncated chunk");
+    }
+    reader.set_position(position);
+    Ok(())
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(content);
+}
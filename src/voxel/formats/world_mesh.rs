@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+
+use bevy::{
+    prelude::Mesh,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
+};
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+use ndshape::RuntimeShape;
+
+use crate::voxel::{
+    render::{mesh_padded_buffer, MeshBuffers, VoxelTerrainMesh},
+    storage::{ChunkMap, VoxelBuffer},
+    ChunkShape, Voxel,
+};
+
+/// Triangles of a [`WorldMesh`] sharing the same material.
+#[derive(Clone, Debug, Default)]
+pub struct MaterialPrimitive {
+    pub material: u16,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// A single mesh built from the chunks of a world box, split into one primitive per material.
+/// Positions are in voxels, relative to the minimum of the box.
+#[derive(Clone, Debug, Default)]
+pub struct WorldMesh {
+    /// Sorted by material ID.
+    pub primitives: Vec<MaterialPrimitive>,
+}
+
+impl WorldMesh {
+    /// Meshes the voxels of a world box, one chunk at a time with the same greedy meshing as the game.
+    /// Voxels outside of the box are considered empty so the mesh is closed along its sides,
+    /// unloaded chunks are skipped.
+    pub fn from_world_box(chunks: &ChunkMap<Voxel, ChunkShape>, extent: Extent<ILIVec3>) -> Self {
+        let mut primitives = BTreeMap::<u16, MaterialPrimitive>::new();
+        if extent.is_empty() {
+            return Self::default();
+        }
+
+        let shape_mask = ILIVec3::from(chunks.shape_mask().to_array());
+        let chunk_shape = !shape_mask + ILIVec3::ONE;
+        let first_chunk = extent.minimum & shape_mask;
+        let last_chunk = extent.max() & shape_mask;
+
+        for chunk_z in (first_chunk.z..=last_chunk.z).step_by(chunk_shape.z as usize) {
+            for chunk_y in (first_chunk.y..=last_chunk.y).step_by(chunk_shape.y as usize) {
+                for chunk_x in (first_chunk.x..=last_chunk.x).step_by(chunk_shape.x as usize) {
+                    let key = ILIVec3::new(chunk_x, chunk_y, chunk_z);
+                    if !chunks.exists(key.to_array().into()) {
+                        continue;
+                    }
+
+                    let piece = Extent::from_min_and_shape(key, chunk_shape).intersection(&extent);
+                    let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+                    mesh_piece(chunks, extent, piece, &mut render_mesh);
+
+                    // the mesh is relative to the padded piece.
+                    let offset = (piece.minimum - ILIVec3::ONE - extent.minimum).as_vec3().to_array();
+                    append_mesh(&mut primitives, &render_mesh, offset);
+                }
+            }
+        }
+
+        Self {
+            primitives: primitives.into_values().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.iter().all(|x| x.indices.is_empty())
+    }
+}
+
+/// Meshes a piece of the box lying in a single chunk, padded with the voxels around it which are inside of the box.
+fn mesh_piece(
+    chunks: &ChunkMap<Voxel, ChunkShape>,
+    extent: Extent<ILIVec3>,
+    piece: Extent<ILIVec3>,
+    render_mesh: &mut Mesh,
+) {
+    let padded = piece.padded(1);
+    let padded_shape = RuntimeShape::<u32, 3>::new(padded.shape.as_uvec3().to_array());
+    let mut padded_buffer = VoxelBuffer::<Voxel, RuntimeShape<u32, 3>>::new_empty(padded_shape);
+
+    for position in padded.iter3() {
+        if extent.contains(position) {
+            *padded_buffer.voxel_at_mut((position - padded.minimum).as_uvec3().to_array().into()) =
+                chunks.voxel_at(position.to_array().into()).unwrap_or_default();
+        }
+    }
+
+    let mut mesh_buffers =
+        MeshBuffers::<Voxel, RuntimeShape<u32, 3>>::new(RuntimeShape::<u32, 3>::new(piece.shape.as_uvec3().to_array()));
+    mesh_padded_buffer(&padded_buffer, &mut mesh_buffers, render_mesh, 1.0);
+}
+
+/// Appends the quads of a chunk mesh to the primitive of their material, translated by `offset`.
+fn append_mesh(primitives: &mut BTreeMap<u16, MaterialPrimitive>, render_mesh: &Mesh, offset: [f32; 3]) {
+    let (
+        Some(VertexAttributeValues::Float32x3(positions)),
+        Some(VertexAttributeValues::Float32x3(normals)),
+        Some(VertexAttributeValues::Float32x2(tex_coords)),
+        Some(VertexAttributeValues::Uint32(data)),
+        Some(Indices::U32(indices)),
+    ) = (
+        render_mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+        render_mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+        render_mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+        render_mesh.attribute(VoxelTerrainMesh::ATTRIBUTE_DATA),
+        render_mesh.indices(),
+    )
+    else {
+        return;
+    };
+
+    // meshing writes 4 vertices and 6 indices per quad, all vertices of a quad sharing the same voxel data.
+    for (quad_vertices, quad_indices) in (0..positions.len()).step_by(4).zip(indices.chunks_exact(6)) {
+        let material = (data[quad_vertices] & 0xFFFF) as u16;
+        let primitive = primitives.entry(material).or_insert_with(|| MaterialPrimitive {
+            material,
+            ..Default::default()
+        });
+
+        let base = primitive.positions.len() as u32;
+        primitive.indices.extend(quad_indices.iter().map(|x| x - quad_vertices as u32 + base));
+        for vertex in quad_vertices..quad_vertices + 4 {
+            let [x, y, z] = positions[vertex];
+            primitive.positions.push([x + offset[0], y + offset[1], z + offset[2]]);
+            primitive.normals.push(normals[vertex]);
+            primitive.tex_coords.push(tex_coords[vertex]);
+        }
+    }
+}
@@ -17,5 +17,8 @@ pub mod material;
 /// rust ports of signed distance field functions for use in world generation.
 pub mod sdf;
 
+/// Readers and writers for voxel data of other tools.
+pub mod formats;
+
 mod voxel;
 pub use voxel::*;
\ No newline at end of file
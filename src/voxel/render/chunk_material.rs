@@ -222,12 +222,25 @@ pub struct GpuVoxelMaterial {
     // alpha: f32,
 }
 
+/// Most materials the GPU knows about on WebGL2, which has no storage buffers: they go in a uniform array there.
+#[cfg(target_arch = "wasm32")]
+pub const MAX_GPU_MATERIALS: usize = 256;
+
+/// Shader def switching the shaders to the uniform array of materials.
+const MATERIALS_UNIFORM_DEF: &str = "VOXEL_MATERIALS_UNIFORM";
+
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 pub struct GpuTerrainUniforms {
     // #[uniform(0)]
     // pub render_distance: u32,
+    /// Indexed by material ID, sized to the number of registered materials.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[storage(0, read_only)]
+    pub materials: Vec<GpuVoxelMaterial>,
+    /// Indexed by material ID, materials past [`MAX_GPU_MATERIALS`] are left out.
+    #[cfg(target_arch = "wasm32")]
     #[uniform(0)]
-    pub materials: [GpuVoxelMaterial; 256],
+    pub materials: [GpuVoxelMaterial; MAX_GPU_MATERIALS],
     #[texture(1)]
     #[sampler(2)]
     pub color_texture: Option<Handle<Image>>,
@@ -237,7 +250,8 @@ impl Default for GpuTerrainUniforms {
     fn default() -> Self {
         Self {
             // render_distance: 16,
-            materials: [default(); 256],
+            // storage buffers can't be empty.
+            materials: into_gpu_materials(vec![default()]),
             color_texture: None,
         }
     }
@@ -265,48 +279,73 @@ impl Material for GpuTerrainUniforms {
             VoxelTerrainMesh::ATTRIBUTE_DATA.at_shader_location(3),
         ])?;
         descriptor.vertex.buffers = vec![vertex_layout];
+        if cfg!(target_arch = "wasm32") {
+            descriptor.vertex.shader_defs.push(MATERIALS_UNIFORM_DEF.into());
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push(MATERIALS_UNIFORM_DEF.into());
+            }
+        }
         Ok(())
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn into_gpu_materials(materials: Vec<GpuVoxelMaterial>) -> Vec<GpuVoxelMaterial> {
+    materials
+}
+
+#[cfg(target_arch = "wasm32")]
+fn into_gpu_materials(materials: Vec<GpuVoxelMaterial>) -> [GpuVoxelMaterial; MAX_GPU_MATERIALS] {
+    if materials.len() > MAX_GPU_MATERIALS {
+        warn!(
+            "{} voxel materials are registered but only {} fit on the GPU, the others render as the first one",
+            materials.len(),
+            MAX_GPU_MATERIALS
+        );
+    }
+    let mut gpu_materials = [GpuVoxelMaterial::default(); MAX_GPU_MATERIALS];
+    for (gpu_material, material) in gpu_materials.iter_mut().zip(materials) {
+        *gpu_material = material;
+    }
+    gpu_materials
+}
+
+/// Uploads the registered materials into the chunk material, again whenever materials get registered at runtime.
 fn update_chunk_material_singleton(
-    mut commands: Commands,
     mut materials: ResMut<Assets<GpuTerrainUniforms>>,
-    chunk_material: ResMut<ChunkMaterialSingleton>,
+    chunk_material: Res<ChunkMaterialSingleton>,
     voxel_materials: Res<VoxelMaterialRegistry>,
-    mut chunk_entities: Query<(Entity, &mut Handle<GpuTerrainUniforms>)>,
     assets: Res<MyAssets>,
 ) {
-    if chunk_material.is_changed() {
-        let mut gpu_mats = GpuTerrainUniforms {
-            materials: [GpuVoxelMaterial {
-                base_color: Color::WHITE,
-                flags: 0,
-                ..Default::default()
-            }; 256],
-            color_texture: Some(assets.uv_checkers.clone()),
-            // render_distance: 32,
+    let mut gpu_materials = vec![
+        GpuVoxelMaterial {
+            base_color: Color::WHITE,
+            flags: 0,
+            ..Default::default()
         };
-
-        voxel_materials
-            .iter_mats()
-            .enumerate()
-            .for_each(|(index, material)| {
-                gpu_mats.materials[index].base_color = material.base_color;
-                gpu_mats.materials[index].flags = material.flags.bits();
-                gpu_mats.materials[index].emissive = material.emissive;
-                gpu_mats.materials[index].perceptual_roughness = material.perceptual_roughness;
-                gpu_mats.materials[index].metallic = material.metallic;
-                gpu_mats.materials[index].reflectance = material.reflectance;
-            });
-
-        let chunk_material = materials.add(gpu_mats);
-        commands.insert_resource(ChunkMaterialSingleton(chunk_material.clone()));
-
-        for (_, mut mat) in &mut chunk_entities {
-            *mat = chunk_material.clone();
-        }
-    }
+        voxel_materials.iter_mats().count().max(1)
+    ];
+
+    voxel_materials
+        .iter_mats()
+        .enumerate()
+        .for_each(|(index, material)| {
+            gpu_materials[index].base_color = material.base_color;
+            gpu_materials[index].flags = material.flags.bits();
+            gpu_materials[index].emissive = material.emissive;
+            gpu_materials[index].perceptual_roughness = material.perceptual_roughness;
+            gpu_materials[index].metallic = material.metallic;
+            gpu_materials[index].reflectance = material.reflectance;
+        });
+
+    let gpu_mats = GpuTerrainUniforms {
+        materials: into_gpu_materials(gpu_materials),
+        color_texture: Some(assets.uv_checkers.clone()),
+        // render_distance: 32,
+    };
+
+    // the chunks all share the singleton's handle, so updating the asset in place reaches every one of them.
+    materials.insert(&chunk_material.0, gpu_mats);
 }
 
 #[derive(Resource, Deref, DerefMut)]
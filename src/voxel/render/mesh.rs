@@ -1,11 +1,14 @@
 use std::marker::PhantomData;
 
-use crate::voxel::{storage::VoxelBuffer, MaterialVoxel};
+use crate::voxel::{
+    storage::{ChunkSection, SectionShape, SectionedVoxelBuffer, VoxelBuffer, SECTION_LENGTH},
+    MaterialVoxel,
+};
 use bevy::{
     prelude::Mesh,
     render::mesh::{Indices, VertexAttributeValues}, log::info,
 };
-use block_mesh::{greedy_quads, GreedyQuadsBuffer, RIGHT_HANDED_Y_UP_CONFIG, UnitQuadBuffer, visible_block_faces};
+use block_mesh::{greedy_quads, GreedyQuadsBuffer, RIGHT_HANDED_Y_UP_CONFIG, UnitQuadBuffer, visible_block_faces, VoxelVisibility};
 use ndcopy::copy3;
 use ndshape::{RuntimeShape, Shape};
 
@@ -41,6 +44,7 @@ where
 
 // Processes the voxel data buffer specified as a parameter and generate.
 //todo: don't populate mesh directly, introduce a meshbuilding system.
+#[allow(dead_code)]
 pub fn mesh_buffer<T, S>(
     buffer: &VoxelBuffer<T, S>,
     mesh_buffers: &mut MeshBuffers<T, S>,
@@ -86,7 +90,7 @@ pub fn mesh_buffer<T, S>(
     // let mut normals = Vec::with_capacity(num_vertices);
     // let mut tex_coords = Vec::with_capacity(num_vertices);
 
-    // for (group, face) in mesh_buffers.visible_buffer.groups.clone().into_iter().zip(RIGHT_HANDED_Y_UP_CONFIG.faces.into_iter()) {
+    // for (group, face) in mesh_buffers.visible_buffer.groups.clone().into_iter().zip(RIGHT_HANDED_Y_UP_CONFIG.faces) {
     //     for quad in group.iter() {
     //         indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
     //         positions.extend_from_slice(&face.quad_mesh_positions(&(*quad).into(), scale));
@@ -132,6 +136,38 @@ pub fn mesh_buffer<T, S>(
         [1; 3],
     );
 
+    mesh_scratch_buffer(mesh_buffers, render_mesh, scale);
+}
+
+/// Same as [`mesh_buffer`], for a buffer which already holds one layer of padding voxels on each side,
+/// e.g. copied from the neighbouring chunks. Padding voxels aren't meshed, they only hide the faces touching them,
+/// so meshes of adjacent buffers join without faces in between.
+/// The padded buffer must be the shape `mesh_buffers` was created with, plus two along each axis.
+pub fn mesh_padded_buffer<T, S>(
+    padded_buffer: &VoxelBuffer<T, RuntimeShape<u32, 3>>,
+    mesh_buffers: &mut MeshBuffers<T, S>,
+    render_mesh: &mut Mesh,
+    scale: f32,
+) where
+    T: Copy + Default + MaterialVoxel,
+    S: Shape<3, Coord = u32>,
+{
+    assert!(padded_buffer.shape().as_array() == mesh_buffers.scratch_buffer.shape().as_array());
+
+    mesh_buffers
+        .scratch_buffer
+        .slice_mut()
+        .copy_from_slice(padded_buffer.slice());
+
+    mesh_scratch_buffer(mesh_buffers, render_mesh, scale);
+}
+
+/// Runs greedy meshing on the padded scratch buffer and inserts the resulting attributes into the mesh.
+fn mesh_scratch_buffer<T, S>(mesh_buffers: &mut MeshBuffers<T, S>, render_mesh: &mut Mesh, scale: f32)
+where
+    T: Copy + Default + MaterialVoxel,
+    S: Shape<3, Coord = u32>,
+{
     greedy_quads(
         mesh_buffers.scratch_buffer.slice(),
         mesh_buffers.scratch_buffer.shape(),
@@ -145,90 +181,167 @@ pub fn mesh_buffer<T, S>(
         &mut mesh_buffers.greedy_buffer,
     );
 
-    let num_indices = mesh_buffers.greedy_buffer.quads.num_quads() * 6;
-    let num_vertices = mesh_buffers.greedy_buffer.quads.num_quads() * 4;
-    let mut indices = Vec::with_capacity(num_indices);
-    let mut positions = Vec::with_capacity(num_vertices);
-    let mut normals = Vec::with_capacity(num_vertices);
-    let mut tex_coords = Vec::with_capacity(num_vertices);
-    let mut data = Vec::with_capacity(num_vertices);
+    let mut attributes = MeshAttributes::default();
+    attributes.push_greedy_quads(mesh_buffers, [0.0; 3], scale);
+    attributes.insert_into(render_mesh);
+}
 
-    for (block_face_normal_index, (group, face)) in mesh_buffers
-        .greedy_buffer
-        .quads
-        .groups
-        .as_ref()
-        .iter()
-        .zip(RIGHT_HANDED_Y_UP_CONFIG.faces.into_iter())
-        .enumerate() {
-        for quad in group.into_iter() {
-            indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-            positions.extend_from_slice(&face.quad_mesh_positions(&quad, scale));
-            normals.extend_from_slice(&face.quad_mesh_normals());
-            tex_coords.extend_from_slice(&face.tex_coords(
-                RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
-                true,
-                &quad,
-            ));
-            data.extend_from_slice(
-                &[(block_face_normal_index as u32) << 8u32
-                    | buffer
-                        .voxel_at(quad.minimum.map(|x| x - 1).into())
-                        .as_mat_id() as u32; 4],
-            );
-
-            // let mat_index = (block_face_normal_index as u32) << 8u32 | buffer
-            //     .voxel_at(quad.minimum.map(|x| x - 1).into())
-            //     .as_mat_id() as u32;
-
-            // let uvs = &face.tex_coords(
-            //     RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
-            //     true,
-            //     &quad,
-            // );
-
-            // convert mat_index to a float and keep the bits intact
-            // let mat_index = [f32::from_bits(mat_index); 3];
-
-            // data.extend_from_slice(
-            //     &[mat_index; 3],
-            // );
-
-            // info!("mat_index: {:#034b}", mat_index);
-            // info!("voxel_data: {:#034b}", data.last().unwrap());
+/// Processes a sectioned voxel buffer one section at a time and generates a single mesh for it.
+/// Empty uniform sections are skipped entirely. Solid ones are meshed like the others, the horizontal padding
+/// being empty they always have faces on the chunk border.
+/// `neighbour_below` and `neighbour_above` are the adjacent sections of the buffers stacked under and over this one, if any.
+pub fn mesh_sectioned_buffer<T, S>(
+    buffer: &SectionedVoxelBuffer<T, S>,
+    neighbour_below: Option<&ChunkSection<T>>,
+    neighbour_above: Option<&ChunkSection<T>>,
+    mesh_buffers: &mut MeshBuffers<T, SectionShape>,
+    render_mesh: &mut Mesh,
+    scale: f32,
+) where
+    T: Copy + Default + PartialEq + MaterialVoxel,
+    S: Shape<3, Coord = u32>,
+{
+    let mut attributes = MeshAttributes::default();
+    let sections = buffer.sections();
+
+    for (index, section) in sections.iter().enumerate() {
+        let below = match index {
+            0 => neighbour_below,
+            _ => sections.get(index - 1),
+        };
+        let above = sections.get(index + 1).or(neighbour_above);
+
+        if section
+            .uniform_value()
+            .is_some_and(|val| val.get_visibility() == VoxelVisibility::Empty)
+        {
+            continue;
         }
-    }
 
-    for uv in tex_coords.iter_mut() {
-        for c in uv.iter_mut() {
-            *c *= UV_SCALE;
+        let scratch_shape = mesh_buffers.scratch_buffer.shape().clone();
+        let scratch = mesh_buffers.scratch_buffer.slice_mut();
+
+        // the horizontal padding always stays empty, the vertical one is taken from the neighbouring sections.
+        scratch.fill(T::default());
+        for z in 0..SECTION_LENGTH {
+            for x in 0..SECTION_LENGTH {
+                for y in 0..SECTION_LENGTH {
+                    scratch[scratch_shape.linearize([x + 1, y + 1, z + 1]) as usize] =
+                        section.voxel_at([x, y, z].into());
+                }
+                if let Some(below) = below {
+                    scratch[scratch_shape.linearize([x + 1, 0, z + 1]) as usize] =
+                        below.voxel_at([x, SECTION_LENGTH - 1, z].into());
+                }
+                if let Some(above) = above {
+                    scratch[scratch_shape.linearize([x + 1, SECTION_LENGTH + 1, z + 1]) as usize] =
+                        above.voxel_at([x, 0, z].into());
+                }
+            }
         }
-    }
 
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::Float32x3(positions),
-    );
-
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_NORMAL,
-        VertexAttributeValues::Float32x3(normals),
-    );
+        greedy_quads(
+            mesh_buffers.scratch_buffer.slice(),
+            &scratch_shape,
+            [0; 3],
+            scratch_shape.as_array().map(|axis| axis - 1),
+            &RIGHT_HANDED_Y_UP_CONFIG.faces,
+            &mut mesh_buffers.greedy_buffer,
+        );
+
+        attributes.push_greedy_quads(
+            mesh_buffers,
+            [0.0, (index as u32 * SECTION_LENGTH) as f32 * scale, 0.0],
+            scale,
+        );
+    }
 
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_UV_0,
-        VertexAttributeValues::Float32x2(tex_coords),
-    );
+    attributes.insert_into(render_mesh);
+}
 
-    render_mesh.insert_attribute(
-        VoxelTerrainMesh::ATTRIBUTE_DATA,
-        VertexAttributeValues::Uint32(data),
-    );
+/// Vertex attributes accumulated while meshing, before being inserted into a [`Mesh`].
+#[derive(Default)]
+struct MeshAttributes {
+    indices: Vec<u32>,
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    data: Vec<u32>,
+}
 
-    // render_mesh.insert_attribute(
-    //     VoxelTerrainMesh::ATTRIBUTE_DATA,
-    //     VertexAttributeValues::Float32x3(data),
-    // );
+impl MeshAttributes {
+    /// Appends the quads found by the last greedy meshing pass, translated by `offset`.
+    fn push_greedy_quads<T, S>(&mut self, mesh_buffers: &MeshBuffers<T, S>, offset: [f32; 3], scale: f32)
+    where
+        T: Copy + Default + MaterialVoxel,
+        S: Shape<3, Coord = u32>,
+    {
+        let num_quads = mesh_buffers.greedy_buffer.quads.num_quads();
+        self.indices.reserve(num_quads * 6);
+        self.positions.reserve(num_quads * 4);
+        self.normals.reserve(num_quads * 4);
+        self.tex_coords.reserve(num_quads * 4);
+        self.data.reserve(num_quads * 4);
+
+        for (block_face_normal_index, (group, face)) in mesh_buffers
+            .greedy_buffer
+            .quads
+            .groups
+            .as_ref()
+            .iter()
+            .zip(RIGHT_HANDED_Y_UP_CONFIG.faces)
+            .enumerate() {
+            for quad in group.iter() {
+                self.indices.extend_from_slice(&face.quad_mesh_indices(self.positions.len() as u32));
+                self.positions.extend(
+                    face.quad_mesh_positions(quad, scale)
+                        .map(|[x, y, z]| [x + offset[0], y + offset[1], z + offset[2]]),
+                );
+                self.normals.extend_from_slice(&face.quad_mesh_normals());
+                self.tex_coords.extend(
+                    face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, quad)
+                        .map(|uv| uv.map(|c| c * UV_SCALE)),
+                );
+                // the scratch buffer is padded, so the quad minimum already points at the right voxel in it.
+                let voxel = mesh_buffers.scratch_buffer.voxel_at(quad.minimum.into());
+                self.data.extend_from_slice(
+                    &[(voxel.state() as u32) << 19u32
+                        | (block_face_normal_index as u32) << 16u32
+                        | voxel.as_mat_id() as u32; 4],
+                );
+
+                // info!("mat_index: {:#034b}", mat_index);
+                // info!("voxel_data: {:#034b}", data.last().unwrap());
+            }
+        }
+    }
 
-    render_mesh.set_indices(Some(Indices::U32(indices.clone())));
+    fn insert_into(self, render_mesh: &mut Mesh) {
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float32x3(self.positions),
+        );
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float32x3(self.normals),
+        );
+
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            VertexAttributeValues::Float32x2(self.tex_coords),
+        );
+
+        render_mesh.insert_attribute(
+            VoxelTerrainMesh::ATTRIBUTE_DATA,
+            VertexAttributeValues::Uint32(self.data),
+        );
+
+        // render_mesh.insert_attribute(
+        //     VoxelTerrainMesh::ATTRIBUTE_DATA,
+        //     VertexAttributeValues::Float32x3(data),
+        // );
+
+        render_mesh.set_indices(Some(Indices::U32(self.indices)));
+    }
 }
\ No newline at end of file
@@ -1,21 +1,26 @@
-use ilattice::morton::Morton3i32;
+use ilattice::{
+    extent::Extent,
+    glam::{IVec3 as ILIVec3, UVec3},
+    morton::Morton3i32,
+};
 use std::{collections::BTreeMap, hash::Hash};
 
-use bevy::{math::IVec3, prelude::Resource};
+use bevy::{math::IVec3, prelude::Resource, utils::HashSet};
 use ndshape::Shape;
 
 use crate::voxel::{CHUNK_LENGTH, CHUNK_HEIGHT};
 
-use super::buffer::VoxelBuffer;
+use super::sectioned::{SectionedVoxelBuffer, SectionedVoxelMut};
 
 /// Provides an interface to query or modify voxel data for worlds or scenes split into multiple voxel data buffers of a same shape with no level of detail.
+/// Buffers are split into vertical sections, uniform ones being stored as a single value, see [`SectionedVoxelBuffer`].
 #[derive(Resource)]
 pub struct ChunkMap<V, S>
 where
     V: Clone + Copy + Default + PartialEq + Eq + Hash,
     S: Shape<3, Coord = u32> + Clone,
 {
-    chunks: BTreeMap<Morton3i32, VoxelBuffer<V, S>>,
+    chunks: BTreeMap<Morton3i32, SectionedVoxelBuffer<V, S>>,
     shape_mask: IVec3,
     shape: S,
 }
@@ -48,7 +53,7 @@ where
             .map(|buffer| buffer.voxel_at(local_minimum.as_uvec3()))
     }
 
-    pub fn voxel_at_mut(&mut self, pos: IVec3) -> Option<&mut V> {
+    pub fn voxel_at_mut(&mut self, pos: IVec3) -> Option<SectionedVoxelMut<'_, V, S>> {
         let chunk_minimum = pos & self.shape_mask;
         // let local_minimum = ilattice::glam::IVec3::from(pos.to_array())
         //     .map(|x| x.rem_euclid(CHUNK_LENGTH as i32))
@@ -70,22 +75,22 @@ where
         self.chunks.contains_key(&minimum.into())
     }
 
-    /// Returns a reference to the [`VoxelBuffer<V, S>`] at the specified minimum if there's one.
+    /// Returns a reference to the [`SectionedVoxelBuffer<V, S>`] at the specified minimum if there's one.
     #[inline]
-    pub fn buffer_at(&self, minimum: IVec3) -> Option<&VoxelBuffer<V, S>> {
+    pub fn buffer_at(&self, minimum: IVec3) -> Option<&SectionedVoxelBuffer<V, S>> {
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
         self.chunks.get(&minimum.into())
     }
 
-    /// Returns a mutable reference to the [`VoxelBuffer<V, S>`] at the specified minimum if there's one.
+    /// Returns a mutable reference to the [`SectionedVoxelBuffer<V, S>`] at the specified minimum if there's one.
     #[inline]
-    pub fn buffer_at_mut(&mut self, minimum: IVec3) -> Option<&mut VoxelBuffer<V, S>> {
+    pub fn buffer_at_mut(&mut self, minimum: IVec3) -> Option<&mut SectionedVoxelBuffer<V, S>> {
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
         self.chunks.get_mut(&minimum.into())
     }
 
     /// Inserts a new buffer at the specified minimum.
-    pub fn insert(&mut self, minimum: IVec3, buffer: VoxelBuffer<V, S>) {
+    pub fn insert(&mut self, minimum: IVec3, buffer: SectionedVoxelBuffer<V, S>) {
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
 
         assert!(buffer.shape().as_array() == self.shape.as_array());
@@ -97,12 +102,12 @@ where
         let minimum = ilattice::glam::IVec3::from(minimum.to_array());
         self.chunks.insert(
             minimum.into(),
-            VoxelBuffer::<V, S>::new_empty(self.shape.clone()),
+            SectionedVoxelBuffer::<V, S>::new_empty(self.shape.clone()),
         );
     }
 
     /// Inserts buffers from an iterator passed as a parameter
-    pub fn insert_batch<T: IntoIterator<Item = (Morton3i32, VoxelBuffer<V, S>)>>(
+    pub fn insert_batch<T: IntoIterator<Item = (Morton3i32, SectionedVoxelBuffer<V, S>)>>(
         &mut self,
         iter: T,
     ) {
@@ -110,11 +115,99 @@ where
     }
 
     /// Removes the buffer at the specified minimum and returns it if it exists.
-    pub fn remove(&mut self, pos: IVec3) -> Option<VoxelBuffer<V, S>> {
+    pub fn remove(&mut self, pos: IVec3) -> Option<SectionedVoxelBuffer<V, S>> {
         let pos = ilattice::glam::IVec3::from(pos.to_array());
         self.chunks.remove(&pos.into())
     }
 
+    /// Fills a world-space extent with the specified value, across as many chunks as it overlaps.
+    /// Voxels in chunks which aren't loaded are left untouched.
+    /// Returns the keys of the loaded chunks overlapped by the extent.
+    pub fn fill_extent(&mut self, extent: Extent<ILIVec3>, val: V) -> HashSet<IVec3> {
+        self.split_extent(extent)
+            .filter_map(|(key, local_extent)| {
+                self.buffer_at_mut(key).map(|buffer| {
+                    buffer.fill_extent(local_extent, val);
+                    key
+                })
+            })
+            .collect()
+    }
+
+    /// Replaces the voxels of a world-space extent for which the predicate holds with the specified value.
+    /// The predicate is called with the world position and the current value of each voxel.
+    /// Returns the keys of the chunks in which at least one voxel changed.
+    pub fn replace_where(
+        &mut self,
+        extent: Extent<ILIVec3>,
+        mut predicate: impl FnMut(IVec3, V) -> bool,
+        val: V,
+    ) -> HashSet<IVec3> {
+        self.apply_extent(extent, |pos, voxel| {
+            if predicate(pos, *voxel) {
+                *voxel = val;
+            }
+        })
+    }
+
+    /// Calls the specified function with the world position and a mutable reference to the value of each voxel of a world-space extent.
+    /// Voxels in chunks which aren't loaded are skipped.
+    /// Returns the keys of the chunks in which at least one voxel changed.
+    pub fn apply_extent(
+        &mut self,
+        extent: Extent<ILIVec3>,
+        mut f: impl FnMut(IVec3, &mut V),
+    ) -> HashSet<IVec3> {
+        let mut touched = HashSet::default();
+
+        for (key, local_extent) in self.split_extent(extent) {
+            let Some(buffer) = self.buffer_at_mut(key) else {
+                continue;
+            };
+
+            for local_pos in local_extent.iter3() {
+                let current = buffer.voxel_at(local_pos);
+                let mut val = current;
+                f(key + IVec3::from(local_pos.as_ivec3().to_array()), &mut val);
+
+                if val != current {
+                    buffer.set_voxel(local_pos, val);
+                    touched.insert(key);
+                }
+            }
+        }
+
+        touched
+    }
+
+    /// Returns the keys of the chunks overlapped by a world-space extent, whether they're loaded or not.
+    pub fn chunk_keys(&self, extent: Extent<ILIVec3>) -> impl Iterator<Item = IVec3> {
+        self.split_extent(extent).map(|(key, _)| key)
+    }
+
+    /// Splits a world-space extent into the keys of the chunks it overlaps along with the overlapped extent in each chunk local space.
+    fn split_extent(&self, extent: Extent<ILIVec3>) -> impl Iterator<Item = (IVec3, Extent<UVec3>)> {
+        let chunk_shape = ILIVec3::from(self.shape.as_array().map(|x| x as i32));
+        let shape_mask = ILIVec3::from(self.shape_mask.to_array());
+
+        let chunks = match extent.is_empty() {
+            true => Extent::from_min_and_shape(ILIVec3::ZERO, ILIVec3::ZERO),
+            false => Extent::from_min_and_max(
+                (extent.minimum & shape_mask) / chunk_shape,
+                (extent.max() & shape_mask) / chunk_shape,
+            ),
+        };
+
+        chunks.iter3().map(move |chunk| {
+            let key = chunk * chunk_shape;
+            let local_extent = extent.intersection(&Extent::from_min_and_shape(key, chunk_shape)) - key;
+            (
+                IVec3::from(key.to_array()),
+                local_extent.map_components(|x| x.as_uvec3()),
+            )
+        })
+    }
+
     #[inline]
     pub const fn shape_mask(&self) -> IVec3 {
         self.shape_mask
@@ -1,5 +1,14 @@
 mod buffer;
 pub use buffer::*;
 
+mod palette;
+pub use palette::*;
+
+mod sectioned;
+pub use sectioned::*;
+
 mod chunk_map;
-pub use chunk_map::*;
\ No newline at end of file
+pub use chunk_map::*;
+
+mod raycast;
+pub use raycast::*;
\ No newline at end of file
@@ -0,0 +1,316 @@
+use std::ops::{Deref, DerefMut};
+
+use ilattice::extent::Extent;
+use ilattice::glam::UVec3;
+use ndshape::Shape;
+
+use super::buffer::VoxelBuffer;
+
+/// Smallest number of bits used to store a palette index.
+const MIN_BITS_PER_INDEX: u32 = 1;
+
+/// A fixed-width array of integers packed into 64 bits words.
+/// Indices never straddle two words, so some bits may be left unused at the end of each word.
+#[derive(Clone, Debug)]
+struct PackedIndices {
+    words: Box<[u64]>,
+    bits_per_index: u32,
+    len: usize,
+}
+
+impl PackedIndices {
+    fn new(len: usize, bits_per_index: u32) -> Self {
+        let per_word = (64 / bits_per_index) as usize;
+        Self {
+            words: vec![0u64; len.div_ceil(per_word)].into_boxed_slice(),
+            bits_per_index,
+            len,
+        }
+    }
+
+    #[inline]
+    fn mask(&self) -> u64 {
+        (1u64 << self.bits_per_index) - 1
+    }
+
+    #[inline]
+    fn locate(&self, index: usize) -> (usize, u32) {
+        let per_word = (64 / self.bits_per_index) as usize;
+        (index / per_word, (index % per_word) as u32 * self.bits_per_index)
+    }
+
+    #[inline]
+    fn get(&self, index: usize) -> usize {
+        let (word, shift) = self.locate(index);
+        ((self.words[word] >> shift) & self.mask()) as usize
+    }
+
+    #[inline]
+    fn set(&mut self, index: usize, value: usize) {
+        let (word, shift) = self.locate(index);
+        let mask = self.mask();
+        self.words[word] = (self.words[word] & !(mask << shift)) | ((value as u64 & mask) << shift);
+    }
+
+    /// Repacks the indices using the specified amount of bits per index.
+    fn repack(&mut self, bits_per_index: u32) {
+        let mut repacked = Self::new(self.len, bits_per_index);
+        (0..self.len).for_each(|i| repacked.set(i, self.get(i)));
+        *self = repacked;
+    }
+
+    #[inline]
+    fn heap_size(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+    }
+}
+
+/// A buffer of typed voxel data stored as a local palette of the distinct voxel values it contains,
+/// plus a bit-packed array of indices into this palette.
+///
+/// The index width starts at 1 bit and grows as new values are written to the buffer, so a chunk
+/// made of a handful of materials uses a fraction of the memory of a [`VoxelBuffer`].
+#[derive(Clone, Debug)]
+pub struct PaletteVoxelBuffer<V, S: Shape<3, Coord = u32>>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    palette: Vec<V>,
+    indices: PackedIndices,
+    shape: S,
+}
+
+#[allow(dead_code)]
+impl<V, S: Shape<3, Coord = u32>> PaletteVoxelBuffer<V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    #[inline]
+    pub fn new(shape: S, initial_val: V) -> Self {
+        Self {
+            palette: vec![initial_val],
+            indices: PackedIndices::new(shape.size() as usize, MIN_BITS_PER_INDEX),
+            shape,
+        }
+    }
+
+    #[inline]
+    pub fn new_empty(shape: S) -> Self {
+        Self::new(shape, Default::default())
+    }
+
+    /// Builds a palette buffer holding the same voxels as the specified [`VoxelBuffer`].
+    pub fn from_buffer(buffer: &VoxelBuffer<V, S>) -> Self
+    where
+        S: Clone,
+    {
+        let mut palette = Vec::new();
+        let raw_indices: Vec<usize> = buffer
+            .slice()
+            .iter()
+            .map(|voxel| match palette.iter().position(|x| x == voxel) {
+                Some(index) => index,
+                None => {
+                    palette.push(*voxel);
+                    palette.len() - 1
+                }
+            })
+            .collect();
+
+        let mut indices = PackedIndices::new(raw_indices.len(), Self::bits_for(palette.len()));
+        raw_indices
+            .into_iter()
+            .enumerate()
+            .for_each(|(i, index)| indices.set(i, index));
+
+        Self {
+            palette,
+            indices,
+            shape: buffer.shape().clone(),
+        }
+    }
+
+    /// Unpacks this buffer into a contiguous [`VoxelBuffer`].
+    pub fn to_buffer(&self) -> VoxelBuffer<V, S>
+    where
+        S: Clone,
+    {
+        let mut buffer = VoxelBuffer::new_empty(self.shape.clone());
+        self.copy_to_slice(buffer.slice_mut());
+        buffer
+    }
+
+    /// Unpacks the voxels of this buffer into a slice linearized with the same shape.
+    pub fn copy_to_slice(&self, dst: &mut [V]) {
+        dst.iter_mut()
+            .enumerate()
+            .for_each(|(i, voxel)| *voxel = self.palette[self.indices.get(i)]);
+    }
+
+    // Returns the voxel at the querried position in local space.
+    #[inline]
+    pub fn voxel_at(&self, pos: UVec3) -> V {
+        self.palette[self.indices.get(self.shape.linearize(pos.to_array()) as usize)]
+    }
+
+    // Returns a handle to the voxel at the querried position in local space.
+    // The value is written back to the buffer when the handle is dropped.
+    #[inline]
+    pub fn voxel_at_mut(&mut self, pos: UVec3) -> PaletteVoxelMut<'_, V, S> {
+        let index = self.shape.linearize(pos.to_array()) as usize;
+        PaletteVoxelMut {
+            value: self.palette[self.indices.get(index)],
+            index,
+            buffer: self,
+        }
+    }
+
+    /// Sets the voxel at the specified position in local space.
+    #[inline]
+    pub fn set_voxel(&mut self, pos: UVec3, val: V) {
+        let index = self.shape.linearize(pos.to_array()) as usize;
+        self.set_linear(index, val);
+    }
+
+    /// Fills an extent of this buffer with the specified value.
+    pub fn fill_extent(&mut self, extent: Extent<UVec3>, val: V) {
+        if extent.minimum == UVec3::ZERO && extent.shape.to_array() == self.shape.as_array() {
+            // the whole buffer gets overwritten, drop the previous palette entirely.
+            self.palette.clear();
+            self.palette.push(val);
+            self.indices = PackedIndices::new(self.indices.len, MIN_BITS_PER_INDEX);
+            return;
+        }
+
+        let palette_index = self.palette_index_for(val);
+        extent.iter3().for_each(|pos| {
+            let index = self.shape.linearize(pos.to_array()) as usize;
+            self.indices.set(index, palette_index);
+        });
+    }
+
+    /// Returns the distinct voxel values referenced by this buffer's palette.
+    #[inline]
+    pub fn palette(&self) -> &[V] {
+        &self.palette
+    }
+
+    /// Returns the number of bits currently used to store each voxel.
+    #[inline]
+    pub fn bits_per_voxel(&self) -> u32 {
+        self.indices.bits_per_index
+    }
+
+    /// Returns the approximate number of bytes allocated on the heap by this buffer.
+    #[inline]
+    pub fn heap_size(&self) -> usize {
+        self.palette.capacity() * std::mem::size_of::<V>() + self.indices.heap_size()
+    }
+
+    #[inline]
+    pub const fn shape(&self) -> &S {
+        &self.shape
+    }
+
+    /// Removes the palette entries which aren't referenced by any voxel anymore and shrinks the index width accordingly.
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        (0..self.indices.len).for_each(|i| used[self.indices.get(i)] = true);
+
+        if used.iter().all(|x| *x) {
+            return;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (old_index, voxel) in self.palette.iter().enumerate() {
+            if used[old_index] {
+                remap[old_index] = palette.len();
+                palette.push(*voxel);
+            }
+        }
+
+        let mut indices = PackedIndices::new(self.indices.len, Self::bits_for(palette.len()));
+        (0..self.indices.len).for_each(|i| indices.set(i, remap[self.indices.get(i)]));
+
+        self.palette = palette;
+        self.indices = indices;
+    }
+
+    #[inline]
+    fn set_linear(&mut self, index: usize, val: V) {
+        let palette_index = self.palette_index_for(val);
+        self.indices.set(index, palette_index);
+    }
+
+    /// Returns the palette index of the specified value, registering it (and widening the indices if needed) when it's missing.
+    fn palette_index_for(&mut self, val: V) -> usize {
+        if let Some(index) = self.palette.iter().position(|x| *x == val) {
+            return index;
+        }
+
+        self.palette.push(val);
+        let required_bits = Self::bits_for(self.palette.len());
+        if required_bits > self.indices.bits_per_index {
+            self.indices.repack(required_bits);
+        }
+        self.palette.len() - 1
+    }
+
+    #[inline]
+    fn bits_for(palette_len: usize) -> u32 {
+        (usize::BITS - palette_len.saturating_sub(1).leading_zeros()).max(MIN_BITS_PER_INDEX)
+    }
+}
+
+impl<V, S> From<VoxelBuffer<V, S>> for PaletteVoxelBuffer<V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+    S: Shape<3, Coord = u32> + Clone,
+{
+    fn from(buffer: VoxelBuffer<V, S>) -> Self {
+        Self::from_buffer(&buffer)
+    }
+}
+
+/// A mutable handle to a single voxel of a [`PaletteVoxelBuffer`].
+/// Since voxels are bit-packed they can't be borrowed directly, the value is written back on drop instead.
+pub struct PaletteVoxelMut<'a, V, S: Shape<3, Coord = u32>>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    buffer: &'a mut PaletteVoxelBuffer<V, S>,
+    index: usize,
+    value: V,
+}
+
+impl<'a, V, S: Shape<3, Coord = u32>> Deref for PaletteVoxelMut<'a, V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<'a, V, S: Shape<3, Coord = u32>> DerefMut for PaletteVoxelMut<'a, V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<'a, V, S: Shape<3, Coord = u32>> Drop for PaletteVoxelMut<'a, V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    fn drop(&mut self) {
+        if self.buffer.palette[self.buffer.indices.get(self.index)] != self.value {
+            self.buffer.set_linear(self.index, self.value);
+        }
+    }
+}
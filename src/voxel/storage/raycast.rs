@@ -0,0 +1,103 @@
+use std::hash::Hash;
+
+use bevy::math::{IVec3, Vec3};
+use block_mesh::{Voxel as MeshableVoxel, VoxelVisibility};
+use ndshape::Shape;
+
+use super::ChunkMap;
+
+/// The result of a ray cast against the voxels of a [`ChunkMap`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelRayHit<V> {
+    /// World position of the hit voxel.
+    pub position: IVec3,
+    pub voxel: V,
+    /// Normal of the voxel face the ray entered through.
+    /// Zero when the ray starts inside the hit voxel.
+    pub normal: IVec3,
+    /// Distance from the ray origin to the hit face.
+    pub distance: f32,
+}
+
+#[allow(dead_code)]
+impl<V, S> ChunkMap<V, S>
+where
+    V: Clone + Copy + Default + PartialEq + Eq + Hash + MeshableVoxel,
+    S: Shape<3, Coord = u32> + Clone,
+{
+    /// Casts a ray from `origin` along `direction` and returns the first non empty voxel crossed within `max_distance`.
+    /// Voxel `p` spans from `p` to `p + 1` in world space. Chunks which aren't loaded are treated as empty.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<VoxelRayHit<V>> {
+        self.raycast_filtered(origin, direction, max_distance, |voxel| {
+            voxel.get_visibility() != VoxelVisibility::Empty
+        })
+    }
+
+    /// Casts a ray from `origin` along `direction` and returns the first voxel crossed within `max_distance`
+    /// for which `is_hit` returns true.
+    ///
+    /// The voxels are visited in the order the ray crosses them (Amanatides & Woo), so the cost is proportional
+    /// to the distance travelled and faces are never missed by floating point errors.
+    pub fn raycast_filtered(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        mut is_hit: impl FnMut(V) -> bool,
+    ) -> Option<VoxelRayHit<V>> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return None;
+        }
+
+        let mut position = origin.floor().as_ivec3();
+        let step = IVec3::select(direction.cmpgt(Vec3::ZERO), IVec3::ONE, IVec3::ZERO)
+            - IVec3::select(direction.cmplt(Vec3::ZERO), IVec3::ONE, IVec3::ZERO);
+        // distance along the ray between two voxel boundaries, for each axis.
+        let t_delta = direction.abs().recip();
+        // distance along the ray to the next voxel boundary, for each axis. Axes the ray is parallel to are never crossed.
+        let mut t_max = Vec3::select(
+            direction.cmpgt(Vec3::ZERO),
+            (position.as_vec3() + Vec3::ONE - origin) * t_delta,
+            Vec3::select(
+                direction.cmplt(Vec3::ZERO),
+                (origin - position.as_vec3()) * t_delta,
+                Vec3::INFINITY,
+            ),
+        );
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+
+        while distance <= max_distance {
+            if let Some(voxel) = self.voxel_at(position).filter(|voxel| is_hit(*voxel)) {
+                return Some(VoxelRayHit {
+                    position,
+                    voxel,
+                    normal,
+                    distance,
+                });
+            }
+
+            let axis = if t_max.x < t_max.y {
+                if t_max.x < t_max.z { 0 } else { 2 }
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+
+            distance = t_max[axis];
+            t_max[axis] += t_delta[axis];
+            position[axis] += step[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+
+        None
+    }
+
+    /// Returns whether the segment between two world positions doesn't cross any non empty voxel.
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        self.raycast(from, to - from, from.distance(to)).is_none()
+    }
+}
@@ -0,0 +1,358 @@
+use std::ops::{Deref, DerefMut};
+
+use ilattice::extent::Extent;
+use ilattice::glam::UVec3;
+use ndshape::{ConstShape3u32, Shape};
+
+use super::{PaletteVoxelBuffer, VoxelBuffer};
+
+/// Edge length of the cubic sections chunks are vertically split into.
+pub const SECTION_LENGTH: u32 = 32;
+pub type SectionShape = ConstShape3u32<SECTION_LENGTH, SECTION_LENGTH, SECTION_LENGTH>;
+
+/// A vertical slice of a chunk.
+/// Sections made of a single voxel value only store that value, the voxel data gets allocated on the first differing write.
+#[derive(Clone, Debug)]
+pub enum ChunkSection<V>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    Uniform(V),
+    Mixed(PaletteVoxelBuffer<V, SectionShape>),
+}
+
+#[allow(dead_code)]
+impl<V> ChunkSection<V>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    // Returns the voxel at the querried position in section local space.
+    #[inline]
+    pub fn voxel_at(&self, pos: UVec3) -> V {
+        match self {
+            Self::Uniform(val) => *val,
+            Self::Mixed(buffer) => buffer.voxel_at(pos),
+        }
+    }
+
+    /// Returns the value this section is made of if it's uniform.
+    #[inline]
+    pub fn uniform_value(&self) -> Option<V> {
+        match self {
+            Self::Uniform(val) => Some(*val),
+            Self::Mixed(_) => None,
+        }
+    }
+
+    #[inline]
+    pub fn is_uniform(&self) -> bool {
+        matches!(self, Self::Uniform(_))
+    }
+
+    /// Sets the voxel at the specified position in section local space, allocating the section data if needed.
+    pub fn set_voxel(&mut self, pos: UVec3, val: V) {
+        match self {
+            Self::Uniform(current) if *current == val => {}
+            Self::Uniform(current) => {
+                let mut buffer = PaletteVoxelBuffer::new(SectionShape {}, *current);
+                buffer.set_voxel(pos, val);
+                *self = Self::Mixed(buffer);
+            }
+            Self::Mixed(buffer) => buffer.set_voxel(pos, val),
+        }
+    }
+
+    /// Fills an extent of this section with the specified value.
+    pub fn fill_extent(&mut self, extent: Extent<UVec3>, val: V) {
+        if extent.minimum == UVec3::ZERO && extent.shape == UVec3::splat(SECTION_LENGTH) {
+            *self = Self::Uniform(val);
+            return;
+        }
+
+        match self {
+            Self::Uniform(current) if *current == val => {}
+            Self::Uniform(current) => {
+                let mut buffer = PaletteVoxelBuffer::new(SectionShape {}, *current);
+                buffer.fill_extent(extent, val);
+                *self = Self::Mixed(buffer);
+            }
+            Self::Mixed(buffer) => buffer.fill_extent(extent, val),
+        }
+    }
+
+    /// Unpacks the voxels of this section into a slice linearized with [`SectionShape`].
+    pub fn copy_to_slice(&self, dst: &mut [V]) {
+        match self {
+            Self::Uniform(val) => dst.fill(*val),
+            Self::Mixed(buffer) => buffer.copy_to_slice(dst),
+        }
+    }
+
+    /// Turns this section back into a uniform one if all its voxels hold the same value.
+    pub fn collapse(&mut self) {
+        if let Self::Mixed(buffer) = self {
+            buffer.compact();
+            if let [val] = buffer.palette() {
+                *self = Self::Uniform(*val);
+            }
+        }
+    }
+
+    /// Returns the approximate number of bytes allocated on the heap by this section.
+    #[inline]
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Self::Uniform(_) => 0,
+            Self::Mixed(buffer) => buffer.heap_size(),
+        }
+    }
+}
+
+/// A buffer of typed voxel data split into vertical [`SECTION_LENGTH`]^3 sections.
+/// Uniform sections only store a single value, which makes mostly empty or solid chunks cheap to store, copy and mesh.
+#[derive(Clone, Debug)]
+pub struct SectionedVoxelBuffer<V, S: Shape<3, Coord = u32>>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    sections: Box<[ChunkSection<V>]>,
+    shape: S,
+}
+
+#[allow(dead_code)]
+impl<V, S: Shape<3, Coord = u32>> SectionedVoxelBuffer<V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    #[inline]
+    pub fn new(shape: S, initial_val: V) -> Self {
+        let [x, y, z] = shape.as_array();
+        assert!(
+            x == SECTION_LENGTH && z == SECTION_LENGTH && y % SECTION_LENGTH == 0,
+            "a sectioned buffer must be {} voxels wide and a multiple of {} voxels high",
+            SECTION_LENGTH,
+            SECTION_LENGTH
+        );
+
+        Self {
+            sections: vec![ChunkSection::Uniform(initial_val); (y / SECTION_LENGTH) as usize]
+                .into_boxed_slice(),
+            shape,
+        }
+    }
+
+    #[inline]
+    pub fn new_empty(shape: S) -> Self {
+        Self::new(shape, Default::default())
+    }
+
+    /// Builds a sectioned buffer holding the same voxels as the specified [`VoxelBuffer`], eliding uniform sections.
+    pub fn from_buffer(buffer: &VoxelBuffer<V, S>) -> Self
+    where
+        S: Clone,
+    {
+        let mut sectioned = Self::new_empty(buffer.shape().clone());
+        let mut section_data = VoxelBuffer::<V, SectionShape>::new_empty(SectionShape {});
+
+        for (index, section) in sectioned.sections.iter_mut().enumerate() {
+            let min_y = index as u32 * SECTION_LENGTH;
+            ndcopy::copy3(
+                [SECTION_LENGTH; 3],
+                buffer.slice(),
+                buffer.shape(),
+                [0, min_y, 0],
+                section_data.slice_mut(),
+                &SectionShape {},
+                [0; 3],
+            );
+
+            let first = section_data.slice()[0];
+            *section = if section_data.slice().iter().all(|x| *x == first) {
+                ChunkSection::Uniform(first)
+            } else {
+                ChunkSection::Mixed(PaletteVoxelBuffer::from_buffer(&section_data))
+            };
+        }
+
+        sectioned
+    }
+
+    /// Unpacks this buffer into a contiguous [`VoxelBuffer`].
+    pub fn to_buffer(&self) -> VoxelBuffer<V, S>
+    where
+        S: Clone,
+    {
+        let mut buffer = VoxelBuffer::new_empty(self.shape.clone());
+        let mut section_data = VoxelBuffer::<V, SectionShape>::new_empty(SectionShape {});
+
+        for (index, section) in self.sections.iter().enumerate() {
+            match section {
+                ChunkSection::Uniform(val) => buffer.fill_extent(
+                    Self::section_extent(index),
+                    *val,
+                ),
+                ChunkSection::Mixed(data) => {
+                    data.copy_to_slice(section_data.slice_mut());
+                    ndcopy::copy3(
+                        [SECTION_LENGTH; 3],
+                        section_data.slice(),
+                        &SectionShape {},
+                        [0; 3],
+                        buffer.slice_mut(),
+                        &self.shape,
+                        [0, index as u32 * SECTION_LENGTH, 0],
+                    );
+                }
+            }
+        }
+
+        buffer
+    }
+
+    // Returns the voxel at the querried position in local space.
+    #[inline]
+    pub fn voxel_at(&self, pos: UVec3) -> V {
+        let (index, local) = Self::split_pos(pos);
+        self.sections[index].voxel_at(local)
+    }
+
+    // Returns a handle to the voxel at the querried position in local space.
+    // The value is written back to the buffer when the handle is dropped.
+    #[inline]
+    pub fn voxel_at_mut(&mut self, pos: UVec3) -> SectionedVoxelMut<'_, V, S> {
+        SectionedVoxelMut {
+            value: self.voxel_at(pos),
+            pos,
+            buffer: self,
+        }
+    }
+
+    /// Sets the voxel at the specified position in local space.
+    #[inline]
+    pub fn set_voxel(&mut self, pos: UVec3, val: V) {
+        let (index, local) = Self::split_pos(pos);
+        self.sections[index].set_voxel(local, val);
+    }
+
+    /// Fills an extent of this buffer with the specified value.
+    pub fn fill_extent(&mut self, extent: Extent<UVec3>, val: V) {
+        if extent.shape.cmpeq(UVec3::ZERO).any() {
+            return;
+        }
+
+        let first_section = (extent.minimum.y / SECTION_LENGTH) as usize;
+        let last_section = ((extent.max().y) / SECTION_LENGTH) as usize;
+
+        for index in first_section..=last_section {
+            let section_min_y = index as u32 * SECTION_LENGTH;
+            let min_y = extent.minimum.y.max(section_min_y) - section_min_y;
+            let lub_y = extent.least_upper_bound().y.min(section_min_y + SECTION_LENGTH) - section_min_y;
+
+            self.sections[index].fill_extent(
+                Extent::from_min_and_shape(
+                    UVec3::new(extent.minimum.x, min_y, extent.minimum.z),
+                    UVec3::new(extent.shape.x, lub_y - min_y, extent.shape.z),
+                ),
+                val,
+            );
+        }
+    }
+
+    /// Turns the sections holding a single value back into uniform sections.
+    pub fn collapse_sections(&mut self) {
+        self.sections.iter_mut().for_each(|section| section.collapse());
+    }
+
+    #[inline]
+    pub fn sections(&self) -> &[ChunkSection<V>] {
+        &self.sections
+    }
+
+    #[inline]
+    pub fn section(&self, index: usize) -> Option<&ChunkSection<V>> {
+        self.sections.get(index)
+    }
+
+    #[inline]
+    pub fn num_sections(&self) -> usize {
+        self.sections.len()
+    }
+
+    /// Returns the extent covered by the section at the specified index, in local space.
+    #[inline]
+    pub fn section_extent(index: usize) -> Extent<UVec3> {
+        Extent::from_min_and_shape(
+            UVec3::new(0, index as u32 * SECTION_LENGTH, 0),
+            UVec3::splat(SECTION_LENGTH),
+        )
+    }
+
+    /// Returns the approximate number of bytes allocated on the heap by this buffer.
+    #[inline]
+    pub fn heap_size(&self) -> usize {
+        self.sections.len() * std::mem::size_of::<ChunkSection<V>>()
+            + self.sections.iter().map(|x| x.heap_size()).sum::<usize>()
+    }
+
+    #[inline]
+    pub const fn shape(&self) -> &S {
+        &self.shape
+    }
+
+    #[inline]
+    fn split_pos(pos: UVec3) -> (usize, UVec3) {
+        (
+            (pos.y / SECTION_LENGTH) as usize,
+            UVec3::new(pos.x, pos.y % SECTION_LENGTH, pos.z),
+        )
+    }
+}
+
+impl<V, S> From<VoxelBuffer<V, S>> for SectionedVoxelBuffer<V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+    S: Shape<3, Coord = u32> + Clone,
+{
+    fn from(buffer: VoxelBuffer<V, S>) -> Self {
+        Self::from_buffer(&buffer)
+    }
+}
+
+/// A mutable handle to a single voxel of a [`SectionedVoxelBuffer`], written back on drop.
+pub struct SectionedVoxelMut<'a, V, S: Shape<3, Coord = u32>>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    buffer: &'a mut SectionedVoxelBuffer<V, S>,
+    pos: UVec3,
+    value: V,
+}
+
+impl<'a, V, S: Shape<3, Coord = u32>> Deref for SectionedVoxelMut<'a, V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<'a, V, S: Shape<3, Coord = u32>> DerefMut for SectionedVoxelMut<'a, V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<'a, V, S: Shape<3, Coord = u32>> Drop for SectionedVoxelMut<'a, V, S>
+where
+    V: Copy + Clone + Default + PartialEq,
+{
+    fn drop(&mut self) {
+        self.buffer.set_voxel(self.pos, self.value);
+    }
+}
@@ -1,138 +1,119 @@
-// use std::ops::Div;
-
-// use bevy::math::{IVec3, UVec3};
-// use ilattice::{glam::UVec2, prelude::Extent};
-
-// use crate::voxel::{
-//     material::VoxelMaterial,
-//     materials::{Dirt, Grass},
-//     storage::VoxelBuffer,
-//     terraingen::noise::Heightmap,
-//     ChunkShape, Voxel, CHUNK_LENGTH, CHUNK_LENGTH_U, CHUNK_HEIGHT,
-// };
-
-// use super::BiomeTerrainGenerator;
-
-// /// A biome terrain generator that applies a set of layers on top of the terrain.
-// pub trait LayeredBiomeTerrainGenerator: BiomeTerrainGenerator {
-//     /// The height function to use for applying the biome material layers on top of the terrain.
-//     fn fill_strata(&self, layer: u32) -> Voxel {
-//         match layer {
-//             0..=1 => Grass::into_voxel(),
-//             _ => Dirt::into_voxel(),
-//         }
-//     }
-
-//     /// Numbers of material layers to apply on top of the terrain
-//     fn num_layers(&self) -> u32 {
-//         8
-//     }
-
-//     fn place_decoration(
-//         &self,
-//         _key: IVec3,
-//         _pos: UVec3,
-//         _buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-//     ) {
-//     }
-
-//     fn get_name(&self) -> &'static str {
-//         "Default Layered Biome"
-//     }
-// }
-
-// impl<T: LayeredBiomeTerrainGenerator> BiomeTerrainGenerator for T {
-//     fn carve_terrain(
-//         &self,
-//         chunk_key: IVec3,
-//         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-//         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-//     ) {
-//         Extent::from_min_and_shape(UVec2::ZERO, UVec2::splat(CHUNK_LENGTH))
-//             .iter2()
-//             .for_each(|pos| {
-//                 let height = heightmap.get(pos.into());
-//                 // we only want to apply surface layer decoration on top of the surface chunk
-//                 if height.div(CHUNK_HEIGHT) == (chunk_key.y as u32).div(CHUNK_HEIGHT) {
-//                     let local_height = height.rem_euclid(CHUNK_HEIGHT);
-
-//                     for h in 0..=self.num_layers() {
-//                         let remaining_height = local_height.checked_sub(h);
-
-//                         if let Some(uh) = remaining_height {
-//                             *buffer.voxel_at_mut([pos.x, uh, pos.y].into()) = self.fill_strata(h)
-//                         }
-//                     }
-//                 }
-//             });
-//     }
-
-//     fn carve_terrain_at_xz(
-//         &self,
-//         chunk_key: IVec3,
-//         x: u32,
-//         z: u32,
-//         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-//         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-//     ) {
-//         let height = heightmap.get([x as u32, z as u32].into());
-//         // we only want to apply surface layer decoration on top of the surface chunk
-//         if height.div(CHUNK_HEIGHT) == (chunk_key.y as u32).div(CHUNK_HEIGHT) {
-//             let local_height = height.rem_euclid(CHUNK_HEIGHT);
-
-//             for h in 0..=self.num_layers() {
-//                 let remaining_height = local_height.checked_sub(h);
-
-//                 if let Some(uh) = remaining_height {
-//                     *buffer.voxel_at_mut([x, uh as u32, z].into()) = self.fill_strata(h)
-//                 }
-//             }
-//         }
-//     }
-
-//     fn decorate_terrain(
-//         &self,
-//         chunk_key: IVec3,
-//         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-//         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-//     ) {
-//         // if chunk_key.y <= 96 {
-//         //     return;
-//         // }
-
-//         Extent::from_min_and_shape(UVec2::ZERO, UVec2::splat(CHUNK_LENGTH))
-//             .iter2()
-//             .for_each(|pos| {
-//                 let height = heightmap.get(pos.into());
-
-//                 if height.div(CHUNK_HEIGHT) == (chunk_key.y as u32).div(CHUNK_HEIGHT) {
-//                     let local_height = height.rem_euclid(CHUNK_HEIGHT);
-//                     self.place_decoration(chunk_key, [pos.x, local_height, pos.y].into(), buffer);
-//                 }
-//             });
-//     }
-
-//     fn decorate_terrain_at_xz(
-//         &self,
-//         chunk_key: IVec3,
-//         x: u32,
-//         z: u32,
-//         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-//         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-//     ) {
-//         // if chunk_key.y <= 96 {
-//         //     return;
-//         // }
-
-//         let height = heightmap.get([x as u32, z as u32].into());
-
-//         // if height.div(CHUNK_HEIGHT) == (chunk_key.y as u32).div(CHUNK_HEIGHT) {
-//             // let local_height = height.rem_euclid(CHUNK_HEIGHT);
-//             self.place_decoration(chunk_key, [x, height, z].into(), buffer);
-//         // }
-//     }
-
-//     fn name(&self) -> &'static str {
-//         self.get_name()
-//     }
-// }
\ No newline at end of file
+use bevy::math::{IVec3, UVec3};
+use ilattice::prelude::UVec3 as ILUVec3;
+
+use crate::voxel::{
+    material::VoxelMaterial,
+    materials::{Dirt, Grass},
+    storage::SectionedVoxelBuffer,
+    terraingen::noise::Heightmap,
+    ChunkShape, Voxel, CHUNK_HEIGHT, CHUNK_LENGTH, CHUNK_LENGTH_U,
+};
+
+use super::{BiomeTerrainGenerator, SurfaceShape};
+
+/// A biome terrain generator that applies a set of layers on top of the terrain.
+pub trait LayeredBiomeTerrainGenerator: 'static + Sync + Send {
+    /// The height function to use for applying the biome material layers on top of the terrain.
+    fn fill_strata(&self, layer: u32) -> Voxel {
+        match layer {
+            0..=1 => Grass::into_voxel(),
+            _ => Dirt::into_voxel(),
+        }
+    }
+
+    /// Numbers of material layers to apply on top of the terrain
+    fn num_layers(&self) -> u32 {
+        8
+    }
+
+    /// Places the decorations of a column, `pos` being the voxel right above its surface.
+    fn place_decoration(
+        &self,
+        _key: IVec3,
+        _pos: UVec3,
+        _buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    ) {
+    }
+
+    fn get_name(&self) -> &'static str {
+        "Default Layered Biome"
+    }
+
+    fn get_surface_shape(&self) -> SurfaceShape {
+        SurfaceShape::default()
+    }
+}
+
+/// Height of the voxel right above the surface of a column, relative to the chunk.
+fn local_surface_height(chunk_key: IVec3, x: u32, z: u32, heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>) -> i32 {
+    heightmap.getf([x, z]).round() as i32 - chunk_key.y
+}
+
+impl<T: LayeredBiomeTerrainGenerator> BiomeTerrainGenerator for T {
+    fn carve_terrain(
+        &self,
+        chunk_key: IVec3,
+        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        for z in 0..CHUNK_LENGTH {
+            for x in 0..CHUNK_LENGTH {
+                self.carve_terrain_at_xz(chunk_key, x, z, heightmap, buffer);
+            }
+        }
+    }
+
+    fn carve_terrain_at_xz(
+        &self,
+        chunk_key: IVec3,
+        x: u32,
+        z: u32,
+        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        // columns going through several chunks only get the part of their layers inside this one.
+        let surface_height = local_surface_height(chunk_key, x, z, heightmap);
+        for layer in 0..=self.num_layers() {
+            let y = surface_height - 1 - layer as i32;
+            if (0..CHUNK_HEIGHT as i32).contains(&y) {
+                buffer.set_voxel(ILUVec3::new(x, y as u32, z), self.fill_strata(layer));
+            }
+        }
+    }
+
+    fn decorate_terrain(
+        &self,
+        chunk_key: IVec3,
+        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        for z in 0..CHUNK_LENGTH {
+            for x in 0..CHUNK_LENGTH {
+                self.decorate_terrain_at_xz(chunk_key, x, z, heightmap, buffer);
+            }
+        }
+    }
+
+    fn decorate_terrain_at_xz(
+        &self,
+        chunk_key: IVec3,
+        x: u32,
+        z: u32,
+        heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        // decorations stand on the surface, only the chunk holding the voxel above it gets them.
+        let surface_height = local_surface_height(chunk_key, x, z, heightmap);
+        if (0..CHUNK_HEIGHT as i32).contains(&surface_height) {
+            self.place_decoration(chunk_key, UVec3::new(x, surface_height as u32, z), buffer);
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        self.get_name()
+    }
+
+    fn surface_shape(&self) -> SurfaceShape {
+        self.get_surface_shape()
+    }
+}
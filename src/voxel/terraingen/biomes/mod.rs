@@ -1,4 +1,4 @@
-use crate::voxel::{storage::VoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH_U};
+use crate::voxel::{storage::SectionedVoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH_U};
 
 use super::noise::Heightmap;
 
@@ -15,6 +15,52 @@ pub use desert::*;
 mod snowy_plains;
 pub use snowy_plains::*;
 
+/// How a biome reshapes the terrain surface given by the terrain splines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceShape {
+    /// Voxels added to the surface height.
+    pub offset: f32,
+    /// Scale of the peaks and valleys relief.
+    pub amplitude: f32,
+}
+
+impl Default for SurfaceShape {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            amplitude: 1.0,
+        }
+    }
+}
+
+impl SurfaceShape {
+    /// Returns the surface height of a column from the height of its terrain and the relief on top of it.
+    pub fn apply(&self, height: f32, relief: f32) -> f32 {
+        height + self.offset + relief * self.amplitude
+    }
+
+    /// Mixes weighted shapes, which gives the same heights as mixing the heights of each shape.
+    pub fn blend(shapes: impl IntoIterator<Item = (SurfaceShape, f32)>) -> Self {
+        let (shape, weight_sum) = shapes.into_iter().fold(
+            (Self { offset: 0.0, amplitude: 0.0 }, 0.0),
+            |(sum, weight_sum), (shape, weight)| {
+                let offset = sum.offset + shape.offset * weight;
+                let amplitude = sum.amplitude + shape.amplitude * weight;
+                (Self { offset, amplitude }, weight_sum + weight)
+            },
+        );
+
+        if weight_sum > 0.0 {
+            Self {
+                offset: shape.offset / weight_sum,
+                amplitude: shape.amplitude / weight_sum,
+            }
+        } else {
+            Self::default()
+        }
+    }
+}
+
 /// A trait representing a terrain generator for a biome.
 /// A biome can be defined as a collection of features that are applied on top of the terrain.
 pub trait BiomeTerrainGenerator: 'static + Sync + Send {
@@ -23,7 +69,7 @@ pub trait BiomeTerrainGenerator: 'static + Sync + Send {
         &self,
         chunk_key: IVec3,
         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     );
 
     fn carve_terrain_at_xz(
@@ -32,7 +78,7 @@ pub trait BiomeTerrainGenerator: 'static + Sync + Send {
         x: u32,
         z: u32,
         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     );
 
     /// Decorate the terrain with this biome specific features (e.g. flowers, trees, ores etc).
@@ -40,7 +86,7 @@ pub trait BiomeTerrainGenerator: 'static + Sync + Send {
         &self,
         chunk_key: IVec3,
         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     );
 
     fn decorate_terrain_at_xz(
@@ -49,10 +95,24 @@ pub trait BiomeTerrainGenerator: 'static + Sync + Send {
         x: u32,
         z: u32,
         heightmap: Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-        buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     );
 
     fn name(&self) -> &'static str;
+
+    /// How the biome reshapes the terrain surface, blended with the neighbouring biomes along its borders.
+    fn surface_shape(&self) -> SurfaceShape {
+        SurfaceShape::default()
+    }
+
+    /// Color of the biome on biome maps, derived from its name unless overridden.
+    fn map_color(&self) -> [u8; 3] {
+        let hash = self
+            .name()
+            .bytes()
+            .fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+        [hash as u8, (hash >> 8) as u8, (hash >> 16) as u8]
+    }
 }
 
 /// Utility trait for boxing biome generators.
@@ -1,70 +1,52 @@
-// use crate::voxel::{
-//     material::VoxelMaterial,
-//     materials::{Dirt, Grass},
-//     storage::VoxelBuffer,
-//     terraingen::noise,
-//     ChunkShape, Voxel,
-// };
-// use bevy::math::{IVec3, UVec3, Vec2, Vec3Swizzles};
-// use ilattice::prelude::UVec3 as ILUVec3;
-
-// use super::LayeredBiomeTerrainGenerator;
-
-// pub struct BasicPlainsBiomeTerrainGenerator;
-
-// impl LayeredBiomeTerrainGenerator for BasicPlainsBiomeTerrainGenerator {
-//     fn fill_strata(&self, layer: u32) -> Voxel {
-//         match layer {
-//             0..=1 => Grass::into_voxel(),
-//             _ => Dirt::into_voxel(),
-//         }
-//     }
-
-//     fn place_decoration(
-//         &self,
-//         key: IVec3,
-//         pos: UVec3,
-//         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-//     ) {
-//         // let spawn_chance = noise::rand2to1(
-//         //     (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
-//         //     Vec2::new(12.989, 78.233),
-//         // );
-
-//         let grass_blade_height = ((noise::rand2to1(
-//             (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
-//             Vec2::new(42.4782, 8472.2437),
-//         ) * 100.) as u32)
-//             .rem_euclid(4);
-
-//         // info!("grass blade height: {}", grass_blade_height);
-
-//         if grass_blade_height > 1 {
-//             for y in 0..grass_blade_height {
-//                 let position = ILUVec3::from_array(pos.to_array()) + ILUVec3::new(0, y, 0);
-//                 *buffer.voxel_at_mut(position) = Grass::into_voxel();
-//             }
-//         }
-
-
-//         // Let's put some rock boulders in the plains to populate a lil bit
-//         // let rock_spawn_chance = noise::rand2to1(
-//         //     (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
-//         //     Vec2::new(72845.4782, 8472.2437),
-//         // );
-
-//         // if rock_spawn_chance > 0.995 {
-//         //     let rock_size = (1.0f32 - rock_spawn_chance) * 1000.0;
-//         //     make_rock::<Rock>(buffer, ILUVec3::from(pos.to_array()), rock_size);
-//         // }
-
-//         // if spawn_chance > 0.998 {
-//         //     // this is a stupid hack but a real fix would be to allow terrain decoration to work vertically
-//         //     make_tree::<Wood, Leaves>(buffer, ILUVec3::from(pos.to_array()));
-//         // }
-//     }
-
-//     fn get_name(&self) -> &'static str {
-//         "Plains"
-//     }
-// }
\ No newline at end of file
+use crate::voxel::{
+    material::VoxelMaterial,
+    materials::{Dirt, Grass},
+    storage::SectionedVoxelBuffer,
+    terraingen::noise,
+    ChunkShape, Voxel, CHUNK_HEIGHT,
+};
+use bevy::math::{IVec3, UVec3, Vec2, Vec3Swizzles};
+use ilattice::prelude::UVec3 as ILUVec3;
+
+use super::{LayeredBiomeTerrainGenerator, SurfaceShape};
+
+pub struct BasicPlainsBiomeTerrainGenerator;
+
+impl LayeredBiomeTerrainGenerator for BasicPlainsBiomeTerrainGenerator {
+    fn fill_strata(&self, layer: u32) -> Voxel {
+        match layer {
+            0..=1 => Grass::into_voxel(),
+            _ => Dirt::into_voxel(),
+        }
+    }
+
+    fn place_decoration(
+        &self,
+        key: IVec3,
+        pos: UVec3,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        let grass_blade_height = ((noise::rand2to1(
+            (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
+            Vec2::new(42.4782, 8472.2437),
+        ) * 100.) as u32)
+            .rem_euclid(4);
+
+        if grass_blade_height > 1 {
+            for y in pos.y..(pos.y + grass_blade_height).min(CHUNK_HEIGHT) {
+                *buffer.voxel_at_mut(ILUVec3::new(pos.x, y, pos.z)) = Grass::into_voxel();
+            }
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        "Plains"
+    }
+
+    fn get_surface_shape(&self) -> SurfaceShape {
+        SurfaceShape {
+            offset: 0.0,
+            amplitude: 0.7,
+        }
+    }
+}
@@ -1,44 +1,54 @@
-// use crate::voxel::{
-//     material::VoxelMaterial,
-//     materials::{Dirt, Grass, PineLeaves, PineWood, Snow},
-//     storage::VoxelBuffer,
-//     terraingen::{common::make_pine_tree, noise},
-//     ChunkShape, Voxel,
-// };
-// use bevy::math::{IVec3, UVec3, Vec2, Vec3Swizzles};
-// use ilattice::prelude::UVec3 as ILUVec3;
-
-// use super::LayeredBiomeTerrainGenerator;
-
-// pub struct BasicSnowyPlainsBiomeTerrainGenerator;
-
-// impl LayeredBiomeTerrainGenerator for BasicSnowyPlainsBiomeTerrainGenerator {
-//     fn fill_strata(&self, layer: u32) -> Voxel {
-//         match layer {
-//             0 => Snow::into_voxel(),
-//             1..=2 => Grass::into_voxel(),
-//             _ => Dirt::into_voxel(),
-//         }
-//     }
-
-//     fn place_decoration(
-//         &self,
-//         key: IVec3,
-//         pos: UVec3,
-//         buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
-//     ) {
-//         let spawn_chance = noise::rand2to1(
-//             (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
-//             Vec2::new(12.989, 78.233),
-//         );
-
-//         if spawn_chance > 0.981 && pos.y <= 13 {
-//             // this is a stupid hack but a real fix would be to allow terrain decoration to work vertically
-//             make_pine_tree::<PineWood, PineLeaves>(buffer, ILUVec3::from(pos.to_array()));
-//         }
-//     }
-
-//     fn get_name(&self) -> &'static str {
-//         "Snowy Plains"
-//     }
-// }
\ No newline at end of file
+use crate::voxel::{
+    material::VoxelMaterial,
+    materials::{Dirt, Grass, PineLeaves, PineWood, Snow},
+    storage::SectionedVoxelBuffer,
+    terraingen::{common::make_pine_tree, noise},
+    ChunkShape, Voxel, CHUNK_HEIGHT,
+};
+use bevy::math::{IVec3, UVec3, Vec2, Vec3Swizzles};
+use ilattice::prelude::UVec3 as ILUVec3;
+
+use super::{LayeredBiomeTerrainGenerator, SurfaceShape};
+
+/// Height of the pine trees, which are cut by the top of the chunk otherwise.
+const PINE_TREE_HEIGHT: u32 = 24;
+
+pub struct BasicSnowyPlainsBiomeTerrainGenerator;
+
+impl LayeredBiomeTerrainGenerator for BasicSnowyPlainsBiomeTerrainGenerator {
+    fn fill_strata(&self, layer: u32) -> Voxel {
+        match layer {
+            0 => Snow::into_voxel(),
+            1..=2 => Grass::into_voxel(),
+            _ => Dirt::into_voxel(),
+        }
+    }
+
+    fn place_decoration(
+        &self,
+        key: IVec3,
+        pos: UVec3,
+        buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    ) {
+        let spawn_chance = noise::rand2to1(
+            (pos.xz().as_vec2() + key.xz().as_vec2()) * 0.1,
+            Vec2::new(12.989, 78.233),
+        );
+
+        if spawn_chance > 0.981 && pos.y + PINE_TREE_HEIGHT <= CHUNK_HEIGHT {
+            // this is a stupid hack but a real fix would be to allow terrain decoration to work vertically
+            make_pine_tree::<PineWood, PineLeaves>(buffer, ILUVec3::from(pos.to_array()));
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        "Snowy Plains"
+    }
+
+    fn get_surface_shape(&self) -> SurfaceShape {
+        SurfaceShape {
+            offset: 10.0,
+            amplitude: 1.3,
+        }
+    }
+}
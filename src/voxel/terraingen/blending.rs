@@ -0,0 +1,113 @@
+use bevy::math::{IVec2, IVec3};
+
+use super::noise::{get_chunk_humidity, get_chunk_temperature};
+use crate::voxel::CHUNK_LENGTH_U;
+
+/// Distance up to which the biomes around a column weigh on it, in voxels.
+pub const BLEND_RADIUS: i32 = 16;
+/// Spacing of the climate samples, which are aligned on world coordinates so chunks blend the same samples
+/// along their borders.
+const SAMPLE_SPACING: i32 = 4;
+/// Standard deviation of the Gaussian kernel weighting the climate samples.
+const KERNEL_SIGMA: f32 = 8.0;
+
+/// The biomes weighing on a column, as indices into the registered biomes, their weights adding up to 1.
+#[derive(Clone, Debug, Default)]
+pub struct BiomeBlend {
+    weights: Vec<(usize, f32)>,
+}
+
+impl BiomeBlend {
+    pub fn weights(&self) -> &[(usize, f32)] {
+        &self.weights
+    }
+
+    /// Picks one of the biomes, each with a chance equal to its weight, `roll` going from 0 to 1.
+    /// Used for what can't be mixed, like surface materials.
+    pub fn pick(&self, roll: f32) -> Option<usize> {
+        let mut weight_sum = 0.0;
+        self.weights
+            .iter()
+            .find(|(_, weight)| {
+                weight_sum += weight;
+                roll < weight_sum
+            })
+            .or(self.weights.last())
+            .map(|(biome, _)| *biome)
+    }
+
+    fn add(&mut self, biome: usize, weight: f32) {
+        match self.weights.iter_mut().find(|(x, _)| *x == biome) {
+            Some((_, x)) => *x += weight,
+            None => self.weights.push((biome, weight)),
+        }
+    }
+
+    fn normalize(&mut self) {
+        let weight_sum: f32 = self.weights.iter().map(|(_, weight)| weight).sum();
+        if weight_sum > 0.0 {
+            self.weights.iter_mut().for_each(|(_, weight)| *weight /= weight_sum);
+        }
+    }
+}
+
+/// Blends the biomes of the climate samples around each column of a chunk, laid out like the noise layers.
+/// `biome_at` gives the biome of a humidity and a temperature.
+pub fn chunk_biome_blends(key: IVec3, seed: i32, biome_at: impl Fn(f32, f32) -> Option<usize>) -> Vec<BiomeBlend> {
+    let chunk_min = IVec2::new(key.x, key.z);
+    let grid_min = (chunk_min - BLEND_RADIUS).div_euclid(IVec2::splat(SAMPLE_SPACING)) * SAMPLE_SPACING;
+    let grid_max = (chunk_min + (CHUNK_LENGTH_U as i32 - 1) + BLEND_RADIUS).div_euclid(IVec2::splat(SAMPLE_SPACING))
+        * SAMPLE_SPACING;
+
+    // climate noise is sampled on the whole area, then only read on the grid.
+    let area_len = (grid_max.x - grid_min.x + 1) as usize;
+    let area_key = IVec3::new(grid_min.x, key.y, grid_min.y);
+    let humidity = get_chunk_humidity(area_key, area_len, seed);
+    let temperature = get_chunk_temperature(area_key, area_len, seed);
+
+    let grid_len = (grid_max.x - grid_min.x) / SAMPLE_SPACING + 1;
+    let samples: Vec<Option<usize>> = (0..grid_len * grid_len)
+        .map(|i| {
+            let position = IVec2::new(i % grid_len, i / grid_len) * SAMPLE_SPACING;
+            let index = position.y as usize * area_len + position.x as usize;
+            biome_at(humidity[index], temperature[index])
+        })
+        .collect();
+
+    let mut blends = Vec::with_capacity(CHUNK_LENGTH_U * CHUNK_LENGTH_U);
+    for z in 0..CHUNK_LENGTH_U as i32 {
+        for x in 0..CHUNK_LENGTH_U as i32 {
+            let column = chunk_min + IVec2::new(x, z);
+            let first = (column - BLEND_RADIUS - grid_min + SAMPLE_SPACING - 1) / SAMPLE_SPACING;
+            let last = (column + BLEND_RADIUS - grid_min) / SAMPLE_SPACING;
+
+            let mut blend = BiomeBlend::default();
+            for sample_z in first.y.max(0)..=last.y.min(grid_len - 1) {
+                for sample_x in first.x.max(0)..=last.x.min(grid_len - 1) {
+                    let Some(biome) = samples[(sample_z * grid_len + sample_x) as usize] else {
+                        continue;
+                    };
+                    let distance_squared =
+                        (grid_min + IVec2::new(sample_x, sample_z) * SAMPLE_SPACING - column).length_squared();
+                    if distance_squared <= BLEND_RADIUS * BLEND_RADIUS {
+                        blend.add(biome, (-(distance_squared as f32) / (2.0 * KERNEL_SIGMA * KERNEL_SIGMA)).exp());
+                    }
+                }
+            }
+            blend.normalize();
+            blends.push(blend);
+        }
+    }
+    blends
+}
+
+/// A number from 0 to 1 which only depends on the column and the seed, to pick among blended biomes.
+pub fn column_roll(x: i32, z: i32, seed: i32) -> f32 {
+    let mut hash = (x as u32).wrapping_mul(0x8da6_b343) ^ (z as u32).wrapping_mul(0xd816_3841) ^ (seed as u32).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb_352d);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x846c_a68b);
+    hash ^= hash >> 16;
+    (hash >> 8) as f32 / (1 << 24) as f32
+}
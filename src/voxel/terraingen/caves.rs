@@ -0,0 +1,326 @@
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Range,
+};
+
+use anyhow::{bail, Result};
+use bevy::math::{IVec3, Vec3};
+use ilattice::glam::UVec3;
+use serde::{Deserialize, Serialize};
+
+use crate::voxel::{
+    material::VoxelMaterial, materials::Bedrock, storage::SectionedVoxelBuffer, ChunkShape, Voxel, CHUNK_HEIGHT,
+    CHUNK_LENGTH, CHUNK_LENGTH_U,
+};
+
+use super::{
+    blending::column_roll,
+    noise::{perlin_1d, Heightmap, NoiseGrid},
+};
+
+const CHEESE_SEED_OFFSET: i32 = 49_979_687;
+const SPAGHETTI_SEED_OFFSETS: [i32; 2] = [67_867_967, 86_028_121];
+const WORM_SEED_OFFSET: i32 = 104_395_301;
+const BREACH_SEED_OFFSET: i32 = 122_949_823;
+
+const CHEESE_OCTAVES: u8 = 3;
+const SPAGHETTI_OCTAVES: u8 = 1;
+
+/// Caves stop this many voxels under the surface unless they breach it.
+const SURFACE_MARGIN: i32 = 8;
+/// Width of the areas whose noise caves either all breach the surface or none does.
+const BREACH_REGION_SIZE: i32 = 64;
+
+/// Distance travelled by worms at each step.
+const WORM_STEP: f32 = 1.0;
+/// How fast worms turn, in noise periods per step.
+const WORM_TURN_RATE: f32 = 0.03;
+/// Steepest slope of worms, in radians.
+const WORM_MAX_PITCH: f32 = 0.6;
+/// Number of steps over which worms widen from their ends.
+const WORM_TAPER_STEPS: f32 = 6.0;
+
+/// Settings of the caves dug under the surface once the terrain is filled.
+///
+/// Cheese caves are large caverns hollowed out where 3D noise is above `cheese_threshold`, and spaghetti caves
+/// long tunnels where two 3D noises are both within `spaghetti_width` of 0. Worms are tunnels dug by spheres
+/// travelling along paths steered by noise, from random points of every chunk.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaveSettings {
+    pub cheese_frequency: f32,
+    /// Noise value from -1 to 1 above which cheese caves are carved, 1 leaving none.
+    pub cheese_threshold: f32,
+    pub spaghetti_frequency: f32,
+    /// The wider, the wider and more frequent the spaghetti caves, 0 leaving none.
+    pub spaghetti_width: f32,
+    /// Average number of worms starting in each chunk.
+    pub worms_per_chunk: f32,
+    /// Longest distance travelled by worms, in voxels.
+    pub worm_length: u32,
+    pub worm_radius: Range<f32>,
+    /// Chance from 0 to 1 for a cave to break through the surface rather than stopping a few voxels under it.
+    pub surface_breach_chance: f32,
+    /// Noise is sampled at the corners of cells this many voxels wide and interpolated in between,
+    /// 1 sampling it at every voxel. Must divide [`CHUNK_LENGTH`].
+    pub cell_size: u32,
+}
+
+impl CaveSettings {
+    /// Checks that the settings can dig caves, e.g. once deserialized.
+    pub fn check(&self) -> Result<()> {
+        if self.cell_size == 0 || !CHUNK_LENGTH.is_multiple_of(self.cell_size) {
+            bail!("cave cells must divide chunks of {} voxels, got a size of {}", CHUNK_LENGTH, self.cell_size);
+        }
+        let radius = &self.worm_radius;
+        if !(radius.start >= 0.0 && radius.start <= radius.end && radius.end.is_finite()) {
+            bail!("invalid worm radius range {:?}", radius);
+        }
+        if !(self.worms_per_chunk >= 0.0 && self.worms_per_chunk.is_finite()) {
+            bail!("invalid number of worms per chunk {}", self.worms_per_chunk);
+        }
+        if !self.cheese_frequency.is_finite() || !self.spaghetti_frequency.is_finite() {
+            bail!("invalid cave frequencies {} and {}", self.cheese_frequency, self.spaghetti_frequency);
+        }
+        Ok(())
+    }
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            cheese_frequency: 0.012,
+            cheese_threshold: 0.6,
+            spaghetti_frequency: 0.02,
+            spaghetti_width: 0.05,
+            worms_per_chunk: 1.0,
+            worm_length: 128,
+            worm_radius: 2.0..4.0,
+            surface_breach_chance: 0.1,
+            cell_size: 4,
+        }
+    }
+}
+
+/// Dig the caves of a chunk. `heightmap` holds the absolute surface height of each column, `min_height` is the
+/// world bottom: bedrock and the bottom layer are never carved.
+pub fn terrain_carve_caves(
+    buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    key: IVec3,
+    seed: i32,
+    min_height: i32,
+    heightmap: &Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+    settings: &CaveSettings,
+) {
+    debug_assert!(settings.check().is_ok(), "cave settings must be checked before carving");
+
+    let surfaces = (0..CHUNK_LENGTH_U * CHUNK_LENGTH_U)
+        .map(|i| heightmap.getf([(i % CHUNK_LENGTH_U) as u32, (i / CHUNK_LENGTH_U) as u32]).round() as i32 - key.y)
+        .collect();
+    let mut carver = Carver {
+        buffer,
+        key,
+        floor: min_height + 1 - key.y,
+        surfaces,
+    };
+    // nothing to dig when the chunk is all above the surface or under the world bottom.
+    let top = carver.surfaces.iter().copied().max().unwrap_or(0);
+    if top <= carver.floor.max(0) || carver.floor >= CHUNK_HEIGHT as i32 {
+        return;
+    }
+
+    carve_noise_caves(&mut carver, seed, settings);
+    carve_worms(&mut carver, seed, settings);
+}
+
+/// Digs voxels out of a chunk, sparing bedrock, the world bottom and what lies right under the surface.
+struct Carver<'a> {
+    buffer: &'a mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    key: IVec3,
+    /// Lowest height that may be carved, relative to the chunk.
+    floor: i32,
+    /// Surface height of each column relative to the chunk.
+    surfaces: Vec<i32>,
+}
+
+impl Carver<'_> {
+    fn surface(&self, x: u32, z: u32) -> i32 {
+        self.surfaces[(z * CHUNK_LENGTH + x) as usize]
+    }
+
+    /// Range of heights of a column that caves may reach, `breach` letting them up to the surface.
+    fn carvable(&self, x: u32, z: u32, breach: bool) -> Range<u32> {
+        let surface = self.surface(x, z);
+        let top = if breach { surface } else { surface - SURFACE_MARGIN };
+        let clamp = |y: i32| y.clamp(0, CHUNK_HEIGHT as i32) as u32;
+        clamp(self.floor)..clamp(top)
+    }
+
+    fn carve(&mut self, pos: UVec3) {
+        let voxel = self.buffer.voxel_at(pos);
+        if voxel != Voxel::EMPTY_VOXEL && voxel.material != Bedrock::ID {
+            self.buffer.set_voxel(pos, Voxel::EMPTY_VOXEL);
+        }
+    }
+}
+
+fn carve_noise_caves(carver: &mut Carver, seed: i32, settings: &CaveSettings) {
+    let key = carver.key;
+    let cell_size = settings.cell_size;
+    let bottom = carver.floor.clamp(0, CHUNK_HEIGHT as i32) as u32;
+    let top = carver.surfaces.iter().copied().max().unwrap_or(0).clamp(0, CHUNK_HEIGHT as i32) as u32;
+    let grid_min_y = bottom / cell_size * cell_size;
+    let grid_max_y = top.div_ceil(cell_size) * cell_size;
+    let sample = |freq: f32, octaves: u8, seed_offset: i32| {
+        NoiseGrid::sample(
+            IVec3::new(key.x, key.y + grid_min_y as i32, key.z),
+            UVec3::new(CHUNK_LENGTH, grid_max_y - grid_min_y, CHUNK_LENGTH),
+            cell_size,
+            freq,
+            octaves,
+            seed.wrapping_add(seed_offset),
+        )
+    };
+    let cheese = sample(settings.cheese_frequency, CHEESE_OCTAVES, CHEESE_SEED_OFFSET);
+    let spaghetti = SPAGHETTI_SEED_OFFSETS.map(|offset| sample(settings.spaghetti_frequency, SPAGHETTI_OCTAVES, offset));
+
+    for z in 0..CHUNK_LENGTH {
+        for x in 0..CHUNK_LENGTH {
+            let breach = column_roll(
+                (key.x + x as i32).div_euclid(BREACH_REGION_SIZE),
+                (key.z + z as i32).div_euclid(BREACH_REGION_SIZE),
+                seed.wrapping_add(BREACH_SEED_OFFSET),
+            ) < settings.surface_breach_chance;
+
+            for y in carver.carvable(x, z, breach) {
+                let pos = UVec3::new(x, y - grid_min_y, z);
+                let hollow = cheese.get(pos) > settings.cheese_threshold
+                    || spaghetti.iter().all(|noise| noise.get(pos).abs() < settings.spaghetti_width);
+                if hollow {
+                    carver.carve(UVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+}
+
+/// Worms come from the chunks all around, every chunk following the paths of the ones reaching it
+/// so that tunnels go on seamlessly from a chunk to the next.
+fn carve_worms(carver: &mut Carver, seed: i32, settings: &CaveSettings) {
+    let size = Vec3::new(CHUNK_LENGTH as f32, CHUNK_HEIGHT as f32, CHUNK_LENGTH as f32);
+    let chunk_min = carver.key.as_vec3();
+    let reach = settings.worm_length as f32 * WORM_STEP + settings.worm_radius.end;
+    let reach_chunks = (Vec3::splat(reach) / size).ceil().as_ivec3();
+
+    for dz in -reach_chunks.z..=reach_chunks.z {
+        for dy in -reach_chunks.y..=reach_chunks.y {
+            for dx in -reach_chunks.x..=reach_chunks.x {
+                let origin = carver.key + IVec3::new(dx, dy, dz) * size.as_ivec3();
+                for worm in Worm::spawn(origin, seed, settings) {
+                    for (center, radius) in worm.path() {
+                        let min = (center - radius - chunk_min).floor().max(Vec3::ZERO);
+                        let max = (center + radius - chunk_min).ceil().min(size);
+                        if min.cmpge(max).any() {
+                            continue;
+                        }
+                        let (min, max) = (UVec3::from(min.as_uvec3().to_array()), UVec3::from(max.as_uvec3().to_array()));
+                        carve_sphere(carver, center - chunk_min, radius, min, max, worm.breach);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Carves the voxels of a sphere whose center is relative to the chunk, within the part of its bounds in the chunk.
+fn carve_sphere(carver: &mut Carver, center: Vec3, radius: f32, min: UVec3, max: UVec3, breach: bool) {
+    for z in min.z..max.z {
+        for x in min.x..max.x {
+            let carvable = carver.carvable(x, z, breach);
+            for y in min.y.max(carvable.start)..max.y.min(carvable.end) {
+                let voxel_center = Vec3::new(x as f32, y as f32, z as f32) + 0.5;
+                if voxel_center.distance_squared(center) <= radius * radius {
+                    carver.carve(UVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+}
+
+/// A tunnel dug by a sphere travelling along a path steered by 1D Perlin noise.
+struct Worm {
+    start: Vec3,
+    yaw: f32,
+    pitch: f32,
+    radius: f32,
+    length: u32,
+    seed: u32,
+    /// Whether the worm digs up to the surface.
+    breach: bool,
+}
+
+impl Worm {
+    /// The worms starting in the chunk with the specified key, always the same for a given seed.
+    fn spawn(chunk_key: IVec3, seed: i32, settings: &CaveSettings) -> Vec<Self> {
+        let mut rng = WormRng::new(chunk_key, seed.wrapping_add(WORM_SEED_OFFSET));
+        let count = settings.worms_per_chunk.floor() as u32 + u32::from(rng.next() < settings.worms_per_chunk.fract());
+        (0..count)
+            .map(|_| {
+                let start = Vec3::new(rng.next(), rng.next(), rng.next())
+                    * Vec3::new(CHUNK_LENGTH as f32, CHUNK_HEIGHT as f32, CHUNK_LENGTH as f32);
+                let radius = &settings.worm_radius;
+                Self {
+                    start: chunk_key.as_vec3() + start,
+                    yaw: rng.next() * TAU,
+                    pitch: (rng.next() - 0.5) * WORM_MAX_PITCH,
+                    radius: radius.start + (radius.end - radius.start) * rng.next(),
+                    // half of the worms are shorter than the longest ones.
+                    length: ((0.5 + rng.next() * 0.5) * settings.worm_length as f32 / WORM_STEP) as u32,
+                    seed: rng.next_u32(),
+                    breach: rng.next() < settings.surface_breach_chance,
+                }
+            })
+            .collect()
+    }
+
+    /// Centers and radii of the spheres digging the worm, one per step.
+    fn path(&self) -> impl Iterator<Item = (Vec3, f32)> + '_ {
+        let mut position = self.start;
+        (0..self.length).map(move |step| {
+            let t = step as f32 * WORM_TURN_RATE;
+            let yaw = self.yaw + perlin_1d(t, self.seed) * PI;
+            let pitch = (self.pitch + perlin_1d(t, self.seed ^ 0x68e3_1da4) * WORM_MAX_PITCH)
+                .clamp(-WORM_MAX_PITCH, WORM_MAX_PITCH);
+            position += Vec3::new(yaw.cos() * pitch.cos(), pitch.sin(), yaw.sin() * pitch.cos()) * WORM_STEP;
+
+            let width = 1.0 + 0.3 * perlin_1d(t * 2.0, self.seed ^ 0xb529_7a4d);
+            let taper = ((step.min(self.length - 1 - step) as f32 + 1.0) / WORM_TAPER_STEPS).min(1.0);
+            (position, self.radius * width * taper.sqrt())
+        })
+    }
+}
+
+/// Random numbers of the worms starting in a chunk, the same whichever chunk they're needed for.
+struct WormRng(u64);
+
+impl WormRng {
+    fn new(chunk_key: IVec3, seed: i32) -> Self {
+        let state = [chunk_key.x, chunk_key.y, chunk_key.z].iter().fold(seed as u32 as u64, |state, &value| {
+            (state ^ value as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(31)
+        });
+        Self(state)
+    }
+
+    // splitmix64.
+    fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) >> 32) as u32
+    }
+
+    /// Returns a number from 0 to 1.
+    fn next(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -5,14 +5,14 @@ use crate::voxel::{
     material::VoxelMaterial,
     materials::{Bedrock, Rock},
     sdf,
-    storage::VoxelBuffer,
+    storage::SectionedVoxelBuffer,
     ChunkShape, Voxel, CHUNK_LENGTH, CHUNK_LENGTH_U, CHUNK_HEIGHT,
 };
 
 use super::noise::Heightmap;
 
 /// Generate the world bottom border for a chunk.
-pub fn terrain_generate_world_bottom_border(buffer: &mut VoxelBuffer<Voxel, ChunkShape>) {
+pub fn terrain_generate_world_bottom_border(buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>) {
     buffer.fill_extent(
         Extent::from_min_and_shape(UVec3::ZERO, UVec3::new(CHUNK_LENGTH, 1, CHUNK_LENGTH)),
         Bedrock::into_voxel(),
@@ -20,8 +20,10 @@ pub fn terrain_generate_world_bottom_border(buffer: &mut VoxelBuffer<Voxel, Chun
 }
 
 /// Carve the general terrain shape for a chunk.
+/// `heightmap` holds the absolute surface height of each column, chunks being stacked vertically only the part of
+/// the columns inside this one is carved.
 pub fn terrain_carve_heightmap(
-    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     key: IVec3,
     heightmap: &Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
 ) {
@@ -33,33 +35,46 @@ pub fn terrain_carve_heightmap(
     //     );
     // }
 
-    // carve the terrain.
-    Extent::from_min_and_shape(UVec2::ZERO, UVec2::new(CHUNK_LENGTH, CHUNK_LENGTH))
+    let local_heights: Vec<(UVec2, u32)> = Extent::from_min_and_shape(UVec2::ZERO, UVec2::new(CHUNK_LENGTH, CHUNK_LENGTH))
         .iter2()
-        .for_each(|pos| {
-            let local_height = heightmap
-                .get(pos.into())
-                .checked_sub(key.y as u32)
-                .unwrap_or_default()
-                .min(CHUNK_HEIGHT);
-                //.min(CHUNK_LENGTH);
+        .map(|pos| {
+            // heights can be under zero, `Heightmap::get` would saturate them.
+            let height = heightmap.getf(pos.into()).round() as i32;
+            (pos, (height - key.y).clamp(0, CHUNK_HEIGHT as i32) as u32)
+        })
+        .collect();
 
-            for h in 0..local_height {
-                // if (pos.x == 0 || pos.x == local_height) || (pos.y == 0 || pos.y == local_height) {
-                //     *buffer.voxel_at_mut([pos.x, h, pos.y].into()) = Water::into_voxel();
-                // } else {
-                    *buffer.voxel_at_mut([pos.x, h, pos.y].into()) = Rock::into_voxel();
-                // }
-            }
-        });
+    // everything under the lowest column is solid, filling it at once keeps the sections it fully covers uniform.
+    let min_local_height = local_heights.iter().map(|(_, h)| *h).min().unwrap_or_default();
+    buffer.fill_extent(
+        Extent::from_min_and_shape(UVec3::ZERO, UVec3::new(CHUNK_LENGTH, min_local_height, CHUNK_LENGTH)),
+        Rock::into_voxel(),
+    );
+
+    // carve the terrain.
+    local_heights.into_iter().for_each(|(pos, local_height)| {
+        for h in min_local_height..local_height {
+            buffer.set_voxel([pos.x, h, pos.y].into(), Rock::into_voxel());
+        }
+    });
+}
+
+/// Returns the voxels of the chunk within `radius` of a vertical axis going `height` voxels up from `origin`,
+/// so decorations don't have to be checked against the whole chunk.
+pub fn decoration_extent(origin: UVec3, radius: u32, height: u32) -> Extent<UVec3> {
+    let min = UVec3::new(origin.x.saturating_sub(radius), origin.y, origin.z.saturating_sub(radius));
+    let lub = (origin + UVec3::new(radius + 1, height, radius + 1))
+        .min(UVec3::new(CHUNK_LENGTH, CHUNK_HEIGHT, CHUNK_LENGTH));
+    Extent::from_min_and_lub(min, lub)
 }
 
 pub fn make_pine_tree<T: VoxelMaterial, L: VoxelMaterial>(
-    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     origin: UVec3,
 ) {
+    let extent = decoration_extent(origin, 8, 24);
     let origin = Vec3::from(origin.as_vec3().to_array());
-    Extent::from_min_and_shape(UVec3::ZERO, UVec3::new(CHUNK_LENGTH, CHUNK_HEIGHT, CHUNK_LENGTH)) //may want to calculate an extent encompassing the tree instead of iterating over the complete volume
+    extent
         .iter3()
         .map(|x| Vec3::from_array(x.as_vec3().to_array()))
         .map(|position| {
@@ -89,11 +104,12 @@ pub fn make_pine_tree<T: VoxelMaterial, L: VoxelMaterial>(
 
 /// Make a tree using SDF functions
 pub fn make_tree<T: VoxelMaterial, L: VoxelMaterial>(
-    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     origin: UVec3,
 ) {
+    let extent = decoration_extent(origin, 6, 21);
     let origin = Vec3::from(origin.as_vec3().to_array());
-    Extent::from_min_and_shape(UVec3::ZERO, UVec3::new(CHUNK_LENGTH, CHUNK_HEIGHT, CHUNK_LENGTH)) //may want to calculate an extent encompassing the tree instead of iterating over the complete 32^3 volume
+    extent
         .iter3()
         .map(|x| Vec3::from_array(x.as_vec3().to_array()))
         .map(|position| {
@@ -121,7 +137,7 @@ pub fn make_tree<T: VoxelMaterial, L: VoxelMaterial>(
 }
 
 pub fn make_rock<V: VoxelMaterial>(
-    buffer: &mut VoxelBuffer<Voxel, ChunkShape>,
+    buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
     origin: UVec3,
     size: f32,
 ) {
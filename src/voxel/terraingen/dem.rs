@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use bevy::math::{IVec3, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::voxel::{CHUNK_LENGTH, CHUNK_LENGTH_U};
+
+/// Settings for taking the terrain surface from an elevation image instead of noise.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DemSettings {
+    /// A grayscale PNG (16-bit, 8-bit images being widened) or a raw `.r16` file of little endian samples.
+    pub path: PathBuf,
+    /// Width of a pixel, in voxels.
+    pub horizontal_scale: f32,
+    /// Height of one sample unit, in voxels. Use `range / 65535.0` to map the full range of a 16-bit image to `range`.
+    pub vertical_scale: f32,
+    /// World position of the first pixel, its y being the height of samples equal to zero.
+    pub origin: IVec3,
+    /// Terrain factor of the columns, from 0 to 1, elevation images having no ruggedness of their own.
+    /// It decides how rugged the biomes make the surface look.
+    pub terrain_factor: f32,
+}
+
+/// A digital elevation model, sampled to give the surface height of the terrain columns.
+/// Columns past the borders of the image take the height of the nearest edge.
+pub struct DemHeightmap {
+    width: u32,
+    depth: u32,
+    samples: Vec<u16>,
+    horizontal_scale: f32,
+    vertical_scale: f32,
+    origin: IVec3,
+    terrain_factor: f32,
+}
+
+impl DemHeightmap {
+    /// Loads the elevation image of the settings, the format being picked from the file extension.
+    pub fn load(settings: &DemSettings) -> Result<Self> {
+        let extension = settings
+            .path
+            .extension()
+            .and_then(|x| x.to_str())
+            .map(|x| x.to_ascii_lowercase());
+
+        let (width, depth, samples) = match extension.as_deref() {
+            Some("png") => read_png(&settings.path)?,
+            Some("r16") | Some("raw") => read_r16(&settings.path)?,
+            _ => bail!("unsupported heightmap format {:?}, expected a .png or .r16 file", settings.path),
+        };
+
+        Self::from_samples(width, depth, samples, settings)
+    }
+
+    /// Makes a heightmap from `width` x `depth` samples stored row by row along x.
+    pub fn from_samples(width: u32, depth: u32, samples: Vec<u16>, settings: &DemSettings) -> Result<Self> {
+        if width == 0 || depth == 0 || (width as usize).checked_mul(depth as usize) != Some(samples.len()) {
+            bail!("a {}x{} heightmap can't hold {} samples", width, depth, samples.len());
+        }
+        if settings.horizontal_scale <= 0.0 {
+            bail!("the horizontal scale must be positive, got {}", settings.horizontal_scale);
+        }
+        if !(0.0..=1.0).contains(&settings.terrain_factor) {
+            bail!("the terrain factor must be between 0 and 1, got {}", settings.terrain_factor);
+        }
+
+        Ok(Self {
+            width,
+            depth,
+            samples,
+            horizontal_scale: settings.horizontal_scale,
+            vertical_scale: settings.vertical_scale,
+            origin: settings.origin,
+            terrain_factor: settings.terrain_factor,
+        })
+    }
+
+    pub fn terrain_factor(&self) -> f32 {
+        self.terrain_factor
+    }
+
+    /// Returns the surface height at the specified world column, interpolated between the surrounding pixels.
+    pub fn height_at(&self, x: i32, z: i32) -> f32 {
+        let max = Vec2::new((self.width - 1) as f32, (self.depth - 1) as f32);
+        let pixel = (Vec2::new((x - self.origin.x) as f32, (z - self.origin.z) as f32) / self.horizontal_scale)
+            .clamp(Vec2::ZERO, max);
+
+        let min = pixel.floor();
+        let t = pixel - min;
+        let sample = |x: f32, z: f32| {
+            let [x, z] = [x.min(max.x) as u32, z.min(max.y) as u32];
+            self.samples[(z * self.width + x) as usize] as f32
+        };
+
+        let top = sample(min.x, min.y) * (1.0 - t.x) + sample(min.x + 1.0, min.y) * t.x;
+        let bottom = sample(min.x, min.y + 1.0) * (1.0 - t.x) + sample(min.x + 1.0, min.y + 1.0) * t.x;
+        self.origin.y as f32 + (top * (1.0 - t.y) + bottom * t.y) * self.vertical_scale
+    }
+
+    /// Returns the surface height of each column of a chunk, laid out for a [`super::noise::Heightmap`].
+    pub fn chunk_heights(&self, key: IVec3) -> Vec<f32> {
+        let mut heights = Vec::with_capacity(CHUNK_LENGTH_U * CHUNK_LENGTH_U);
+        for z in 0..CHUNK_LENGTH as i32 {
+            for x in 0..CHUNK_LENGTH as i32 {
+                heights.push(self.height_at(key.x + x, key.z + z));
+            }
+        }
+        heights
+    }
+}
+
+fn read_png(path: &Path) -> Result<(u32, u32, Vec<u16>)> {
+    let image = image::open(path)?.into_luma16();
+    Ok((image.width(), image.height(), image.into_raw()))
+}
+
+/// `.r16` files have no header, they are expected to be square.
+fn read_r16(path: &Path) -> Result<(u32, u32, Vec<u16>)> {
+    let bytes = std::fs::read(path)?;
+    let count = bytes.len() / 2;
+    let width = (count as f64).sqrt().round() as usize;
+    if bytes.len() % 2 != 0 || width.checked_mul(width) != Some(count) {
+        bail!("{:?} isn't a square heightmap of 16-bit samples", path);
+    }
+
+    let samples = bytes
+        .chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .collect();
+    let width = u32::try_from(width).map_err(|_| anyhow!("{:?} is too large", path))?;
+    Ok((width, width, samples))
+}
@@ -0,0 +1,136 @@
+use anyhow::{bail, Result};
+use bevy::math::IVec3;
+use ilattice::{glam::UVec3, prelude::Extent};
+use serde::{Deserialize, Serialize};
+
+use crate::voxel::{
+    material::VoxelMaterial, materials::Rock, storage::SectionedVoxelBuffer, ChunkShape, Voxel, CHUNK_HEIGHT,
+    CHUNK_LENGTH, CHUNK_LENGTH_U,
+};
+
+use super::noise::{Heightmap, NoiseGrid};
+
+const DENSITY_SEED_OFFSET: i32 = 32_452_843;
+
+/// Settings of the 3D density terrain.
+///
+/// The density of a voxel is its depth under the surface of its column plus 3D noise, the voxel being solid
+/// where it's positive. The noise goes up to `noise_height` voxels for a terrain factor of 1, so rugged terrain
+/// gets cliffs, arches and overhangs while flat terrain barely changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DensitySettings {
+    pub frequency: f32,
+    pub octaves: u8,
+    pub noise_height: f32,
+    /// Noise is sampled at the corners of cells this many voxels wide and interpolated in between,
+    /// 1 sampling it at every voxel. Must divide [`CHUNK_LENGTH`].
+    pub cell_size: u32,
+}
+
+impl DensitySettings {
+    /// Checks that the settings can carve terrain, e.g. once deserialized.
+    pub fn check(&self) -> Result<()> {
+        if self.cell_size == 0 || !CHUNK_LENGTH.is_multiple_of(self.cell_size) {
+            bail!("density cells must divide chunks of {} voxels, got a size of {}", CHUNK_LENGTH, self.cell_size);
+        }
+        if !self.frequency.is_finite() || !self.noise_height.is_finite() || self.noise_height < 0.0 {
+            bail!("invalid density frequency {} or noise height {}", self.frequency, self.noise_height);
+        }
+        Ok(())
+    }
+}
+
+impl Default for DensitySettings {
+    fn default() -> Self {
+        Self {
+            frequency: 0.015,
+            octaves: 4,
+            noise_height: 40.0,
+            cell_size: 4,
+        }
+    }
+}
+
+/// Carve the terrain of a chunk from the density function. `heightmap` holds the absolute surface height of each
+/// column and `factors` their terrain factor, laid out the same way.
+///
+/// Returns the height of the voxel above the topmost solid voxel of each column, which is where its surface
+/// actually lies. Columns solid up to the top of the chunk get their surface above it, and empty ones under it.
+pub fn terrain_carve_density(
+    buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>,
+    key: IVec3,
+    seed: i32,
+    heightmap: &Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
+    factors: &[f32],
+    settings: &DensitySettings,
+) -> Vec<f32> {
+    debug_assert!(settings.check().is_ok(), "density settings must be checked before carving");
+
+    // heights relative to the chunk and how far from them noise reaches.
+    let columns: Vec<(i32, f32)> = factors
+        .iter()
+        .enumerate()
+        .map(|(i, factor)| {
+            let height = heightmap.getf([(i % CHUNK_LENGTH_U) as u32, (i / CHUNK_LENGTH_U) as u32]).round() as i32;
+            (height - key.y, factor * settings.noise_height)
+        })
+        .collect();
+
+    // noise can't make anything solid above this band nor empty under it.
+    let band_min = columns.iter().map(|(height, reach)| height - reach.ceil() as i32 - 1).min().unwrap_or(0);
+    let band_max = columns.iter().map(|(height, reach)| height + reach.ceil() as i32 + 1).max().unwrap_or(0);
+    let band_min = band_min.clamp(0, CHUNK_HEIGHT as i32) as u32;
+    let band_max = band_max.clamp(0, CHUNK_HEIGHT as i32) as u32;
+
+    // everything under the band is solid, filling it at once keeps the sections it fully covers uniform.
+    buffer.fill_extent(
+        Extent::from_min_and_shape(UVec3::ZERO, UVec3::new(CHUNK_LENGTH, band_min, CHUNK_LENGTH)),
+        Rock::into_voxel(),
+    );
+    if band_min >= band_max {
+        return columns.iter().map(|(height, _)| surface_height(key, *height, band_min)).collect();
+    }
+
+    let cell_size = settings.cell_size;
+    let grid_min_y = band_min / cell_size * cell_size;
+    let grid_max_y = band_max.div_ceil(cell_size) * cell_size;
+    let grid = NoiseGrid::sample(
+        IVec3::new(key.x, key.y + grid_min_y as i32, key.z),
+        UVec3::new(CHUNK_LENGTH, grid_max_y - grid_min_y, CHUNK_LENGTH),
+        cell_size,
+        settings.frequency,
+        settings.octaves,
+        seed.wrapping_add(DENSITY_SEED_OFFSET),
+    );
+
+    let mut surface_heights = Vec::with_capacity(columns.len());
+    for z in 0..CHUNK_LENGTH {
+        for x in 0..CHUNK_LENGTH {
+            let (height, reach) = columns[(z * CHUNK_LENGTH + x) as usize];
+            let mut column_top = band_min;
+            for y in (band_min..band_max).rev() {
+                // without noise, solid voxels are exactly the ones a heightmap fills.
+                let density = (height - y as i32) as f32 - 0.5 + grid.get(UVec3::new(x, y - grid_min_y, z)) * reach;
+                if density > 0.0 {
+                    buffer.set_voxel(UVec3::new(x, y, z), Rock::into_voxel());
+                    column_top = column_top.max(y + 1);
+                }
+            }
+            surface_heights.push(surface_height(key, height, column_top));
+        }
+    }
+    surface_heights
+}
+
+/// Absolute surface height of a column whose voxels are solid up to `column_top`, `height` being its height
+/// without noise relative to the chunk.
+fn surface_height(key: IVec3, height: i32, column_top: u32) -> f32 {
+    let local_height = match column_top {
+        // the surface is somewhere above, or nowhere near when this chunk is deep underground.
+        CHUNK_HEIGHT => height.max(CHUNK_HEIGHT as i32),
+        // decorations need ground to stand on, nothing of this column is in the chunk.
+        0 => height.min(-1),
+        _ => column_top as i32,
+    };
+    (key.y + local_height) as f32
+}
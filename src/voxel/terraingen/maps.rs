@@ -0,0 +1,222 @@
+use std::{fmt::Write as _, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail, Result};
+use bevy::math::{IVec2, IVec3, UVec2};
+use directories::BaseDirs;
+use image::{ImageBuffer, Luma, Rgb};
+
+use super::{
+    noise::{
+        get_chunk_continentalness, get_chunk_erosion, get_chunk_humidity, get_chunk_peaks_valleys,
+        get_chunk_temperature,
+    },
+    TerrainGenerator, TERRAIN_GENERATOR,
+};
+use crate::voxel::{CHUNK_LENGTH, CHUNK_LENGTH_U};
+
+/// Maps can't be larger than this along any axis, a map that size already takes a few hundred megabytes to sample.
+const MAX_MAP_SIZE: u32 = 2048;
+/// Color of the columns without a biome on biome maps.
+const NO_BIOME_COLOR: [u8; 3] = [0, 0, 0];
+
+/// The noise layers of the terrain generator and their outcome over an XZ rectangle,
+/// one value per column stored row by row along x.
+pub struct TerrainMaps {
+    pub seed: i32,
+    pub min: IVec2,
+    pub size: UVec2,
+    pub surface_height: Vec<f32>,
+    pub continentalness: Vec<f32>,
+    pub erosion: Vec<f32>,
+    pub peaks_valleys: Vec<f32>,
+    pub humidity: Vec<f32>,
+    pub temperature: Vec<f32>,
+    /// Name and map color of the biome of each column, if any.
+    pub biomes: Vec<Option<(&'static str, [u8; 3])>>,
+}
+
+impl TerrainMaps {
+    /// Samples the generator over a rectangle without generating any voxel.
+    /// Noise is sampled one chunk at a time so values are exactly the ones used when generating chunks.
+    pub fn sample(generator: &TerrainGenerator, seed: i32, min: IVec2, size: UVec2) -> Result<Self> {
+        if size.cmpeq(UVec2::ZERO).any() || size.cmpgt(UVec2::splat(MAX_MAP_SIZE)).any() {
+            bail!("map size must be between 1 and {} along each axis, got {}", MAX_MAP_SIZE, size);
+        }
+
+        let len = (size.x * size.y) as usize;
+        let mut maps = Self {
+            seed,
+            min,
+            size,
+            surface_height: vec![0.0; len],
+            continentalness: vec![0.0; len],
+            erosion: vec![0.0; len],
+            peaks_valleys: vec![0.0; len],
+            humidity: vec![0.0; len],
+            temperature: vec![0.0; len],
+            biomes: vec![None; len],
+        };
+
+        let chunk_length = CHUNK_LENGTH as i32;
+        let max = min + size.as_ivec2();
+        let first_chunk = IVec2::new(min.x.div_euclid(chunk_length), min.y.div_euclid(chunk_length)) * chunk_length;
+
+        for chunk_z in (first_chunk.y..max.y).step_by(CHUNK_LENGTH_U) {
+            for chunk_x in (first_chunk.x..max.x).step_by(CHUNK_LENGTH_U) {
+                let key = IVec3::new(chunk_x, 0, chunk_z);
+                let continentalness = get_chunk_continentalness(key, CHUNK_LENGTH_U, seed);
+                let erosion = get_chunk_erosion(key, CHUNK_LENGTH_U, seed);
+                let peaks_valleys = get_chunk_peaks_valleys(key, CHUNK_LENGTH_U, seed);
+                let humidity = get_chunk_humidity(key, CHUNK_LENGTH_U, seed);
+                let temperature = get_chunk_temperature(key, CHUNK_LENGTH_U, seed);
+                let surface_heights = generator.surface_heights(key, seed);
+                let biomes = generator.chunk_biomes(key, seed);
+
+                for local_z in 0..chunk_length {
+                    for local_x in 0..chunk_length {
+                        let position = IVec2::new(chunk_x + local_x, chunk_z + local_z) - min;
+                        if position.cmplt(IVec2::ZERO).any() || position.cmpge(size.as_ivec2()).any() {
+                            continue;
+                        }
+
+                        let local = (local_z * chunk_length + local_x) as usize;
+                        let index = (position.y * size.x as i32 + position.x) as usize;
+                        maps.continentalness[index] = continentalness[local];
+                        maps.erosion[index] = erosion[local];
+                        maps.peaks_valleys[index] = peaks_valleys[local];
+                        maps.humidity[index] = humidity[local];
+                        maps.temperature[index] = temperature[local];
+                        maps.surface_height[index] = surface_heights[local];
+                        maps.biomes[index] = biomes[local].map(|biome| (biome.name(), biome.map_color()));
+                    }
+                }
+            }
+        }
+
+        Ok(maps)
+    }
+
+    /// Writes a PNG per layer in a directory, with x going right and z going down.
+    /// Layers are 16-bit grayscale images spanning the range of their values, which are listed in `legend.txt`
+    /// along the biome colors.
+    pub fn write_pngs(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut legend = String::new();
+        writeln!(legend, "seed: {}", self.seed)?;
+        writeln!(legend, "x: {} .. {}", self.min.x, self.min.x + self.size.x as i32)?;
+        writeln!(legend, "z: {} .. {}", self.min.y, self.min.y + self.size.y as i32)?;
+        writeln!(legend)?;
+
+        for (name, values) in [
+            ("surface_height", &self.surface_height),
+            ("continentalness", &self.continentalness),
+            ("erosion", &self.erosion),
+            ("peaks_valleys", &self.peaks_valleys),
+            ("humidity", &self.humidity),
+            ("temperature", &self.temperature),
+        ] {
+            let (min, max) = write_grayscale(&dir.join(format!("{}.png", name)), self.size, values)?;
+            writeln!(legend, "{}: black = {}, white = {}", name, min, max)?;
+        }
+
+        let mut biomes: Vec<(&str, [u8; 3])> = self.biomes.iter().flatten().copied().collect();
+        biomes.sort_unstable();
+        biomes.dedup();
+
+        let image = ImageBuffer::from_fn(self.size.x, self.size.y, |x, z| {
+            let biome = self.biomes[(z * self.size.x + x) as usize];
+            Rgb(biome.map_or(NO_BIOME_COLOR, |(_, color)| color))
+        });
+        image.save(dir.join("biomes.png"))?;
+
+        writeln!(legend)?;
+        writeln!(legend, "biomes:")?;
+        writeln!(legend, "  #{} none", hex_color(NO_BIOME_COLOR))?;
+        for (name, color) in biomes {
+            writeln!(legend, "  #{} {}", hex_color(color), name)?;
+        }
+
+        std::fs::write(dir.join("legend.txt"), legend)?;
+        Ok(())
+    }
+}
+
+/// Options of the `export-maps` command.
+pub struct MapExportOptions {
+    pub seed: i32,
+    pub min: IVec2,
+    pub size: UVec2,
+    /// Defaults to a directory per seed in the data directory.
+    pub output_dir: Option<PathBuf>,
+}
+
+impl Default for MapExportOptions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            min: IVec2::splat(-1024),
+            size: UVec2::splat(2048),
+            output_dir: None,
+        }
+    }
+}
+
+impl MapExportOptions {
+    /// Parses the `--seed`, `--x`, `--z`, `--width`, `--depth` and `--out` arguments, all of them being optional.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| anyhow!("missing value for {}", arg))?;
+            let parse_error = |_| anyhow!("invalid value {} for {}", value, arg);
+            match arg.as_str() {
+                "--seed" => options.seed = value.parse().map_err(parse_error)?,
+                "--x" => options.min.x = value.parse().map_err(parse_error)?,
+                "--z" => options.min.y = value.parse().map_err(parse_error)?,
+                "--width" => options.size.x = value.parse().map_err(parse_error)?,
+                "--depth" => options.size.y = value.parse().map_err(parse_error)?,
+                "--out" => options.output_dir = Some(value.into()),
+                _ => bail!("unknown argument {}", arg),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Samples the generator with the specified options and writes the maps, returning the directory they were written to.
+pub fn export_maps(options: &MapExportOptions) -> Result<PathBuf> {
+    let output_dir = match &options.output_dir {
+        Some(dir) => dir.clone(),
+        None => match BaseDirs::new() {
+            Some(base_dirs) => base_dirs
+                .data_dir()
+                .join(".yavafg")
+                .join("maps")
+                .join(format!("seed_{}", options.seed)),
+            None => bail!("No valid directory path could be retrieved from the operating system."),
+        },
+    };
+
+    let generator = TERRAIN_GENERATOR.read().unwrap();
+    TerrainMaps::sample(&generator, options.seed, options.min, options.size)?.write_pngs(&output_dir)?;
+    Ok(output_dir)
+}
+
+/// Writes values as a 16-bit grayscale image spanning their range, returning that range.
+fn write_grayscale(path: &Path, size: UVec2, values: &[f32]) -> Result<(f32, f32)> {
+    let min = values.iter().copied().fold(f32::MAX, f32::min);
+    let max = values.iter().copied().fold(f32::MIN, f32::max);
+    let range = (max - min).max(f32::EPSILON);
+
+    let image: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_fn(size.x, size.y, |x, z| {
+        let value = values[(z * size.x + x) as usize];
+        Luma([((value - min) / range * u16::MAX as f32).round() as u16])
+    });
+    image.save(path)?;
+    Ok((min, max))
+}
+
+fn hex_color(color: [u8; 3]) -> String {
+    format!("{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
@@ -1,129 +1,327 @@
-use float_ord::FloatOrd;
-use ilattice::prelude::{Extent, UVec2};
-use std::{collections::BTreeMap, sync::RwLock };
+use std::{ops::Range, sync::RwLock};
 
+use anyhow::Result;
 use bevy::{
-    math::IVec3,
-    prelude::Plugin, log::info,
+    math::{IVec3, Vec2},
+    prelude::Plugin,
 };
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
 use self::{
-    biomes::{BiomeTerrainGenerator, IntoBoxedTerrainGenerator},
-    common::terrain_generate_world_bottom_border,
-    noise::{Heightmap, get_chunk_continentalness, get_chunk_erosion, get_chunk_peaks_valleys},
+    biomes::{BiomeTerrainGenerator, IntoBoxedTerrainGenerator, SurfaceShape},
+    blending::{column_roll, BiomeBlend},
+    caves::{terrain_carve_caves, CaveSettings},
+    common::{terrain_carve_heightmap, terrain_generate_world_bottom_border},
+    dem::DemHeightmap,
+    density::{terrain_carve_density, DensitySettings},
+    noise::{get_chunk_continentalness, get_chunk_erosion, get_chunk_peaks_valleys, Heightmap},
+    spline::{TerrainParams, TerrainSplines, RELIEF_HEIGHT},
 };
 
-use super::{storage::VoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH_U, CHUNK_LENGTH, materials::Rock, material::VoxelMaterial };
+use super::{storage::SectionedVoxelBuffer, ChunkShape, Voxel, CHUNK_LENGTH_U };
 
 pub mod biomes;
 
+/// Mixing of the biomes along their borders.
+pub mod blending;
+
 /// noise functions ported over from C / GLSL code
 pub mod noise;
 
 /// common functions used by all terrain generators
 pub mod common;
 
+/// Offline previews of the generator output, as images.
+pub mod maps;
+
+/// Terrain surfaces taken from elevation images.
+pub mod dem;
+
+/// Splines shaping the terrain from the noise layers.
+pub mod spline;
+
+/// Terrain filled from a 3D density function.
+pub mod density;
+
+/// Caves dug under the surface.
+pub mod caves;
+
+/// Identifies this generator in world metadata.
+pub const GENERATOR_ID: &str = "yavafg";
+
+/// Version of the terrain generated for a given seed, to bump whenever the generator output changes:
+/// chunks saved as a difference with the generated terrain only make sense over the version they were compared to.
+pub const GENERATOR_VERSION: u16 = 5;
+
 // Terrain generator singleton.
 pub static TERRAIN_GENERATOR: Lazy<RwLock<TerrainGenerator>> = Lazy::new(Default::default);
 
+/// Height of the terrain surface where the terrain splines give no offset.
+pub const BASE_SURFACE_LEVEL: i32 = 64;
+
+/// Where the height of the terrain surface comes from.
 #[derive(Default)]
+pub enum SurfaceSource {
+    /// Continentalness, erosion and peaks / valleys noise layers.
+    #[default]
+    Noise,
+    /// An elevation image.
+    Dem(DemHeightmap),
+}
+
+/// How the terrain under the surface gets filled.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TerrainMode {
+    /// Columns are solid up to the surface height.
+    #[default]
+    Heightmap,
+    /// Voxels are solid where a density function is positive, which gives cliffs, arches and overhangs.
+    Density(DensitySettings),
+}
+
+/// The humidity and temperature a biome grows in, both going from 0 to 1.
+#[derive(Clone, Debug)]
+pub struct ClimateRange {
+    pub humidity: Range<f32>,
+    pub temperature: Range<f32>,
+}
+
+impl ClimateRange {
+    pub fn new(humidity: Range<f32>, temperature: Range<f32>) -> Self {
+        Self { humidity, temperature }
+    }
+
+    pub fn contains(&self, humidity: f32, temperature: f32) -> bool {
+        self.humidity.contains(&humidity) && self.temperature.contains(&temperature)
+    }
+
+    /// Distance from a climate to the center of the range.
+    fn distance_to(&self, humidity: f32, temperature: f32) -> f32 {
+        let center = |range: &Range<f32>| (range.start + range.end) / 2.0;
+        Vec2::new(humidity - center(&self.humidity), temperature - center(&self.temperature)).length()
+    }
+}
+
 pub struct TerrainGenerator {
-    biomes_map: BTreeMap<FloatOrd<f32>, Box<dyn BiomeTerrainGenerator>>,
-    biome_list: Vec<Box<dyn BiomeTerrainGenerator>>,
+    biomes: Vec<(ClimateRange, Box<dyn BiomeTerrainGenerator>)>,
+    surface_source: SurfaceSource,
+    splines: TerrainSplines,
+    mode: TerrainMode,
+    caves: Option<CaveSettings>,
 }
 
-const BIOME_INVSCALE: f32 = 0.005;
+/// The generator comes with the built-in biomes, the terrain of a seed must not depend on who generates it:
+/// chunks saved as deltas are compared to it.
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        let mut generator = Self {
+            biomes: Vec::new(),
+            surface_source: SurfaceSource::default(),
+            splines: TerrainSplines::default(),
+            mode: TerrainMode::default(),
+            caves: Some(CaveSettings::default()),
+        };
+        generator
+            .register_biome(
+                ClimateRange::new(0.0..1.0, 0.0..0.35),
+                biomes::BasicSnowyPlainsBiomeTerrainGenerator.into_boxed_generator(),
+            )
+            .register_biome(
+                ClimateRange::new(0.0..0.5, 0.6..1.0),
+                biomes::BasicDesertBiomeTerrainGenerator.into_boxed_generator(),
+            )
+            .register_biome(
+                ClimateRange::new(0.0..1.0, 0.35..0.6),
+                biomes::BasicPlainsBiomeTerrainGenerator.into_boxed_generator(),
+            );
+        generator
+    }
+}
 
 impl TerrainGenerator {
-    pub fn register_biome_generator(
-        &mut self,
-        chance: f32,
-        biome: Box<dyn BiomeTerrainGenerator>,
-    ) -> &mut Self {
-        self.biomes_map.insert(FloatOrd(chance), biome);
+    /// Registers a biome growing in the specified climate. Where ranges overlap, the biome registered first wins.
+    pub fn register_biome(&mut self, climate: ClimateRange, biome: Box<dyn BiomeTerrainGenerator>) -> &mut Self {
+        self.biomes.push((climate, biome));
         self
     }
 
-    pub fn register_biome(
-        &mut self,
-        biome: Box<dyn BiomeTerrainGenerator>,
-    ) -> &mut Self {
-        self.biome_list.push(biome);
+    /// Returns the biome growing in the specified climate, or the biome whose climate range is the closest
+    /// if no range holds it. `None` if no biome is registered.
+    pub fn biome_at(&self, humidity: f32, temperature: f32) -> Option<&dyn BiomeTerrainGenerator> {
+        self.biome_index_at(humidity, temperature)
+            .map(|biome| self.biomes[biome].1.as_ref())
+    }
+
+    fn biome_index_at(&self, humidity: f32, temperature: f32) -> Option<usize> {
+        self.biomes
+            .iter()
+            .position(|(climate, _)| climate.contains(humidity, temperature))
+            .or_else(|| {
+                (0..self.biomes.len()).min_by(|a, b| {
+                    let distance = |biome: &usize| self.biomes[*biome].0.distance_to(humidity, temperature);
+                    distance(a).total_cmp(&distance(b))
+                })
+            })
+    }
+
+    /// Returns how the biomes around each column of the chunk with the specified key weigh on it,
+    /// laid out like [`Self::surface_heights`].
+    pub fn chunk_biome_blends(&self, chunk_key: IVec3, seed: i32) -> Vec<BiomeBlend> {
+        blending::chunk_biome_blends(chunk_key, seed, |humidity, temperature| {
+            self.biome_index_at(humidity, temperature)
+        })
+    }
+
+    /// Returns the biome whose surface materials and decorations each column of the chunk with the specified key
+    /// gets, laid out like [`Self::surface_heights`]. Columns along borders pick one of the biomes they blend,
+    /// each with a chance equal to its weight.
+    pub fn chunk_biomes(&self, chunk_key: IVec3, seed: i32) -> Vec<Option<&dyn BiomeTerrainGenerator>> {
+        self.pick_biomes(chunk_key, seed, &self.chunk_biome_blends(chunk_key, seed))
+    }
+
+    fn pick_biomes(&self, chunk_key: IVec3, seed: i32, blends: &[BiomeBlend]) -> Vec<Option<&dyn BiomeTerrainGenerator>> {
+        blends
+            .iter()
+            .enumerate()
+            .map(|(i, blend)| {
+                let x = chunk_key.x + (i % CHUNK_LENGTH_U) as i32;
+                let z = chunk_key.z + (i / CHUNK_LENGTH_U) as i32;
+                blend
+                    .pick(column_roll(x, z, seed))
+                    .map(|biome| self.biomes[biome].1.as_ref())
+            })
+            .collect()
+    }
+
+    /// Mixes the surface shapes of the biomes of a column.
+    fn surface_shape(&self, blend: &BiomeBlend) -> SurfaceShape {
+        SurfaceShape::blend(
+            blend
+                .weights()
+                .iter()
+                .map(|(biome, weight)| (self.biomes[*biome].1.surface_shape(), *weight)),
+        )
+    }
+
+    /// Sets where the terrain surface comes from, chunks generated from then on use it.
+    pub fn set_surface_source(&mut self, source: SurfaceSource) -> &mut Self {
+        self.surface_source = source;
         self
     }
 
-    //returns the biome with the closest temp / humidity
-    // #[allow(clippy::borrowed_box)]
-    // #[allow(dead_code)]
-    // pub fn biome_at_chunk(&self, chunk_key: IVec3) -> &Box<dyn BiomeTerrainGenerator> {
-    //     let coords = noise::voronoi(chunk_key.xzy().truncate().as_vec2() * BIOME_INVSCALE);
-    //     let p = FloatOrd(noise::rand2to1i(coords));
-
-    //     self.biomes_map
-    //         .range(..=p)
-    //         .last()
-    //         .map_or(self.biomes_map.first_key_value().unwrap().1, |x| x.1)
-    // }
-
-    #[allow(clippy::borrowed_box)]
-    pub fn biome_at_xz(
-        &self,
-        x: i32,
-        z: i32,
-        humidity_map: &Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-        temperature_map: &Heightmap<CHUNK_LENGTH_U, CHUNK_LENGTH_U>,
-    ) -> &Box<dyn BiomeTerrainGenerator> {
-        let humidity = humidity_map.getf([x as u32, z as u32].into());
-        let temperature = temperature_map.getf([x as u32, z as u32].into());
-
-        // info!("humidity: {}, temperature: {}", humidity, temperature);
-
-        match (humidity, temperature) {
-            (x, y) if x > 0.8 && y < 0.2 => &self.biome_list[2],
-            (x, y) if x > 0.8 && y > 0.2 => &self.biome_list[1],
-            _ => &self.biome_list[0],
-        }
+    /// Sets the splines shaping the terrain surface from the noise layers, chunks generated from then on use them.
+    pub fn set_terrain_splines(&mut self, splines: TerrainSplines) -> &mut Self {
+        self.splines = splines;
+        self
+    }
 
-        // let coods = noise::voronoi(Vec2::new(x as f32, y as f32) * BIOME_INVSCALE);
-        // let p = FloatOrd(noise::rand2to1i(coords));
+    pub fn terrain_splines(&self) -> &TerrainSplines {
+        &self.splines
+    }
 
-        // self.biomes_mrap
-        //     .range(..=p)
-        //     .last()
-        //     .map_or(self.biomes_map.first_key_value().unwrap().1, |x| x.1)
+    /// Sets how the terrain under the surface gets filled, chunks generated from then on use it.
+    /// Fails if its settings can't generate terrain, the generator being left as it was.
+    pub fn set_terrain_mode(&mut self, mode: TerrainMode) -> Result<&mut Self> {
+        if let TerrainMode::Density(settings) = &mode {
+            settings.check()?;
+        }
+        self.mode = mode;
+        Ok(self)
     }
 
-    pub fn generate(&self, chunk_key: IVec3, buffer: &mut VoxelBuffer<Voxel, ChunkShape>, seed: i32) {
-        let continentalness_noise = get_chunk_continentalness(chunk_key, CHUNK_LENGTH_U, seed);
-        let erosion_noise = get_chunk_erosion(chunk_key, CHUNK_LENGTH_U, seed);
-        let peaks_valleys_noise = get_chunk_peaks_valleys(chunk_key, CHUNK_LENGTH_U, seed);
+    /// Sets the caves dug under the surface, `None` leaving the ground whole. Chunks generated from then on use them.
+    /// Fails if their settings can't dig caves, the generator being left as it was.
+    pub fn set_caves(&mut self, caves: Option<CaveSettings>) -> Result<&mut Self> {
+        if let Some(settings) = &caves {
+            settings.check()?;
+        }
+        self.caves = caves;
+        Ok(self)
+    }
 
-        let continentalness = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&continentalness_noise);
-        let erosion = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&erosion_noise);
-        let peaks_valleys = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&peaks_valleys_noise);
+    /// Returns the surface height of each column of the chunk with the specified key, laid out for a [`Heightmap`].
+    pub fn surface_heights(&self, chunk_key: IVec3, seed: i32) -> Vec<f32> {
+        self.blended_surface(chunk_key, seed, &self.chunk_biome_blends(chunk_key, seed)).0
+    }
 
-        let mut surface_level = 64;
+    /// Surface heights shaped by the biomes along with the terrain factor of each column,
+    /// elevation images being used as they are.
+    fn blended_surface(&self, chunk_key: IVec3, seed: i32, blends: &[BiomeBlend]) -> (Vec<f32>, Vec<f32>) {
+        match &self.surface_source {
+            SurfaceSource::Noise => {
+                let continentalness = get_chunk_continentalness(chunk_key, CHUNK_LENGTH_U, seed);
+                let erosion = get_chunk_erosion(chunk_key, CHUNK_LENGTH_U, seed);
+                let peaks_valleys = get_chunk_peaks_valleys(chunk_key, CHUNK_LENGTH_U, seed);
 
-        Extent::from_min_and_shape(UVec2::ZERO, UVec2::new(CHUNK_LENGTH, CHUNK_LENGTH))
-            .iter2()
-            .for_each(|pos| {
-                surface_level += ((continentalness.getf(pos.into()) + erosion.getf(pos.into()) + peaks_valleys.getf(pos.into()) ) / 3.0) as i32;
-                // surface_level += erosion.getf(pos.into()) as i32;
+                continentalness
+                    .into_iter()
+                    .zip(erosion)
+                    .zip(peaks_valleys)
+                    .zip(blends)
+                    .map(|(((continentalness, erosion), peaks_valleys), blend)| {
+                        let params = TerrainParams {
+                            continentalness,
+                            erosion,
+                            peaks_valleys,
+                        };
+                        let shape = self.splines.shape(&params);
+                        let relief = shape.factor * peaks_valleys * RELIEF_HEIGHT;
+                        let height = self
+                            .surface_shape(blend)
+                            .apply(BASE_SURFACE_LEVEL as f32 + shape.offset, relief);
+                        (height, shape.factor)
+                    })
+                    .unzip()
+            }
+            SurfaceSource::Dem(dem) => (
+                dem.chunk_heights(chunk_key),
+                vec![dem.terrain_factor(); CHUNK_LENGTH_U * CHUNK_LENGTH_U],
+            ),
+        }
+    }
 
-                for h in 0..surface_level {
-                    *buffer.voxel_at_mut([pos.x, h as u32, pos.y].into()) = Rock::into_voxel();
-                }
-                // for h in surface_level..73 {
-                //     *buffer.voxel_at_mut([pos.x, h as u32, pos.y].into()) = Water::into_voxel();
-                // }
-                surface_level = 64;
-            });
+    /// Generates the chunk with the specified key. `min_height` is the world bottom, which gets a bedrock border.
+    pub fn generate(&self, chunk_key: IVec3, buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>, seed: i32, min_height: i32) {
+        let blends = self.chunk_biome_blends(chunk_key, seed);
+        let (surface_heights, factors) = self.blended_surface(chunk_key, seed, &blends);
+        let heightmap = Heightmap::<CHUNK_LENGTH_U, CHUNK_LENGTH_U>::from_slice(&surface_heights);
 
-        // *buffer.voxel_at_mut([0, 100, 0].into()) = Rock::into_voxel();
+        // with density, biomes dress the surface where it actually ended up.
+        let density_surface_heights = match &self.mode {
+            TerrainMode::Heightmap => {
+                terrain_carve_heightmap(buffer, chunk_key, &heightmap);
+                None
+            }
+            TerrainMode::Density(settings) => Some(terrain_carve_density(
+                buffer, chunk_key, seed, &heightmap, &factors, settings,
+            )),
+        };
+        let heightmap = density_surface_heights
+            .as_deref()
+            .map_or(heightmap, Heightmap::from_slice);
 
+        // every column gets its strata before any decoration, which may spread over the neighbouring columns.
+        let biomes = self.pick_biomes(chunk_key, seed, &blends);
+        let columns = || {
+            biomes.iter().enumerate().filter_map(|(i, biome)| {
+                biome.map(|biome| (biome, (i % CHUNK_LENGTH_U) as u32, (i / CHUNK_LENGTH_U) as u32))
+            })
+        };
+        for (biome, x, z) in columns() {
+            biome.carve_terrain_at_xz(chunk_key, x, z, heightmap, buffer);
+        }
+        // caves go through the strata, and decorations come last not to be dug into.
+        if let Some(caves) = &self.caves {
+            terrain_carve_caves(buffer, chunk_key, seed, min_height, &heightmap, caves);
+        }
+        for (biome, x, z) in columns() {
+            biome.decorate_terrain_at_xz(chunk_key, x, z, heightmap, buffer);
+        }
 
-        terrain_generate_world_bottom_border(buffer);
+        if chunk_key.y == min_height {
+            terrain_generate_world_bottom_border(buffer);
+        }
     }
 }
 
@@ -131,29 +329,7 @@ pub struct TerrainGeneratorPlugin;
 
 impl Plugin for TerrainGeneratorPlugin {
     fn build(&self, _: &mut bevy::prelude::App) {
-        TERRAIN_GENERATOR
-            .write()
-            .unwrap();
-            // .register_biome_generator(
-            //     0.0f32,
-            //     biomes::BasicPlainsBiomeTerrainGenerator.into_boxed_generator(),
-            // )
-            // .register_biome_generator(
-            //     0.8f32,
-            //     biomes::BasicDesertBiomeTerrainGenerator.into_boxed_generator(),
-            // )
-            // .register_biome_generator(
-            //     3.21,
-            //     biomes::BasicSnowyPlainsBiomeTerrainGenerator.into_boxed_generator(),
-            // )
-            // .register_biome(
-            //     biomes::BasicPlainsBiomeTerrainGenerator.into_boxed_generator(),
-            // )
-            // .register_biome(
-            //     biomes::BasicDesertBiomeTerrainGenerator.into_boxed_generator(),
-            // )
-            // .register_biome(
-            //     biomes::BasicSnowyPlainsBiomeTerrainGenerator.into_boxed_generator(),
-            // );
-    }
-}
\ No newline at end of file
+        // sets the biomes up before the first chunk gets generated on a task.
+        Lazy::force(&TERRAIN_GENERATOR);
+    }
+}
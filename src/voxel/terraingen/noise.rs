@@ -1,6 +1,7 @@
 use std::ops::{Add, Mul};
 
 use bevy::math::{IVec3, Vec2, Vec2Swizzles, Vec3, Vec3Swizzles};
+use ilattice::glam::UVec3;
 
 use simdnoise::*;
 
@@ -75,6 +76,7 @@ pub fn voronoi(p: Vec2) -> Vec2 {
     closest_point
 }
 
+/// Erosion of each column of a chunk, from -1 (mountainous) to 1 (flat).
 pub fn get_chunk_erosion(key: IVec3, chunk_len: usize, seed: i32) -> Vec<f32> {
     // Erosion noise
     // let erosion_noise = noise::Fbm::<Perlin>::new(248) //@todo : use random seed
@@ -106,18 +108,14 @@ pub fn get_chunk_erosion(key: IVec3, chunk_len: usize, seed: i32) -> Vec<f32> {
     .with_lacunarity(2.0)
     .with_octaves(6)
     .with_gain(0.5)
-    .with_seed(seed)
+    .with_seed(seed.wrapping_add(EROSION_SEED_OFFSET))
     .generate();
 
-    // scales the noise
-    let noise = noise
-        .into_iter()
-        .map(|x| x.abs() * 700.0)
-        .collect();
-    noise
+    noise.into_iter().map(normalize_fbm).collect()
 }
 
 
+/// Peaks and valleys of each column of a chunk, from -1 (valleys) to 1 (peaks).
 pub fn get_chunk_peaks_valleys(key: IVec3, chunk_len: usize, seed: i32) -> Vec<f32> {
     // let peaks_valleys_noise = noise::Fbm::<noise::SuperSimplex>::new(5238532) //@todo : use random seed
     //     .set_octaves(6)
@@ -151,17 +149,17 @@ pub fn get_chunk_peaks_valleys(key: IVec3, chunk_len: usize, seed: i32) -> Vec<f
     .with_lacunarity(2.0)
     .with_octaves(6)
     .with_gain(0.5)
-    .with_seed(seed)
+    .with_seed(seed.wrapping_add(PEAKS_VALLEYS_SEED_OFFSET))
     .generate();
 
-    // scales the noise
-    let noise = noise
-        .into_iter()
-        .map(|x| x.abs() * 1200.0)
-        .collect();
+    // folded so that -1 lies in valleys and 1 on the ridges between them.
     noise
+        .into_iter()
+        .map(|x| 1.0 - (3.0 * normalize_fbm(x).abs() - 2.0).abs())
+        .collect()
 }
 
+/// Continentalness of each column of a chunk, from -1 (deep oceans) to 1 (far inland).
 pub fn get_chunk_continentalness(key: IVec3, chunk_len: usize, seed: i32) -> Vec<f32> {
     // // Continentalness noise
     // let continental_noise = noise::Fbm::<Perlin>::new(0)
@@ -195,15 +193,122 @@ pub fn get_chunk_continentalness(key: IVec3, chunk_len: usize, seed: i32) -> Vec
     .with_seed(seed)
     .generate();
 
-    // scales the noise
-    let noise = noise
-        .into_iter()
-        .map(|x| x.abs() * 1400.0)
-        .collect();
+    noise.into_iter().map(normalize_fbm).collect()
+}
+
+
+/// Humidity of each column of a chunk, from 0 (dry) to 1 (wet).
+pub fn get_chunk_humidity(key: IVec3, chunk_len: usize, seed: i32) -> Vec<f32> {
+    get_chunk_climate(key, chunk_len, seed.wrapping_add(HUMIDITY_SEED_OFFSET), 0.0011)
+}
+
+/// Temperature of each column of a chunk, from 0 (cold) to 1 (hot).
+pub fn get_chunk_temperature(key: IVec3, chunk_len: usize, seed: i32) -> Vec<f32> {
+    get_chunk_climate(key, chunk_len, seed.wrapping_add(TEMPERATURE_SEED_OFFSET), 0.0008)
+}
+
+// every layer gets a seed of its own, the same noise would tie them together, and biomes to the height.
+const EROSION_SEED_OFFSET: i32 = 104_729;
+const PEAKS_VALLEYS_SEED_OFFSET: i32 = 1_299_709;
+const HUMIDITY_SEED_OFFSET: i32 = 7919;
+const TEMPERATURE_SEED_OFFSET: i32 = 15_485_863;
+
+/// Maps fbm values to -1..1, low frequency fbm values mostly staying within -0.04..0.04.
+pub(super) fn normalize_fbm(x: f32) -> f32 {
+    (x * 25.0).clamp(-1.0, 1.0)
+}
+
+fn get_chunk_climate(key: IVec3, chunk_len: usize, seed: i32, freq: f32) -> Vec<f32> {
+    let (noise, _min, _max) = NoiseBuilder::fbm_2d_offset(key.x as f32, chunk_len, key.z as f32, chunk_len)
+    .with_freq(freq)
+    .with_lacunarity(2.0)
+    .with_octaves(4)
+    .with_gain(0.5)
+    .with_seed(seed)
+    .generate();
+
     noise
+        .into_iter()
+        .map(|x| (normalize_fbm(x) + 1.0) / 2.0)
+        .collect()
 }
 
 
+/// 1D gradient noise from -1 to 1, smooth and repeating nowhere, integer inputs always giving 0.
+pub fn perlin_1d(x: f32, seed: u32) -> f32 {
+    let gradient = |i: i32| {
+        let mut hash = (i as u32).wrapping_mul(0x8da6_b343) ^ seed.wrapping_mul(0xcb1a_b31f);
+        hash ^= hash >> 16;
+        hash = hash.wrapping_mul(0x7feb_352d);
+        hash ^= hash >> 15;
+        (hash >> 8) as f32 / (1 << 23) as f32 - 1.0
+    };
+
+    let cell = x.floor();
+    let t = x - cell;
+    let from = gradient(cell as i32) * t;
+    let to = gradient(cell as i32 + 1) * (t - 1.0);
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    // two gradients of 1 meeting halfway only reach 0.5.
+    ((from + (to - from) * fade) * 2.0).clamp(-1.0, 1.0)
+}
+
+/// 3D noise sampled at the corners of cells aligned on world coordinates, so chunks sample the same values
+/// along their borders.
+pub struct NoiseGrid {
+    cell_size: u32,
+    /// Number of corners along each axis.
+    len: UVec3,
+    values: Vec<f32>,
+}
+
+impl NoiseGrid {
+    /// Samples the corners of the cells covering a box, whose minimum and shape are multiples of the cell size.
+    pub fn sample(min: IVec3, shape: UVec3, cell_size: u32, freq: f32, octaves: u8, seed: i32) -> Self {
+        let len = shape / cell_size + UVec3::ONE;
+        let offset = min / cell_size as i32;
+
+        let (noise, _min, _max) = NoiseBuilder::fbm_3d_offset(
+            offset.x as f32,
+            len.x as usize,
+            offset.y as f32,
+            len.y as usize,
+            offset.z as f32,
+            len.z as usize,
+        )
+        .with_freq(freq * cell_size as f32)
+        .with_lacunarity(2.0)
+        .with_octaves(octaves)
+        .with_gain(0.5)
+        .with_seed(seed)
+        .generate();
+
+        Self {
+            cell_size,
+            len,
+            values: noise.into_iter().map(normalize_fbm).collect(),
+        }
+    }
+
+    /// Returns the noise at a position relative to the grid minimum, trilinearly interpolated between the corners
+    /// of its cell.
+    pub fn get(&self, pos: UVec3) -> f32 {
+        let cell = pos / self.cell_size;
+        let t = (pos % self.cell_size).as_vec3() / self.cell_size as f32;
+        let corner = |x: u32, y: u32, z: u32| {
+            let corner = cell + UVec3::new(x, y, z);
+            self.values[((corner.z * self.len.y + corner.y) * self.len.x + corner.x) as usize]
+        };
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), t.x);
+        let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), t.x);
+        let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), t.x);
+        let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), t.x);
+        lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+    }
+}
+
 /// A view into a slice of noise values with W x H dimensions.
 /// Provides methods for fetching a value at specified coordinates and to map values to a range.
 #[derive(Clone, Copy)]
@@ -0,0 +1,224 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Height of the peaks and valleys relief for a terrain factor of 1, in voxels.
+pub const RELIEF_HEIGHT: f32 = 48.0;
+
+/// A noise layer splines are sampled along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SplineInput {
+    Continentalness,
+    Erosion,
+    PeaksValleys,
+}
+
+/// The values of the noise layers at a column, all going from -1 to 1.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TerrainParams {
+    pub continentalness: f32,
+    pub erosion: f32,
+    pub peaks_valleys: f32,
+}
+
+impl TerrainParams {
+    pub fn get(&self, input: SplineInput) -> f32 {
+        match input {
+            SplineInput::Continentalness => self.continentalness,
+            SplineInput::Erosion => self.erosion,
+            SplineInput::PeaksValleys => self.peaks_valleys,
+        }
+    }
+}
+
+/// The value of a spline point, either fixed or given by another spline sampled along another noise layer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SplineValue {
+    Constant(f32),
+    Spline(Box<Spline>),
+}
+
+impl SplineValue {
+    pub fn sample(&self, params: &TerrainParams) -> f32 {
+        match self {
+            Self::Constant(value) => *value,
+            Self::Spline(spline) => spline.sample(params),
+        }
+    }
+}
+
+impl From<f32> for SplineValue {
+    fn from(value: f32) -> Self {
+        Self::Constant(value)
+    }
+}
+
+impl From<Spline> for SplineValue {
+    fn from(spline: Spline) -> Self {
+        Self::Spline(Box::new(spline))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SplinePoint {
+    pub location: f32,
+    pub value: SplineValue,
+    /// Slope of the curve at the point.
+    pub derivative: f32,
+}
+
+/// A piecewise cubic Hermite curve along a noise layer, going through its points with their slopes.
+/// Past its first and last points, the curve follows their slope.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Spline {
+    input: SplineInput,
+    points: Vec<SplinePoint>,
+}
+
+impl Spline {
+    pub fn new(input: SplineInput) -> Self {
+        Self {
+            input,
+            points: Vec::new(),
+        }
+    }
+
+    /// Adds a point, which must lie after the previous ones along the noise layer.
+    pub fn point(mut self, location: f32, value: impl Into<SplineValue>, derivative: f32) -> Self {
+        assert!(
+            self.points.last().is_none_or(|x| x.location < location),
+            "spline points must be added in order"
+        );
+        self.points.push(SplinePoint {
+            location,
+            value: value.into(),
+            derivative,
+        });
+        self
+    }
+
+    /// Checks that the points of the curve and of the splines it's made of are in order, e.g. once deserialized.
+    pub fn check(&self) -> Result<()> {
+        for (i, point) in self.points.iter().enumerate() {
+            if !point.location.is_finite() || (i > 0 && self.points[i - 1].location >= point.location) {
+                bail!("the points of a {:?} spline aren't in order at {}", self.input, point.location);
+            }
+            if let SplineValue::Spline(spline) = &point.value {
+                spline.check()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Samples the curve at the value of its noise layer, 0 if it has no point.
+    pub fn sample(&self, params: &TerrainParams) -> f32 {
+        let x = params.get(self.input);
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return 0.0;
+        };
+
+        if x <= first.location {
+            return first.value.sample(params) + first.derivative * (x - first.location);
+        }
+        if x >= last.location {
+            return last.value.sample(params) + last.derivative * (x - last.location);
+        }
+
+        let i = self.points.partition_point(|point| point.location <= x) - 1;
+        let (a, b) = (&self.points[i], &self.points[i + 1]);
+        let width = b.location - a.location;
+        let t = (x - a.location) / width;
+        let (value_a, value_b) = (a.value.sample(params), b.value.sample(params));
+
+        // the Hermite basis, written as the linear interpolation of the values plus a cubic correction.
+        let slope_a = a.derivative * width - (value_b - value_a);
+        let slope_b = -b.derivative * width + (value_b - value_a);
+        lerp(t, value_a, value_b) + t * (1.0 - t) * lerp(t, slope_a, slope_b)
+    }
+}
+
+/// Where a column lies relatively to the base surface level and how rugged it is.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TerrainShape {
+    /// Height of the terrain above the base surface level, in voxels.
+    pub offset: f32,
+    /// Ruggedness of the terrain, scaling the peaks and valleys relief.
+    pub factor: f32,
+}
+
+/// The splines shaping the terrain from the noise layers: continentalness makes oceans, coasts and inland,
+/// erosion flattens the terrain into plains and plateaus or lets it rise into mountains,
+/// peaks and valleys carve ridges and the jagged peaks on top of them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TerrainSplines {
+    pub offset: Spline,
+    pub factor: Spline,
+}
+
+impl TerrainSplines {
+    pub fn check(&self) -> Result<()> {
+        self.offset.check()?;
+        self.factor.check()
+    }
+
+    pub fn shape(&self, params: &TerrainParams) -> TerrainShape {
+        TerrainShape {
+            offset: self.offset.sample(params),
+            factor: self.factor.sample(params).max(0.0),
+        }
+    }
+}
+
+impl Default for TerrainSplines {
+    fn default() -> Self {
+        use SplineInput::*;
+
+        let jagged_peaks = |height: f32| {
+            Spline::new(PeaksValleys)
+                .point(-1.0, height * 0.6, 0.0)
+                .point(0.2, height, 0.6 * height)
+                .point(1.0, height * 1.6, 0.0)
+        };
+        let inland = |scale: f32| {
+            Spline::new(Erosion)
+                .point(-1.0, jagged_peaks(70.0 * scale), 0.0)
+                // plateaus: the terrain stays flat over that range of erosion.
+                .point(-0.55, 45.0 * scale, 0.0)
+                .point(-0.3, 45.0 * scale, 0.0)
+                .point(0.1, 16.0 * scale, -20.0)
+                .point(1.0, 4.0, 0.0)
+        };
+
+        let offset = Spline::new(Continentalness)
+            .point(-1.0, -60.0, 0.0)
+            .point(-0.45, -40.0, 20.0)
+            .point(-0.2, -12.0, 60.0)
+            // coasts
+            .point(-0.1, -2.0, 40.0)
+            .point(0.0, Spline::new(Erosion).point(-1.0, 10.0, 0.0).point(1.0, 2.0, 0.0), 0.0)
+            .point(0.35, inland(1.0), 0.0)
+            .point(1.0, inland(1.5), 0.0);
+
+        let inland_factor = |scale: f32| {
+            Spline::new(Erosion)
+                .point(-1.0, 1.0 * scale, 0.0)
+                .point(-0.5, 0.15, 0.0)
+                .point(-0.3, 0.15, 0.0)
+                .point(0.2, 0.45 * scale, 0.0)
+                .point(1.0, 0.15, 0.0)
+        };
+
+        let factor = Spline::new(Continentalness)
+            .point(-1.0, 0.1, 0.0)
+            .point(-0.1, 0.15, 0.0)
+            .point(0.0, Spline::new(Erosion).point(-1.0, 0.5, 0.0).point(1.0, 0.15, 0.0), 0.0)
+            .point(0.35, inland_factor(1.0), 0.0)
+            .point(1.0, inland_factor(1.3), 0.0);
+
+        Self { offset, factor }
+    }
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
@@ -1,11 +1,26 @@
 use block_mesh::{MergeVoxel, Voxel as MeshableVoxel};
 use serde::{Serialize, Deserialize};
 
+/// A single voxel: the ID of its material, and a small material specific state
+/// (e.g. orientation, fluid level, growth stage or snow depth).
 #[derive(Clone, Copy, Hash, Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub struct Voxel(pub u8);
+pub struct Voxel {
+    pub material: u16,
+    pub state: u8,
+}
 
 impl Voxel {
-    pub const EMPTY_VOXEL: Self = Self(0);
+    pub const EMPTY_VOXEL: Self = Self::new(0);
+
+    #[inline]
+    pub const fn new(material: u16) -> Self {
+        Self { material, state: 0 }
+    }
+
+    #[inline]
+    pub const fn with_state(self, state: u8) -> Self {
+        Self { state, ..self }
+    }
 }
 
 impl Default for Voxel {
@@ -17,29 +32,36 @@ impl Default for Voxel {
 impl MeshableVoxel for Voxel {
     #[inline]
     fn get_visibility(&self) -> block_mesh::VoxelVisibility {
-        match *self {
-            Self::EMPTY_VOXEL => block_mesh::VoxelVisibility::Empty,
-            Voxel(6) => block_mesh::VoxelVisibility::Translucent, // Water Voxel type has ID 6.
+        match self.material {
+            0 => block_mesh::VoxelVisibility::Empty,
+            6 => block_mesh::VoxelVisibility::Translucent, // Water Voxel type has ID 6.
             _ => block_mesh::VoxelVisibility::Opaque,
         }
     }
 }
 
 impl MergeVoxel for Voxel {
-    type MergeValue = u8;
+    // faces of voxels in different states may look different, so only identical voxels get merged.
+    type MergeValue = Self;
 
     #[inline]
     fn merge_value(&self) -> Self::MergeValue {
-        self.0
+        *self
     }
 }
 
 pub trait MaterialVoxel: MergeVoxel + MeshableVoxel {
-    fn as_mat_id(&self) -> u8;
+    fn as_mat_id(&self) -> u16;
+
+    fn state(&self) -> u8;
 }
 
 impl MaterialVoxel for Voxel {
-    fn as_mat_id(&self) -> u8 {
-        self.0
+    fn as_mat_id(&self) -> u16 {
+        self.material
+    }
+
+    fn state(&self) -> u8 {
+        self.state
     }
 }
\ No newline at end of file
@@ -0,0 +1,315 @@
+use std::{io::Read, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use bevy::{
+    log::{info, warn},
+    math::{IVec2, IVec3},
+    utils::HashMap,
+};
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use super::{offline::OfflineWorld, terrain::save_chunk_to_disk, ChunkShape, CHUNK_HEIGHT, CHUNK_LENGTH};
+use crate::voxel::{
+    formats::{
+        block_mapping::BlockMapping,
+        nbt::{self, NbtCompound, NbtTag},
+    },
+    storage::VoxelBuffer,
+    Voxel,
+};
+
+const ANVIL_SECTOR_SIZE: usize = 4096;
+/// Number of Minecraft chunks along each axis of a region file.
+const ANVIL_REGION_LENGTH: i32 = 32;
+/// Minecraft chunks are 16 blocks wide, and split into 16 blocks high sections.
+const ANVIL_CHUNK_LENGTH: i32 = 16;
+const ANVIL_SECTION_VOLUME: usize = 16 * 16 * 16;
+/// Since this data version (20w17a), packed block states don't span over two longs anymore.
+const NON_SPANNING_DATA_VERSION: i64 = 2529;
+/// Minecraft chunks making one of our chunks along each horizontal axis.
+const CHUNKS_PER_COLUMN: i32 = CHUNK_LENGTH as i32 / ANVIL_CHUNK_LENGTH;
+
+/// A 16^3 section of a Minecraft chunk, as material IDs.
+struct AnvilSection {
+    y: i32,
+    voxels: Vec<Voxel>,
+}
+
+/// Imports the `r.{x}.{z}.mca` region files of a Minecraft world (1.13+) found in a directory into a world save.
+///
+/// Each imported column is written for the whole world height, so the terrain generator doesn't fill its gaps.
+/// Blocks are mapped to materials with the specified mapping and moved vertically by `y_offset`,
+/// blocks ending up outside of the world height limits are dropped. Returns the number of chunks written.
+/// Chunks which can't be read are skipped with a warning.
+pub fn import_anvil_world(region_dir: &Path, world: &OfflineWorld, mapping: &BlockMapping, y_offset: i32) -> Result<usize> {
+    let mut imported = 0;
+    for dir_entry in std::fs::read_dir(region_dir)? {
+        let path = dir_entry?.path();
+        if path.extension().and_then(|x| x.to_str()) != Some("mca") {
+            continue;
+        }
+
+        let Some(region) = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_prefix("r."))
+            .and_then(|x| x.split_once('.'))
+            .and_then(|(x, z)| Some(IVec2::new(x.parse().ok()?, z.parse().ok()?)))
+        else {
+            warn!("skipping unrecognized region file {:?}", path);
+            continue;
+        };
+
+        imported += import_anvil_region(&path, region, world, mapping, y_offset)?;
+    }
+
+    info!("imported {} chunks from {:?}", imported, region_dir);
+    Ok(imported)
+}
+
+/// Imports a single Minecraft region file, see [`import_anvil_world`].
+pub fn import_anvil_region(
+    path: &Path,
+    region: IVec2,
+    world: &OfflineWorld,
+    mapping: &BlockMapping,
+    y_offset: i32,
+) -> Result<usize> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() < 2 * ANVIL_SECTOR_SIZE {
+        bail!("region file {:?} is truncated", path);
+    }
+
+    // block names are mapped once, unmapped ones are only reported once too.
+    let mut block_materials = HashMap::<String, Voxel>::default();
+    let mut imported = 0;
+
+    // a region file holds an even number of Minecraft chunks per axis, so each of our columns lies in a single file.
+    for column_x in 0..ANVIL_REGION_LENGTH / CHUNKS_PER_COLUMN {
+        for column_z in 0..ANVIL_REGION_LENGTH / CHUNKS_PER_COLUMN {
+            let mut column: Vec<(IVec2, Vec<AnvilSection>)> = Vec::new();
+            for offset_x in 0..CHUNKS_PER_COLUMN {
+                for offset_z in 0..CHUNKS_PER_COLUMN {
+                    let local = IVec2::new(
+                        column_x * CHUNKS_PER_COLUMN + offset_x,
+                        column_z * CHUNKS_PER_COLUMN + offset_z,
+                    );
+                    let chunk = match read_anvil_chunk(&bytes, local) {
+                        Ok(Some(chunk)) => chunk,
+                        Ok(None) => continue,
+                        Err(err) => {
+                            warn!("skipping chunk {} of region {:?}: {}", local, path, err);
+                            continue;
+                        }
+                    };
+                    let sections = match read_sections(&chunk, mapping, &mut block_materials) {
+                        Ok(sections) => sections,
+                        Err(err) => {
+                            warn!("skipping chunk {} of region {:?}: {}", local, path, err);
+                            continue;
+                        }
+                    };
+                    column.push((IVec2::new(offset_x, offset_z) * ANVIL_CHUNK_LENGTH, sections));
+                }
+            }
+
+            if column.is_empty() {
+                continue;
+            }
+
+            let column_min = (region * ANVIL_REGION_LENGTH + IVec2::new(column_x, column_z) * CHUNKS_PER_COLUMN)
+                * ANVIL_CHUNK_LENGTH;
+            imported += write_column(column_min, &column, world, y_offset)?;
+        }
+    }
+
+    Ok(imported)
+}
+
+/// Writes the chunks of a column made of up to 2x2 Minecraft chunks, positioned in the column by their minimum.
+fn write_column(
+    column_min: IVec2,
+    column: &[(IVec2, Vec<AnvilSection>)],
+    world: &OfflineWorld,
+    y_offset: i32,
+) -> Result<usize> {
+    let mut layers = HashMap::<i32, VoxelBuffer<Voxel, ChunkShape>>::default();
+    for layer_y in (world.settings.min_height..world.settings.max_height).step_by(CHUNK_HEIGHT as usize) {
+        layers.insert(layer_y, VoxelBuffer::new_empty(ChunkShape {}));
+    }
+
+    for (chunk_min, sections) in column {
+        for section in sections {
+            for (index, voxel) in section.voxels.iter().enumerate() {
+                let index = index as i32;
+                let (x, z, y) = (index % 16, index / 16 % 16, index / 256);
+                let world_y = section.y * ANVIL_CHUNK_LENGTH + y + y_offset;
+                let layer_y = world_y.div_euclid(CHUNK_HEIGHT as i32) * CHUNK_HEIGHT as i32;
+
+                if let Some(layer) = layers.get_mut(&layer_y) {
+                    *layer.voxel_at_mut(
+                        [
+                            (chunk_min.x + x) as u32,
+                            world_y.rem_euclid(CHUNK_HEIGHT as i32) as u32,
+                            (chunk_min.y + z) as u32,
+                        ]
+                        .into(),
+                    ) = *voxel;
+                }
+            }
+        }
+    }
+
+    let count = layers.len();
+    for (layer_y, buffer) in layers {
+        save_chunk_to_disk(
+            &buffer,
+            IVec3::new(column_min.x, layer_y, column_min.y),
+            &world.settings.name,
+            &world.materials,
+        )?;
+    }
+
+    Ok(count)
+}
+
+/// Reads the NBT data of the chunk at the specified position within a region file, if it was ever generated.
+fn read_anvil_chunk(region: &[u8], local: IVec2) -> Result<Option<NbtCompound>> {
+    let index = (local.y * ANVIL_REGION_LENGTH + local.x) as usize * 4;
+    let location = &region[index..index + 4];
+    let sector = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize;
+    if sector == 0 || location[3] == 0 {
+        return Ok(None);
+    }
+
+    let start = sector * ANVIL_SECTOR_SIZE;
+    let Some(header) = region.get(start..start + 5) else {
+        bail!("chunk data is past the end of the file");
+    };
+    let length = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let Some(data) = length
+        .checked_sub(1)
+        .and_then(|length| region.get(start + 5..start + 5 + length))
+    else {
+        bail!("chunk data is truncated");
+    };
+
+    let mut decompressed = Vec::new();
+    match header[4] {
+        1 => {
+            GzDecoder::new(data).read_to_end(&mut decompressed)?;
+        }
+        2 => {
+            ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+        }
+        3 => decompressed.extend_from_slice(data),
+        compression if compression & 128 != 0 => bail!("chunks stored in external .mcc files aren't supported"),
+        compression => bail!("unsupported chunk compression {}", compression),
+    }
+
+    Ok(Some(nbt::read_nbt(&decompressed)?.1))
+}
+
+/// Reads the sections of a chunk, in both the 1.18+ layout and the older `Level` compound one.
+fn read_sections(
+    chunk: &NbtCompound,
+    mapping: &BlockMapping,
+    block_materials: &mut HashMap<String, Voxel>,
+) -> Result<Vec<AnvilSection>> {
+    let data_version = chunk.get("DataVersion").and_then(|x| x.as_i64()).unwrap_or(0);
+    let spanning = data_version < NON_SPANNING_DATA_VERSION;
+
+    let (sections, legacy) = match chunk.get("sections") {
+        Some(sections) => (sections, false),
+        None => {
+            let level = chunk
+                .get("Level")
+                .and_then(|x| x.as_compound())
+                .ok_or_else(|| anyhow!("chunk has neither sections nor a Level compound"))?;
+            (level.get("Sections").ok_or_else(|| anyhow!("chunk has no sections"))?, true)
+        }
+    };
+
+    let mut result = Vec::new();
+    for section in sections.as_list().unwrap_or_default() {
+        let Some(section) = section.as_compound() else {
+            continue;
+        };
+        let Some(y) = section.get("Y").and_then(|x| x.as_i64()) else {
+            continue;
+        };
+
+        let (palette, data) = if legacy {
+            (section.get("Palette"), section.get("BlockStates"))
+        } else {
+            let Some(block_states) = section.get("block_states").and_then(|x| x.as_compound()) else {
+                continue;
+            };
+            (block_states.get("palette"), block_states.get("data"))
+        };
+        // sections without a palette are empty, or pre 1.13 numeric block IDs which aren't supported.
+        let Some(palette) = palette.and_then(|x| x.as_list()) else {
+            continue;
+        };
+
+        let palette: Vec<Voxel> = palette
+            .iter()
+            .map(|entry| {
+                let name = entry
+                    .as_compound()
+                    .and_then(|x| x.get("Name"))
+                    .and_then(NbtTag::as_str)
+                    .unwrap_or("minecraft:air");
+                *block_materials
+                    .entry(name.to_string())
+                    .or_insert_with(|| Voxel::new(mapping.material_for(name)))
+            })
+            .collect();
+
+        let voxels = match (palette.len(), data.and_then(|x| x.as_long_array())) {
+            (0, _) => continue,
+            (1, _) | (_, None) => vec![palette[0]; ANVIL_SECTION_VOLUME],
+            (_, Some(data)) => {
+                let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(4) as usize;
+                unpack_block_states(data, bits, spanning)?
+                    .into_iter()
+                    .map(|x| palette.get(x as usize).copied().unwrap_or_default())
+                    .collect()
+            }
+        };
+
+        result.push(AnvilSection { y: y as i32, voxels });
+    }
+
+    Ok(result)
+}
+
+/// Unpacks the palette indices of a section, stored with `bits` bits each.
+fn unpack_block_states(data: &[i64], bits: usize, spanning: bool) -> Result<Vec<u16>> {
+    let mask = (1u64 << bits) - 1;
+    let per_long = 64 / bits;
+    let required = match spanning {
+        true => (ANVIL_SECTION_VOLUME * bits).div_ceil(64),
+        false => ANVIL_SECTION_VOLUME.div_ceil(per_long),
+    };
+    if data.len() < required {
+        bail!("section holds {} longs of block states, expected {}", data.len(), required);
+    }
+
+    Ok((0..ANVIL_SECTION_VOLUME)
+        .map(|index| {
+            let value = if spanning {
+                let bit = index * bits;
+                let (long, offset) = (bit / 64, bit % 64);
+                let mut value = data[long] as u64 >> offset;
+                if offset + bits > 64 {
+                    value |= (data[long + 1] as u64) << (64 - offset);
+                }
+                value
+            } else {
+                data[index / per_long] as u64 >> (index % per_long * bits)
+            };
+            (value & mask) as u16
+        })
+        .collect())
+}
@@ -0,0 +1,421 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bevy::{
+    ecs::system::{Commands, Res, ResMut, Resource},
+    log::warn,
+};
+use ndshape::{RuntimeShape, Shape};
+use serde::{Deserialize, Serialize};
+
+use super::{ChunkShape, CHUNK_HEIGHT, CHUNK_LENGTH};
+use crate::voxel::{
+    material::VoxelMaterialRegistry,
+    storage::{SectionedVoxelBuffer, VoxelBuffer},
+    Voxel,
+};
+
+/// Bytes every saved chunk starts with, chunks saved before the header existed (format version 1) don't have it.
+const CHUNK_MAGIC: [u8; 4] = *b"YVCK";
+/// Version of the chunk payload written by [`encode_chunk`].
+pub const CHUNK_FORMAT_VERSION: u16 = 3;
+/// magic + format version + CRC of the compressed payload.
+const HEADER_SIZE: usize = CHUNK_MAGIC.len() + 2 + 4;
+/// Bytes chunks saved as a difference with the generated terrain start with.
+const DELTA_MAGIC: [u8; 4] = *b"YVCD";
+/// Version of the chunk deltas written by [`encode_delta`].
+pub const DELTA_FORMAT_VERSION: u16 = 1;
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Upgrades the uncompressed payload of a chunk saved with the format version it's indexed by (starting at 1)
+/// to the next version. Changing the payload layout means bumping [`CHUNK_FORMAT_VERSION`] and appending a migration here.
+const MIGRATIONS: [fn(Vec<u8>) -> Result<Vec<u8>>; CHUNK_FORMAT_VERSION as usize - 1] = [migrate_v1_to_v2, migrate_v2_to_v3];
+
+/// Material IDs in use when chunks were saved as raw voxel data.
+const V1_MATERIAL_NAMES: [&str; 14] = [
+    "Void",
+    "Bedrock",
+    "Rock",
+    "Dirt",
+    "Sand",
+    "Grass",
+    "Snow",
+    "Water",
+    "Sandstone",
+    "Cactus",
+    "Wood",
+    "Leaves",
+    "PineLeaves",
+    "PineWood",
+];
+
+/// Voxel data along with the names of the materials it references, as saved for chunks in the current format version.
+#[derive(Serialize, Deserialize)]
+pub(super) struct VoxelPayload {
+    /// Buffer dimensions at the time of saving.
+    dims: [u32; 3],
+    /// Names of the materials referenced by the voxels, indexed by the voxel values.
+    materials: Vec<String>,
+    /// Voxel material indices into `materials`, linearized in x, y, z order.
+    voxels: Vec<u16>,
+    /// Voxel states, linearized like `voxels`.
+    states: Vec<u8>,
+}
+
+/// Voxels of a chunk which differ from the generated terrain, as saved for chunk deltas.
+#[derive(Serialize, Deserialize)]
+struct DeltaPayload {
+    /// Version of the terrain generator the voxels were compared to.
+    generator_version: u16,
+    dims: [u32; 3],
+    materials: Vec<String>,
+    /// Runs of consecutive differing voxels, as the index of their first voxel and their length.
+    runs: Vec<(u32, u32)>,
+    /// Material indices into `materials` of the voxels of all the runs, one run after the other.
+    voxels: Vec<u16>,
+    states: Vec<u8>,
+}
+
+/// Payload of a chunk in format version 2, when voxels were only a material index.
+#[derive(Serialize, Deserialize)]
+struct ChunkPayloadV2 {
+    dims: [u32; 3],
+    materials: Vec<String>,
+    voxels: Vec<u8>,
+}
+
+/// The names of the registered materials indexed by their ID, used to describe the voxels of saved chunks.
+#[derive(Resource, Clone, Default)]
+pub struct MaterialNames(Arc<[&'static str]>);
+
+impl MaterialNames {
+    pub fn from_registry(registry: &VoxelMaterialRegistry) -> Self {
+        Self(registry.iter_mats().map(|x| x.name).collect())
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn id_for(&self, name: &str) -> Option<u16> {
+        self.0.iter().position(|x| *x == name).map(|x| x as u16)
+    }
+}
+
+impl VoxelPayload {
+    pub(super) fn from_voxels(dims: [u32; 3], voxels: &[Voxel], materials: &MaterialNames) -> Self {
+        Self {
+            dims,
+            materials: materials.0.iter().map(|x| x.to_string()).collect(),
+            voxels: voxels.iter().map(|x| x.material).collect(),
+            states: voxels.iter().map(|x| x.state).collect(),
+        }
+    }
+
+    /// Checks that the payload holds as many voxels as its dimensions require.
+    pub(super) fn check(&self) -> Result<()> {
+        let [x, y, z] = self.dims;
+        if self.voxels.len() != (x * y * z) as usize || self.states.len() != self.voxels.len() {
+            bail!("payload holds {} voxels, expected {}", self.voxels.len(), x * y * z);
+        }
+        Ok(())
+    }
+
+    /// Builds a buffer holding the saved voxels, remapped to the current material IDs.
+    pub(super) fn into_buffer(self, materials: &MaterialNames) -> Result<VoxelBuffer<Voxel, RuntimeShape<u32, 3>>> {
+        self.check()?;
+
+        let remap = remap_materials(&self.materials, materials);
+
+        let mut buffer = VoxelBuffer::<Voxel, RuntimeShape<u32, 3>>::new_empty(RuntimeShape::<u32, 3>::new(self.dims));
+        buffer
+            .slice_mut()
+            .iter_mut()
+            .zip(self.voxels.into_iter().zip(self.states))
+            .for_each(|(voxel, (id, state))| {
+                *voxel = match remap.get(id as usize) {
+                    Some(material) => Voxel::new(*material).with_state(state),
+                    None => Voxel::EMPTY_VOXEL,
+                }
+            });
+
+        Ok(buffer)
+    }
+}
+
+pub fn init_material_names(mut commands: Commands, registry: Res<VoxelMaterialRegistry>) {
+    commands.insert_resource(MaterialNames::from_registry(&registry));
+}
+
+/// Keeps the names in sync with the materials registered after startup, e.g. by model imports.
+pub fn update_material_names(mut names: ResMut<MaterialNames>, registry: Res<VoxelMaterialRegistry>) {
+    if names.0.len() != registry.iter_mats().count() {
+        *names = MaterialNames::from_registry(&registry);
+    }
+}
+
+impl DeltaPayload {
+    /// Checks that the runs are ordered, fit in the chunk and hold as many voxels as there are.
+    fn check(&self) -> Result<()> {
+        let [x, y, z] = self.dims;
+        if self.dims != (ChunkShape {}).as_array() {
+            bail!("chunk delta is {}x{}x{} voxels, expected {}x{}x{}", x, y, z, CHUNK_LENGTH, CHUNK_HEIGHT, CHUNK_LENGTH);
+        }
+
+        let volume = x * y * z;
+        let mut end = 0;
+        let mut length_sum = 0;
+        for (start, length) in &self.runs {
+            if *start < end || *length == 0 || start.checked_add(*length).is_none_or(|x| x > volume) {
+                bail!("invalid run of {} voxels at {} in chunk delta", length, start);
+            }
+            end = start + length;
+            length_sum += *length as usize;
+        }
+
+        if self.voxels.len() != length_sum || self.states.len() != length_sum {
+            bail!("chunk delta holds {} voxels, expected {}", self.voxels.len(), length_sum);
+        }
+        Ok(())
+    }
+}
+
+/// Maps the material indices of saved voxels to the current material IDs,
+/// saved materials missing from the registry end up as void.
+fn remap_materials(saved: &[String], materials: &MaterialNames) -> Vec<u16> {
+    saved
+        .iter()
+        .map(|name| {
+            materials.id_for(name).unwrap_or_else(|| {
+                warn!("unknown material {} in saved voxels, replacing it with void", name);
+                Voxel::EMPTY_VOXEL.material
+            })
+        })
+        .collect()
+}
+
+/// The voxels of a chunk which differ from the terrain generated for it, stored as runs of consecutive voxels.
+pub struct ChunkDelta {
+    generator_version: u16,
+    runs: Vec<(u32, u32)>,
+    voxels: Vec<Voxel>,
+}
+
+impl ChunkDelta {
+    /// Compares the voxels of a chunk to the ones generated for it, both linearized like [`ChunkShape`].
+    pub fn between(generated: &[Voxel], voxels: &[Voxel], generator_version: u16) -> Self {
+        let mut runs = Vec::<(u32, u32)>::new();
+        let mut changed = Vec::new();
+        for (index, (generated, voxel)) in generated.iter().zip(voxels).enumerate() {
+            if generated == voxel {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some((start, length)) if *start + *length == index as u32 => *length += 1,
+                _ => runs.push((index as u32, 1)),
+            }
+            changed.push(*voxel);
+        }
+
+        Self {
+            generator_version,
+            runs,
+            voxels: changed,
+        }
+    }
+
+    /// Returns the number of voxels differing from the generated terrain.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    #[inline]
+    pub fn generator_version(&self) -> u16 {
+        self.generator_version
+    }
+
+    /// Writes the differing voxels over the generated terrain of the chunk.
+    pub fn apply(&self, buffer: &mut SectionedVoxelBuffer<Voxel, ChunkShape>) {
+        let mut voxels = self.voxels.iter();
+        for (start, length) in &self.runs {
+            for index in *start..*start + *length {
+                buffer.set_voxel(ChunkShape {}.delinearize(index).into(), *voxels.next().unwrap());
+            }
+        }
+    }
+}
+
+/// A chunk as saved on disk.
+pub enum SavedChunk {
+    /// All the voxels of the chunk.
+    Full(VoxelBuffer<Voxel, ChunkShape>),
+    /// The voxels to write over the terrain generated for the chunk.
+    Delta(ChunkDelta),
+}
+
+/// Serializes a chunk with the current format version: magic, version, CRC, then the compressed [`VoxelPayload`].
+pub fn encode_chunk(chunk_data: &VoxelBuffer<Voxel, ChunkShape>, materials: &MaterialNames) -> Result<Vec<u8>> {
+    let payload = VoxelPayload::from_voxels(ChunkShape {}.as_array(), chunk_data.slice(), materials);
+    write_container(CHUNK_MAGIC, CHUNK_FORMAT_VERSION, &payload)
+}
+
+/// Serializes a chunk delta with the current delta format version, in the same container as full chunks.
+pub fn encode_delta(delta: &ChunkDelta, materials: &MaterialNames) -> Result<Vec<u8>> {
+    let payload = DeltaPayload {
+        generator_version: delta.generator_version,
+        dims: ChunkShape {}.as_array(),
+        materials: materials.0.iter().map(|x| x.to_string()).collect(),
+        runs: delta.runs.clone(),
+        voxels: delta.voxels.iter().map(|x| x.material).collect(),
+        states: delta.voxels.iter().map(|x| x.state).collect(),
+    };
+    write_container(DELTA_MAGIC, DELTA_FORMAT_VERSION, &payload)
+}
+
+/// Deserializes a chunk saved in full with any format version or as a delta,
+/// remapping its voxels to the current material IDs.
+pub fn decode_chunk(bytes: &[u8], materials: &MaterialNames) -> Result<SavedChunk> {
+    if let Some(payload) = decode_delta_payload(bytes)? {
+        let remap = remap_materials(&payload.materials, materials);
+        return Ok(SavedChunk::Delta(ChunkDelta {
+            generator_version: payload.generator_version,
+            runs: payload.runs,
+            voxels: payload
+                .voxels
+                .into_iter()
+                .zip(payload.states)
+                .map(|(id, state)| match remap.get(id as usize) {
+                    Some(material) => Voxel::new(*material).with_state(state),
+                    None => Voxel::EMPTY_VOXEL,
+                })
+                .collect(),
+        }));
+    }
+
+    let payload = decode_payload(bytes)?;
+    let y = payload.dims[1];
+    let saved = payload.into_buffer(materials)?;
+
+    // chunks saved with a different height keep their bottom part.
+    let mut buffer = VoxelBuffer::<Voxel, ChunkShape>::new_empty(ChunkShape {});
+    ndcopy::copy3(
+        [CHUNK_LENGTH, y.min(CHUNK_HEIGHT), CHUNK_LENGTH],
+        saved.slice(),
+        saved.shape(),
+        [0; 3],
+        buffer.slice_mut(),
+        &ChunkShape {},
+        [0; 3],
+    );
+
+    Ok(SavedChunk::Full(buffer))
+}
+
+/// Checks that a saved chunk can be decoded, without needing the registered materials.
+pub fn verify_chunk(bytes: &[u8]) -> Result<()> {
+    if decode_delta_payload(bytes)?.is_some() {
+        return Ok(());
+    }
+    decode_payload(bytes)?.check()
+}
+
+/// Reads the payload of a chunk saved as a delta, or returns `None` if it was saved in full.
+fn decode_delta_payload(bytes: &[u8]) -> Result<Option<DeltaPayload>> {
+    let Some((version, payload)) = read_container(bytes, DELTA_MAGIC)? else {
+        return Ok(None);
+    };
+    if version != DELTA_FORMAT_VERSION {
+        bail!("unsupported chunk delta format version {}", version);
+    }
+
+    let payload: DeltaPayload = bincode::deserialize(&payload)?;
+    payload.check()?;
+    Ok(Some(payload))
+}
+
+/// Reads the payload of a chunk saved with any format version, upgraded to the current one.
+fn decode_payload(bytes: &[u8]) -> Result<VoxelPayload> {
+    let (version, mut payload) = match read_container(bytes, CHUNK_MAGIC)? {
+        Some(container) => container,
+        None => (1, zstd::decode_all(bytes)?),
+    };
+
+    if version == 0 || version > CHUNK_FORMAT_VERSION {
+        bail!("unsupported chunk format version {}", version);
+    }
+
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        payload = migration(payload)?;
+    }
+
+    let payload: VoxelPayload = bincode::deserialize(&payload)?;
+    let [x, _, z] = payload.dims;
+    if x != CHUNK_LENGTH || z != CHUNK_LENGTH {
+        bail!("chunk is {}x{} voxels wide, expected {}x{}", x, z, CHUNK_LENGTH, CHUNK_LENGTH);
+    }
+
+    Ok(payload)
+}
+
+/// Writes a payload behind a header made of the specified magic, format version and the CRC of the compressed payload.
+pub(super) fn write_container(magic: [u8; 4], version: u16, payload: &impl Serialize) -> Result<Vec<u8>> {
+    let encoded_payload = bincode::serialize(payload)?;
+    let compressed_payload = zstd::encode_all(encoded_payload.as_slice(), COMPRESSION_LEVEL)?;
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + compressed_payload.len());
+    bytes.extend_from_slice(&magic);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&compressed_payload).to_le_bytes());
+    bytes.extend_from_slice(&compressed_payload);
+    Ok(bytes)
+}
+
+/// Checks the header written by [`write_container`] and returns the format version along with the decompressed payload,
+/// or `None` if the bytes don't start with the specified magic.
+pub(super) fn read_container(bytes: &[u8], magic: [u8; 4]) -> Result<Option<(u16, Vec<u8>)>> {
+    let Some(rest) = bytes.strip_prefix(&magic) else {
+        return Ok(None);
+    };
+    if rest.len() < HEADER_SIZE - magic.len() {
+        bail!("truncated header");
+    }
+
+    let version = u16::from_le_bytes([rest[0], rest[1]]);
+    let crc = u32::from_le_bytes([rest[2], rest[3], rest[4], rest[5]]);
+    let compressed_payload = &rest[6..];
+    if crc32fast::hash(compressed_payload) != crc {
+        bail!("checksum mismatch");
+    }
+
+    Ok(Some((version, zstd::decode_all(compressed_payload)?)))
+}
+
+/// Version 1 payloads were the bincode encoded voxel data alone, using the hardcoded material IDs of the time.
+fn migrate_v1_to_v2(payload: Vec<u8>) -> Result<Vec<u8>> {
+    let voxels: Vec<u8> = bincode::deserialize(&payload)?;
+    Ok(bincode::serialize(&ChunkPayloadV2 {
+        dims: [32, 256, 32],
+        materials: V1_MATERIAL_NAMES.iter().map(|x| x.to_string()).collect(),
+        voxels,
+    })?)
+}
+
+/// Version 2 payloads stored 8 bit material indices and no voxel state.
+fn migrate_v2_to_v3(payload: Vec<u8>) -> Result<Vec<u8>> {
+    let payload: ChunkPayloadV2 = bincode::deserialize(&payload)?;
+    Ok(bincode::serialize(&VoxelPayload {
+        dims: payload.dims,
+        materials: payload.materials,
+        states: vec![0; payload.voxels.len()],
+        voxels: payload.voxels.into_iter().map(u16::from).collect(),
+    })?)
+}
@@ -8,7 +8,7 @@ use bevy::{
 };
 use float_ord::FloatOrd;
 
-use super::{player::PlayerController, Chunk, ChunkShape, CHUNK_LENGTH};
+use super::{player::PlayerController, saving::ChunkSaveQueue, Chunk, ChunkShape, WorldSettings, CHUNK_HEIGHT, CHUNK_LENGTH};
 use crate::{voxel::storage::ChunkMap, AppState};
 use crate::voxel::Voxel;
 
@@ -16,7 +16,7 @@ use crate::voxel::Voxel;
 pub fn get_chunk_for_pos(pos: Vec3) -> Vec3 {
     Vec3::new(
         pos.x.div_euclid(CHUNK_LENGTH as f32) * CHUNK_LENGTH as f32,
-        0f32,
+        pos.y.div_euclid(CHUNK_HEIGHT as f32) * CHUNK_HEIGHT as f32,
         pos.z.div_euclid(CHUNK_LENGTH as f32) * CHUNK_LENGTH as f32,
     )
 }
@@ -44,39 +44,37 @@ fn update_view_chunks(
     player_pos: Res<CurrentLocalPlayerChunk>,
     chunk_entities: Res<ChunkEntities>,
     view_radius: Res<ChunkLoadRadius>,
+    world_settings: Res<WorldSettings>,
     mut chunk_command_queue: ResMut<ChunkCommandQueue>,
 ) {
-    // quick n dirty circular chunk loading.
+    // quick n dirty cylindrical chunk loading.
     //perf: optimize this.
     for x in -view_radius.horizontal..view_radius.horizontal {
         for z in -view_radius.horizontal..view_radius.horizontal {
-            // for y in -view_radius.vertical..view_radius.vertical {
-                if x.pow(2) + z.pow(2) >= view_radius.horizontal.pow(2) {
+            if x.pow(2) + z.pow(2) >= view_radius.horizontal.pow(2) {
+                continue;
+            }
+
+            for y in -view_radius.vertical..=view_radius.vertical {
+                let chunk_key: IVec3 = player_pos.chunk_min
+                    + IVec3::new(
+                        x * CHUNK_LENGTH as i32,
+                        y * CHUNK_HEIGHT as i32,
+                        z * CHUNK_LENGTH as i32,
+                    );
+
+                if !world_settings.contains_chunk(chunk_key) {
                     continue;
                 }
 
-                let chunk_key = {
-                    let pos: IVec3 = player_pos.chunk_min
-                        + IVec3::new(
-                            x * CHUNK_LENGTH as i32,
-                            // y * CHUNK_HEIGHT as i32,
-                            0,
-                            z * CHUNK_LENGTH as i32,
-                        );
-
-                    // pos.y = pos.y.max(0);
-
-                    pos
-                };
-
                 if chunk_entities.entity(chunk_key).is_none() {
                     chunk_command_queue.create.push(chunk_key);
                 }
-            // }
+            }
         }
     }
 
-    // quick n dirty circular chunk !loading.
+    // quick n dirty cylindrical chunk !loading.
     for loaded_chunk in chunk_entities.0.keys() {
         let delta: IVec3 = *loaded_chunk - player_pos.chunk_min;
 
@@ -84,7 +82,7 @@ fn update_view_chunks(
         #[allow(clippy::suspicious_operation_groupings)]
         if delta.x.pow(2) + delta.z.pow(2)
             > view_radius.horizontal.pow(2) * (CHUNK_LENGTH as i32).pow(2)
-            // || delta.y.pow(2) > view_radius.vertical.pow(2) * (CHUNK_HEIGHT as i32).pow(2)
+            || delta.y.abs() > view_radius.vertical * CHUNK_HEIGHT as i32
         {
             chunk_command_queue.destroy.push(*loaded_chunk);
         }
@@ -118,21 +116,28 @@ fn create_chunks(
         );
 }
 
+/// Destroys the requested chunks, queuing the modified ones to be saved.
 fn destroy_chunks(
     mut chunks_command_queue: ResMut<ChunkCommandQueue>,
     mut chunks: ResMut<ChunkMap<Voxel, ChunkShape>>,
     mut chunk_entities: ResMut<ChunkEntities>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut save_queue: ResMut<ChunkSaveQueue>,
     mut cmds: Commands,
 ) {
     for command in chunks_command_queue.destroy.drain(..) {
         cmds.entity(chunk_entities.detach_entity(command).unwrap())
             .despawn();
-        chunks.remove(command);
+        if let Some(buffer) = chunks.remove(command) {
+            if dirty_chunks.take_modified(command) {
+                save_queue.queue(command, buffer);
+            }
+        }
     }
 }
 
 fn clear_dirty_chunks(mut dirty_chunks: ResMut<DirtyChunks>) {
-    dirty_chunks.0.clear();
+    dirty_chunks.dirty.clear();
 }
 
 /// Label for the stage housing the chunk loading systems.
@@ -173,22 +178,57 @@ impl ChunkEntities {
     }
 }
 
-/// Holds the dirty chunk for the current frame.
+/// Holds the dirty chunk for the current frame, along with the chunks modified since they were last saved.
 #[derive(Default, Resource)]
-pub struct DirtyChunks(HashSet<IVec3>);
+pub struct DirtyChunks {
+    dirty: HashSet<IVec3>,
+    modified: HashSet<IVec3>,
+}
 
 #[allow(dead_code)]
 impl DirtyChunks {
+    /// Marks a chunk whose voxels were modified, it gets remeshed and saved.
     pub fn mark_dirty(&mut self, chunk: IVec3) {
-        self.0.insert(chunk);
+        self.dirty.insert(chunk);
+        self.modified.insert(chunk);
+    }
+
+    pub fn mark_all_dirty(&mut self, chunks: impl IntoIterator<Item = IVec3>) {
+        for chunk in chunks {
+            self.mark_dirty(chunk);
+        }
+    }
+
+    /// Marks a chunk to be remeshed without saving it, e.g. when it was just loaded or generated.
+    pub fn mark_loaded(&mut self, chunk: IVec3) {
+        self.dirty.insert(chunk);
     }
 
     pub fn iter_dirty(&self) -> impl Iterator<Item = &IVec3> {
-        self.0.iter()
+        self.dirty.iter()
     }
 
     pub fn num_dirty(&self) -> usize {
-        self.0.len()
+        self.dirty.len()
+    }
+
+    /// Marks a chunk as modified since it was last saved without remeshing it.
+    pub fn mark_modified(&mut self, chunk: IVec3) {
+        self.modified.insert(chunk);
+    }
+
+    /// Unmarks a modified chunk, returning whether it was modified since it was last saved.
+    pub fn take_modified(&mut self, chunk: IVec3) -> bool {
+        self.modified.remove(&chunk)
+    }
+
+    /// Unmarks all the modified chunks, returning them.
+    pub fn drain_modified(&mut self) -> impl Iterator<Item = IVec3> + '_ {
+        self.modified.drain()
+    }
+
+    pub fn num_modified(&self) -> usize {
+        self.modified.len()
     }
 }
 
@@ -203,7 +243,8 @@ pub struct CurrentLocalPlayerChunk {
 #[derive(Resource)]
 pub struct ChunkLoadRadius {
     pub horizontal: i32,
-    // pub vertical: i32,
+    /// Number of chunks loaded above and below the player chunk.
+    pub vertical: i32,
 }
 
 /// A queue tracking the creation / destroy commands for chunks.
@@ -223,7 +264,7 @@ impl Plugin for VoxelWorldChunkingPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource::<ChunkLoadRadius>(ChunkLoadRadius {
             horizontal: 8,
-            // vertical: 1,
+            vertical: 1,
         })
         .init_resource::<ChunkEntities>()
         .insert_resource(CurrentLocalPlayerChunk {
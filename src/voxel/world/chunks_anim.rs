@@ -29,7 +29,8 @@ fn attach_chunk_animation(
             // });
             if let Ok((mut transform, mut visibility, chunk)) = ready_chunks.get_mut(entity) {
                 *visibility = Visibility::Visible;
-                transform.translation.y = chunk.0.y as f32;
+                // meshes are built from padded buffers, so they're offset by one voxel like on spawn.
+                transform.translation.y = chunk.0.y as f32 - 1.0;
             };
         }
     });
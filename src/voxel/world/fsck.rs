@@ -0,0 +1,177 @@
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use bevy::math::IVec3;
+
+use super::{
+    chunk_format,
+    region::{self, RegionFile},
+    saved_worlds::{world_dir, WorldLock},
+    terrain::{quarantine_chunk, quarantine_dir, TEMP_FILE_SUFFIX},
+};
+
+/// A saved chunk which can't be loaded.
+pub struct DamagedChunk {
+    pub key: IVec3,
+    pub error: String,
+}
+
+/// What was found when checking the saved data of a world, and repaired if asked to.
+#[derive(Default)]
+pub struct FsckReport {
+    pub saves_dir: PathBuf,
+    pub repaired: bool,
+    pub regions: usize,
+    pub chunks: usize,
+    pub damaged_chunks: Vec<DamagedChunk>,
+    /// Keys of the chunks whose region table entry is invalid, their data can't be located.
+    pub invalid_entries: Vec<IVec3>,
+    /// Region files which can't be opened at all, along with the error.
+    pub unreadable_regions: Vec<(PathBuf, String)>,
+    /// Temporary files left behind by interrupted writes.
+    pub temp_files: Vec<PathBuf>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.damaged_chunks.is_empty()
+            && self.invalid_entries.is_empty()
+            && self.unreadable_regions.is_empty()
+            && self.temp_files.is_empty()
+    }
+}
+
+impl Display for FsckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "checked {} chunks in {} regions of {}", self.chunks, self.regions, self.saves_dir.display())?;
+        for chunk in &self.damaged_chunks {
+            writeln!(f, "damaged chunk {}: {}", chunk.key, chunk.error)?;
+        }
+        for key in &self.invalid_entries {
+            writeln!(f, "invalid region entry for chunk {}", key)?;
+        }
+        for (path, error) in &self.unreadable_regions {
+            writeln!(f, "unreadable region file {}: {}", path.display(), error)?;
+        }
+        for path in &self.temp_files {
+            writeln!(f, "leftover temporary file {}", path.display())?;
+        }
+
+        if self.is_clean() {
+            write!(f, "no problem found")
+        } else if self.repaired {
+            write!(f, "repaired, damaged data was moved to {}", self.saves_dir.join("quarantine").display())
+        } else {
+            write!(f, "run again with --repair to fix these problems")
+        }
+    }
+}
+
+/// Checks every chunk saved for the specified world, see [`fsck_dir`].
+pub fn fsck_world(world_name: &str, repair: bool) -> Result<FsckReport> {
+    let saves_dir = world_dir(world_name)?;
+    // repairs write to the regions, which the process the world is open in may be doing as well.
+    let _lock = repair.then(|| WorldLock::acquire(&saves_dir)).transpose()?;
+    fsck_dir(&saves_dir, repair)
+}
+
+/// Checks the region files of a world saves directory, which must not be in use by the game.
+///
+/// Without `repair`, nothing is modified. Otherwise damaged chunks and unreadable region files are moved to the
+/// quarantine directory so they get generated again, invalid region entries are cleared and temporary files removed.
+pub fn fsck_dir(saves_dir: &Path, repair: bool) -> Result<FsckReport> {
+    let mut report = FsckReport {
+        saves_dir: saves_dir.to_path_buf(),
+        repaired: repair,
+        ..Default::default()
+    };
+
+    let mut paths = std::fs::read_dir(saves_dir)?
+        .map(|x| x.map(|x| x.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort_unstable();
+
+    for path in paths {
+        if path.to_str().is_some_and(|x| x.ends_with(TEMP_FILE_SUFFIX)) {
+            if repair {
+                std::fs::remove_file(&path)?;
+            }
+            report.temp_files.push(path);
+        } else if let Some(region) = region::parse_region_path(&path) {
+            report.regions += 1;
+            check_region(saves_dir, &path, region, repair, &mut report)?;
+        }
+    }
+
+    Ok(report)
+}
+
+fn check_region(saves_dir: &Path, path: &Path, region: IVec3, repair: bool, report: &mut FsckReport) -> Result<()> {
+    // always checked read only first, opening a region for writing fixes its length.
+    let mut region_file = match RegionFile::open_read_only(path) {
+        Ok(region_file) => region_file,
+        Err(e) => {
+            if repair {
+                quarantine_file(saves_dir, path)?;
+            }
+            report.unreadable_regions.push((path.to_path_buf(), e.to_string()));
+            return Ok(());
+        }
+    };
+
+    let invalid_entries: Vec<_> = region_file.invalid_entries().collect();
+    report
+        .invalid_entries
+        .extend(invalid_entries.iter().map(|local| region::chunk_key(region, *local)));
+
+    let mut damaged = Vec::new();
+    for local in region_file.chunks().collect::<Vec<_>>() {
+        report.chunks += 1;
+        let key = region::chunk_key(region, local);
+        match region_file.read_chunk(local) {
+            Ok(Some(bytes)) => {
+                if let Err(e) = chunk_format::verify_chunk(&bytes) {
+                    report.damaged_chunks.push(DamagedChunk { key, error: e.to_string() });
+                    damaged.push((local, Some(bytes)));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                report.damaged_chunks.push(DamagedChunk { key, error: e.to_string() });
+                damaged.push((local, None));
+            }
+        }
+    }
+
+    if repair && (!damaged.is_empty() || !invalid_entries.is_empty()) {
+        drop(region_file);
+        let mut region_file = RegionFile::open(path)?;
+        for (local, bytes) in damaged {
+            if let Some(bytes) = bytes {
+                quarantine_chunk(saves_dir, region::chunk_key(region, local), &bytes)?;
+            }
+            region_file.remove_chunk(local)?;
+        }
+        for local in invalid_entries {
+            if let Some(bytes) = region_file.read_invalid_chunk(local)? {
+                quarantine_chunk(saves_dir, region::chunk_key(region, local), &bytes)?;
+            }
+            region_file.clear_invalid_entry(local)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves a whole file to the quarantine directory.
+fn quarantine_file(saves_dir: &Path, path: &Path) -> Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", timestamp));
+    std::fs::rename(path, quarantine_dir(saves_dir)?.join(file_name))?;
+    Ok(())
+}
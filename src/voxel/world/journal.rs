@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    ecs::system::Resource,
+    log::warn,
+    math::IVec3,
+    utils::HashSet,
+};
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+
+use super::{ChunkShape, DirtyChunks};
+use crate::voxel::{storage::ChunkMap, Voxel};
+
+/// Default memory budget of the [`EditJournal`], enough for roughly a million voxel edits.
+pub const DEFAULT_JOURNAL_BUDGET: usize = 16 * 1024 * 1024;
+
+/// A single voxel write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxelEdit {
+    pub position: IVec3,
+    pub old: Voxel,
+    pub new: Voxel,
+}
+
+/// A group of voxel writes undone / redone at once, in the order they were made.
+#[derive(Clone, Debug, Default)]
+pub struct EditTransaction {
+    edits: Vec<VoxelEdit>,
+}
+
+impl EditTransaction {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Returns the approximate number of bytes used by this transaction.
+    #[inline]
+    pub fn heap_size(&self) -> usize {
+        self.edits.capacity() * std::mem::size_of::<VoxelEdit>()
+    }
+}
+
+/// Records the voxel writes made to the [`ChunkMap`] so they can be undone and redone.
+///
+/// Writes are grouped into transactions: everything recorded between [`EditJournal::begin`] and [`EditJournal::commit`]
+/// is undone as a whole. Recording a new transaction clears the redo history.
+/// The oldest transactions are forgotten once the journal uses more than its memory budget,
+/// and the whole history when a single transaction exceeds it.
+#[derive(Resource)]
+pub struct EditJournal {
+    undo: VecDeque<EditTransaction>,
+    redo: Vec<EditTransaction>,
+    current: Option<EditTransaction>,
+    budget: usize,
+    used: usize,
+}
+
+impl Default for EditJournal {
+    fn default() -> Self {
+        Self::new(DEFAULT_JOURNAL_BUDGET)
+    }
+}
+
+#[allow(dead_code)]
+impl EditJournal {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            undo: Default::default(),
+            redo: Default::default(),
+            current: None,
+            budget,
+            used: 0,
+        }
+    }
+
+    /// Starts a new transaction, committing the pending one if any.
+    pub fn begin(&mut self) {
+        self.commit();
+        self.current = Some(EditTransaction::default());
+    }
+
+    /// Closes the pending transaction, making it the next one to undo.
+    pub fn commit(&mut self) {
+        let Some(mut transaction) = self.current.take() else {
+            return;
+        };
+        if transaction.is_empty() {
+            return;
+        }
+
+        transaction.edits.shrink_to_fit();
+        let size = transaction.heap_size();
+        if size > self.budget {
+            // older transactions would restore voxels this edit changed without being recorded.
+            warn!(
+                "edit of {} voxels exceeds the journal memory budget, it and the previous edits can't be undone",
+                transaction.edits.len()
+            );
+            self.clear();
+            return;
+        }
+
+        self.clear_redo();
+        self.used += size;
+        self.undo.push_back(transaction);
+        self.trim();
+    }
+
+    /// Records a voxel write in the pending transaction, starting one if needed.
+    pub fn record(&mut self, position: IVec3, old: Voxel, new: Voxel) {
+        if old != new {
+            self.current
+                .get_or_insert_with(Default::default)
+                .edits
+                .push(VoxelEdit { position, old, new });
+        }
+    }
+
+    /// Same as [`ChunkMap::apply_extent`], recording every voxel changed.
+    pub fn apply_extent(
+        &mut self,
+        chunks: &mut ChunkMap<Voxel, ChunkShape>,
+        extent: Extent<ILIVec3>,
+        mut f: impl FnMut(IVec3, &mut Voxel),
+    ) -> HashSet<IVec3> {
+        chunks.apply_extent(extent, |pos, voxel| {
+            let old = *voxel;
+            f(pos, voxel);
+            self.record(pos, old, *voxel);
+        })
+    }
+
+    /// Same as [`ChunkMap::fill_extent`], recording every voxel changed.
+    pub fn fill_extent(
+        &mut self,
+        chunks: &mut ChunkMap<Voxel, ChunkShape>,
+        extent: Extent<ILIVec3>,
+        val: Voxel,
+    ) -> HashSet<IVec3> {
+        self.apply_extent(chunks, extent, |_, voxel| *voxel = val)
+    }
+
+    /// Same as [`ChunkMap::replace_where`], recording every voxel changed.
+    pub fn replace_where(
+        &mut self,
+        chunks: &mut ChunkMap<Voxel, ChunkShape>,
+        extent: Extent<ILIVec3>,
+        mut predicate: impl FnMut(IVec3, Voxel) -> bool,
+        val: Voxel,
+    ) -> HashSet<IVec3> {
+        self.apply_extent(chunks, extent, |pos, voxel| {
+            if predicate(pos, *voxel) {
+                *voxel = val;
+            }
+        })
+    }
+
+    /// Reverts the last transaction and marks the chunks it touched as dirty.
+    /// Voxels in chunks which aren't loaded anymore are left untouched.
+    /// Returns false if there was nothing to undo.
+    pub fn undo(&mut self, chunks: &mut ChunkMap<Voxel, ChunkShape>, dirty_chunks: &mut DirtyChunks) -> bool {
+        self.commit();
+        let Some(transaction) = self.undo.pop_back() else {
+            return false;
+        };
+
+        dirty_chunks.mark_all_dirty(Self::write(
+            chunks,
+            transaction.edits.iter().rev().map(|edit| (edit.position, edit.old)),
+        ));
+        self.redo.push(transaction);
+        true
+    }
+
+    /// Reapplies the last undone transaction and marks the chunks it touched as dirty.
+    /// Returns false if there was nothing to redo.
+    pub fn redo(&mut self, chunks: &mut ChunkMap<Voxel, ChunkShape>, dirty_chunks: &mut DirtyChunks) -> bool {
+        self.commit();
+        let Some(transaction) = self.redo.pop() else {
+            return false;
+        };
+
+        dirty_chunks.mark_all_dirty(Self::write(
+            chunks,
+            transaction.edits.iter().map(|edit| (edit.position, edit.new)),
+        ));
+        self.undo.push_back(transaction);
+        true
+    }
+
+    #[inline]
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.current.as_ref().is_some_and(|x| !x.is_empty())
+    }
+
+    #[inline]
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forgets the whole history, e.g. when switching worlds.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.current = None;
+        self.used = 0;
+    }
+
+    /// Changes the memory budget, forgetting the oldest transactions if it's now exceeded.
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        if self.used > self.budget {
+            self.clear_redo();
+        }
+        self.trim();
+    }
+
+    /// Returns the approximate number of bytes used by the recorded transactions.
+    #[inline]
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    /// Forgets the oldest transactions until the memory budget is met.
+    fn trim(&mut self) {
+        while self.used > self.budget {
+            let Some(oldest) = self.undo.pop_front() else {
+                break;
+            };
+            self.used -= oldest.heap_size();
+        }
+    }
+
+    fn clear_redo(&mut self) {
+        self.used -= self.redo.drain(..).map(|x| x.heap_size()).sum::<usize>();
+    }
+
+    fn write(
+        chunks: &mut ChunkMap<Voxel, ChunkShape>,
+        voxels: impl Iterator<Item = (IVec3, Voxel)>,
+    ) -> HashSet<IVec3> {
+        let shape_mask = chunks.shape_mask();
+        voxels
+            .filter_map(|(position, val)| {
+                let mut voxel = chunks.voxel_at_mut(position)?;
+                *voxel = val;
+                Some(position & shape_mask)
+            })
+            .collect()
+    }
+}
@@ -1,7 +1,11 @@
-use bevy::{prelude::{Color, Plugin}, pbr::StandardMaterial};
+use bevy::{prelude::{Color, Plugin, PreStartup, Res, ResMut}, pbr::StandardMaterial};
 
+use super::saved_worlds::WorldMeta;
 use crate::{
-    voxel::material::{MaterialRegistryInfo, VoxelMaterialFlags, VoxelMaterialRegistry},
+    voxel::{
+        formats::vox::register_color_material,
+        material::{MaterialRegistryInfo, VoxelMaterialFlags, VoxelMaterialRegistry},
+    },
     voxel_material,
 };
 
@@ -24,11 +28,21 @@ pub struct VoxelWorldBaseMaterialsPlugin;
 
 impl Plugin for VoxelWorldBaseMaterialsPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        let mut registry = app
-            .world
-            .get_resource_mut::<VoxelMaterialRegistry>()
-            .unwrap();
+        Self::register_base_materials(&mut app.world.get_resource_mut::<VoxelMaterialRegistry>().unwrap());
+        app.add_systems(PreStartup, register_model_materials);
+    }
+}
+
+/// Registers the materials given to the colours of the models imported into the world, after the base ones.
+fn register_model_materials(meta: Res<WorldMeta>, mut registry: ResMut<VoxelMaterialRegistry>) {
+    for color in &meta.model_colors {
+        register_color_material(&mut registry, *color);
+    }
+}
 
+impl VoxelWorldBaseMaterialsPlugin {
+    /// Registers the materials of the terrain generator, in the order of their IDs.
+    pub fn register_base_materials(registry: &mut VoxelMaterialRegistry) {
         registry.register_material::<Void>(MaterialRegistryInfo {
             base_color: Color::BLACK,
             name: "Void",
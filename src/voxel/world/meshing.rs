@@ -2,18 +2,19 @@ use std::cell::RefCell;
 
 use super::{
     chunks::{ChunkEntities, ChunkLoadingSet, DirtyChunks},
-    terrain::{TerrainGenSet, save_chunk_to_disk},
-    Chunk, ChunkShape, Voxel, CHUNK_LENGTH, CHUNK_HEIGHT, WorldSettings,
+    terrain::TerrainGenSet,
+    Chunk, ChunkShape, Voxel, CHUNK_LENGTH, CHUNK_HEIGHT,
 };
 use crate::{voxel::{
-    render::{mesh_buffer, ChunkMaterialSingleton, MeshBuffers},
-    storage::ChunkMap,
+    render::{mesh_sectioned_buffer, ChunkMaterialSingleton, MeshBuffers},
+    storage::{ChunkMap, ChunkSection, SectionShape, SectionedVoxelBuffer},
 }, AppState, MyAssets};
 use bevy::{
     pbr::NotShadowCaster,
     prelude::*,
     render::{primitives::Aabb, render_resource::PrimitiveTopology, texture::{ImageSampler, ImageSamplerDescriptor}},
     tasks::{AsyncComputeTaskPool, Task},
+    utils::HashSet,
 };
 use futures_lite::future;
 use once_cell::sync::Lazy;
@@ -58,7 +59,7 @@ pub fn prepare_chunks(
 }
 
 // a pool of mesh buffers shared between meshing tasks.
-static SHARED_MESH_BUFFERS: Lazy<ThreadLocal<RefCell<MeshBuffers<Voxel, ChunkShape>>>> =
+static SHARED_MESH_BUFFERS: Lazy<ThreadLocal<RefCell<MeshBuffers<Voxel, SectionShape>>>> =
     Lazy::new(ThreadLocal::default);
 
 /// Queues meshing tasks for the chunks in need of a remesh.
@@ -67,40 +68,50 @@ fn queue_mesh_tasks(
     dirty_chunks: Res<DirtyChunks>,
     chunk_entities: Res<ChunkEntities>,
     chunks: Res<ChunkMap<Voxel, ChunkShape>>,
-    world_settings: Res<WorldSettings>,
 ) {
     let task_pool = AsyncComputeTaskPool::get();
 
-    let name = world_settings.name;
-
-    let mesh_gen = |buffer, key, name| {
-        let _ = save_chunk_to_disk(&buffer, key, name);
-
+    let mesh_gen = |buffer: SectionedVoxelBuffer<Voxel, ChunkShape>,
+                    below: Option<ChunkSection<Voxel>>,
+                    above: Option<ChunkSection<Voxel>>| {
         let mut mesh_buffers = SHARED_MESH_BUFFERS
         .get_or(|| {
-            RefCell::new(MeshBuffers::<Voxel, ChunkShape>::new(ChunkShape {}))
+            RefCell::new(MeshBuffers::<Voxel, SectionShape>::new(SectionShape {}))
         })
         .borrow_mut();
 
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-        mesh_buffer(&buffer, &mut mesh_buffers, &mut mesh, 1.0);
+        mesh_sectioned_buffer(&buffer, below.as_ref(), above.as_ref(), &mut mesh_buffers, &mut mesh, 1.0);
 
         mesh
     };
 
-    dirty_chunks
+    // the boundary layers of a chunk show through the faces of its vertical neighbours, so they get remeshed too.
+    let vertical_offset = IVec3::new(0, CHUNK_HEIGHT as i32, 0);
+    let to_mesh: HashSet<IVec3> = dirty_chunks
         .iter_dirty()
-        .filter_map(|key| chunk_entities.entity(*key).map(|entity| (key, entity)))
+        .flat_map(|key| [*key, *key - vertical_offset, *key + vertical_offset])
+        .collect();
+
+    to_mesh
+        .into_iter()
+        .filter_map(|key| chunk_entities.entity(key).map(|entity| (key, entity)))
         .filter_map(|(key, entity)| {
             chunks
-                .buffer_at(*key)
-                .map(|buffer| (buffer.clone(), entity, *key))
+                .buffer_at(key)
+                .map(|buffer| (buffer.clone(), entity, key))
         })
         .map(|(buffer, entity, key)| {
+            let below = chunks
+                .buffer_at(key - vertical_offset)
+                .and_then(|buffer| buffer.sections().last().cloned());
+            let above = chunks
+                .buffer_at(key + vertical_offset)
+                .and_then(|buffer| buffer.sections().first().cloned());
             (
                 entity,
                 ChunkMeshingTask(task_pool.spawn(async move {
-                    mesh_gen(buffer, key, name)
+                    mesh_gen(buffer, below, above)
                 })),
             )
         })
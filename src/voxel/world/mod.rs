@@ -14,17 +14,75 @@ pub use chunks::{
 
 use bevy_vector_shapes::prelude::*;
 
+pub mod anvil;
+mod chunk_format;
 mod chunks_anim;
+pub mod fsck;
+pub mod journal;
 pub mod materials;
 mod meshing;
+pub mod offline;
 pub mod player;
+mod region;
+pub mod saved_worlds;
+pub mod saving;
+pub mod schematic;
 mod sky;
 mod terrain;
 
 #[derive(Resource)]
 pub struct WorldSettings {
     pub seed: i32,
-    pub name: &'static str,
+    /// Name of the directory the world is saved in, see [`saved_worlds`].
+    pub name: String,
+    /// Lowest buildable height, the world bottom border sits there. Must be a multiple of [`CHUNK_HEIGHT`].
+    pub min_height: i32,
+    /// Height above the highest buildable voxel. Must be a multiple of [`CHUNK_HEIGHT`].
+    pub max_height: i32,
+    /// Elevation image the terrain surface is taken from, noise is used when there's none.
+    pub heightmap: Option<terraingen::dem::DemSettings>,
+    /// Splines shaping the terrain surface from the noise layers.
+    pub terrain_splines: terraingen::spline::TerrainSplines,
+    pub terrain_mode: terraingen::TerrainMode,
+    /// Caves dug under the surface, the ground staying whole when there are none.
+    pub caves: Option<terraingen::caves::CaveSettings>,
+    pub save_mode: SaveMode,
+}
+
+/// How the modified chunks of a world are written to disk.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum SaveMode {
+    /// All the voxels of the chunks.
+    #[default]
+    Full,
+    /// Only the voxels which differ from the generated terrain, chunks being generated again when loaded.
+    /// Chunks differing too much are saved in full. The deltas only apply over the terrain of the generator version
+    /// they were saved with, so the world can't be opened by another one.
+    Delta,
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            name: "world".to_string(),
+            min_height: -(CHUNK_HEIGHT as i32),
+            max_height: 2 * CHUNK_HEIGHT as i32,
+            heightmap: None,
+            terrain_splines: Default::default(),
+            terrain_mode: Default::default(),
+            caves: Some(Default::default()),
+            save_mode: SaveMode::Full,
+        }
+    }
+}
+
+impl WorldSettings {
+    /// Checks whether the chunk with the specified key lies within the world height limits.
+    #[inline]
+    pub fn contains_chunk(&self, key: IVec3) -> bool {
+        key.y >= self.min_height && key.y < self.max_height
+    }
 }
 
 /// Registers all resources and systems for simulating and rendering an editable and interactive voxel world.
@@ -33,16 +91,15 @@ pub struct VoxelWorldPlugin;
 impl Plugin for VoxelWorldPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(ChunkMap::<Voxel, ChunkShape>::new(ChunkShape {}))
-            .insert_resource(WorldSettings {
-                seed: 0,
-                name: "world",
-            })
+            .init_resource::<WorldSettings>()
+            .init_resource::<journal::EditJournal>()
             .add_plugins(ShapePlugin::default())
             .add_plugins(chunks::VoxelWorldChunkingPlugin)
             .add_plugins(meshing::VoxelWorldMeshingPlugin)
             // ordering of plugin insertion matters here.
             .add_plugins(terraingen::TerrainGeneratorPlugin)
             .add_plugins(terrain::VoxelWorldTerrainGenPlugin)
+            .add_plugins(saving::VoxelWorldSavingPlugin)
             .add_plugins(super::material::VoxelMaterialPlugin)
             .add_plugins(super::render::ChunkMaterialPlugin)
             .add_plugins(materials::VoxelWorldBaseMaterialsPlugin)
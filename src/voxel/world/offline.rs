@@ -0,0 +1,120 @@
+use anyhow::{bail, Result};
+use bevy::{log::warn, math::IVec3, utils::HashSet};
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+
+use super::{
+    chunk_format::MaterialNames,
+    materials::VoxelWorldBaseMaterialsPlugin,
+    saved_worlds::{world_dir, WorldLock, WorldMeta},
+    saving::{open_world, SaveTarget},
+    terrain::{load_chunk, world_saves_dir},
+    ChunkShape, WorldSettings,
+};
+use crate::voxel::{formats::vox::register_color_material, material::VoxelMaterialRegistry, storage::ChunkMap, Voxel};
+
+/// Boxes loaded at once can't be larger than this along any axis, a box that size already takes a few thousand chunks.
+pub const MAX_BOX_SIZE: u32 = 1024;
+
+/// A saved world opened by the command line tools instead of the game, which must not be playing it meanwhile.
+pub struct OfflineWorld {
+    pub settings: WorldSettings,
+    pub meta: WorldMeta,
+    /// The base materials and those of the world, materials registered while it's open must be recorded
+    /// with [`OfflineWorld::record_new_materials`] before saving chunks.
+    pub registry: VoxelMaterialRegistry,
+    pub materials: MaterialNames,
+    /// The chunks loaded so far, see [`OfflineWorld::load_box`].
+    pub chunks: ChunkMap<Voxel, ChunkShape>,
+    /// Chunks whose saved data couldn't be read, they're never saved over it.
+    unsaveable: HashSet<IVec3>,
+    _lock: WorldLock,
+}
+
+impl OfflineWorld {
+    /// Opens an existing world the same way the game does.
+    pub fn open(name: &str) -> Result<Self> {
+        // a mistyped name mustn't make a new world.
+        world_dir(name)?;
+        let mut settings = WorldSettings {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let (meta, lock) = open_world(&mut settings)?;
+
+        let mut registry = VoxelMaterialRegistry::default();
+        VoxelWorldBaseMaterialsPlugin::register_base_materials(&mut registry);
+        for color in &meta.model_colors {
+            register_color_material(&mut registry, *color);
+        }
+        Ok(Self {
+            materials: MaterialNames::from_registry(&registry),
+            registry,
+            settings,
+            meta,
+            chunks: ChunkMap::new(ChunkShape {}),
+            unsaveable: Default::default(),
+            _lock: lock,
+        })
+    }
+
+    /// Loads the chunks overlapped by a world-space box within the world height limits, as the game would.
+    pub fn load_box(&mut self, extent: Extent<ILIVec3>) -> Result<()> {
+        if extent.shape.cmpgt(ILIVec3::splat(MAX_BOX_SIZE as i32)).any() {
+            bail!("boxes can't be larger than {} voxels along any axis, got {}", MAX_BOX_SIZE, extent.shape);
+        }
+
+        let keys: Vec<_> = self.chunks.chunk_keys(extent).collect();
+        for key in keys {
+            if !self.settings.contains_chunk(key) || self.chunks.exists(key) {
+                continue;
+            }
+
+            let loaded = load_chunk(key, &self.settings.name, self.settings.seed, self.settings.min_height, &self.materials);
+            if !loaded.saveable {
+                self.unsaveable.insert(key);
+            }
+            self.chunks.insert(key, loaded.voxels);
+        }
+        Ok(())
+    }
+
+    /// Records the materials registered since the world was opened, e.g. by model imports, in its metadata
+    /// so that the game registers them too.
+    pub fn record_new_materials(&mut self) -> Result<()> {
+        let known = self.materials.len();
+        let new_colors: Vec<[u8; 4]> = self
+            .registry
+            .iter_mats()
+            .skip(known)
+            .map(|x| x.base_color.as_rgba_f32().map(|x| (x * 255.0).round() as u8))
+            .collect();
+        if new_colors.is_empty() {
+            return Ok(());
+        }
+
+        self.meta.model_colors.extend(new_colors);
+        self.meta.write(&world_saves_dir(&self.settings.name)?)?;
+        self.materials = MaterialNames::from_registry(&self.registry);
+        Ok(())
+    }
+
+    /// Writes loaded chunks the same way the game does, returning how many were written.
+    /// Chunks whose saved data couldn't be read are left out with a warning.
+    pub fn save_chunks(&self, keys: impl IntoIterator<Item = IVec3>) -> Result<usize> {
+        let target = SaveTarget::new(&self.settings);
+        let mut saved = 0;
+        for key in keys {
+            let Some(buffer) = self.chunks.buffer_at(key) else {
+                continue;
+            };
+            if self.unsaveable.contains(&key) {
+                warn!("chunk {} of world {} couldn't be loaded, it's left as it was", key, self.settings.name);
+                continue;
+            }
+
+            target.save(buffer, key, &self.materials)?;
+            saved += 1;
+        }
+        Ok(saved)
+    }
+}
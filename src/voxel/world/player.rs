@@ -8,18 +8,18 @@ use bevy_vector_shapes::shapes::{DiscPainter, LinePainter, Cap, ThicknessType};
 
 use std::f32::consts::PI;
 
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+
 use crate::AppState;
 use crate::debug::{DebugUISet, DebugUIState};
 use crate::voxel::Voxel;
 use crate::voxel::material::{VoxelMaterial, VoxelMaterialRegistry};
-use crate::voxel::storage::ChunkMap;
-use crate::voxel::world::chunks::get_chunk_for_pos;
+use crate::voxel::storage::{ChunkMap, VoxelRayHit};
 
+use super::journal::EditJournal;
 use super::materials::{Rock, Void};
 use super::{ChunkShape, DirtyChunks};
 
-use bevy_mod_raycast::prelude::*;
-
 
 #[derive(Default, Component)]
 pub struct PlayerController {
@@ -66,7 +66,7 @@ pub fn handle_player_inputs(
     windows: Query<&mut Window>,
     mut chunks: ResMut<ChunkMap<Voxel, ChunkShape>>,
     mut dirty_chunks: ResMut<DirtyChunks>,
-    mut raycast: Raycast,
+    mut journal: ResMut<EditJournal>,
     mut materials: ResMut<VoxelMaterialRegistry>,
 
     // under this is only for debug/test purposes. don't forget to remove it later
@@ -165,16 +165,15 @@ pub fn handle_player_inputs(
     transform.translation += velocity * settings.speed * acceleration * time.delta_seconds();
 
     let direction = Quat::from_rotation_y(controller.yaw) * Quat::from_rotation_x(controller.pitch) * Vec3::new(0.0, 0.0, -1.0);
-    let ray = Ray3d::new(transform.translation, direction.normalize());
-    let hits = match raycast.debug_cast_ray(ray, &default(), &mut gizmos) {
-        &[(_entity, ref data), ..] => Some(data),
-        &[] => None,
-    };
-
-    let in_range_hit = |hit: &IntersectionData| match hit.distance() {
-        x if x < 10.0 => Some((hit.position(), hit.normal())),
-        _ => None,
-    };
+    let hit = chunks.raycast(transform.translation, direction, 10.0);
+
+    // outline the voxel the player is looking at
+    if let Some(hit) = hit {
+        gizmos.cuboid(
+            Transform::from_translation(hit.position.as_vec3() + Vec3::splat(0.5)).with_scale(Vec3::splat(1.002)),
+            Color::ORANGE,
+        );
+    }
 
     // // draw square on the face of the block the player is looking at
     // if let Some((pos, normal)) = hits.and_then(in_range_hit) {
@@ -237,62 +236,53 @@ pub fn handle_player_inputs(
     if mouse_buttons.just_pressed(MouseButton::Right) && window.cursor.grab_mode != CursorGrabMode::None {
         // thanks to Zatmos (https://www.zatmos.xyz) for the monadic style
 
-        let place_block = |(pos, normal)| {
-            let pos = pos + normal * 0.5;
-
-            let chunk_pos = get_chunk_for_pos(pos);
-
-            let pos_in_chunk = pos - chunk_pos - Vec3::Y;
-
-            chunks.buffer_at_mut(chunk_pos.as_ivec3())
-            .map(|buffer| {
-                if buffer.voxel_at([
-                    pos_in_chunk.x as u32,
-                    pos_in_chunk.y as u32,
-                    pos_in_chunk.z as u32,
-                ].into()) != Void::into_voxel() {
-                    return;
-                }
-                *buffer.voxel_at_mut([
-                    pos_in_chunk.x as u32,
-                    pos_in_chunk.y as u32,
-                    pos_in_chunk.z as u32,
-                ].into()) = Voxel(debug_ui_state.selected_mat);
-            }).and_then(|_| {
-                dirty_chunks.mark_dirty(chunk_pos.as_ivec3());
-                Some(())
-            });
+        let place_block = |hit: VoxelRayHit<Voxel>| {
+            let pos = hit.position + hit.normal;
+
+            journal.begin();
+            let touched = journal.replace_where(
+                &mut chunks,
+                Extent::from_min_and_shape(ILIVec3::from(pos.to_array()), ILIVec3::ONE),
+                |_, voxel| voxel == Void::into_voxel(),
+                Voxel::new(debug_ui_state.selected_mat),
+            );
+            journal.commit();
+            dirty_chunks.mark_all_dirty(touched);
         };
 
-        hits.and_then(in_range_hit)
-            .map(place_block);
+        hit.map(place_block);
     }
 
     if mouse_buttons.just_pressed(MouseButton::Left) && window.cursor.grab_mode != CursorGrabMode::None {
         // thanks to Zatmos (https://www.zatmos.xyz) for the monadic style
 
-        let remove_block = |(pos, normal)| {
-            let pos = pos - normal * 0.5;
-
-            let chunk_pos = get_chunk_for_pos(pos);
-
-            let pos_in_chunk = pos - chunk_pos;
-
-            chunks.buffer_at_mut(chunk_pos.as_ivec3())
-            .map(|buffer| {
-                *buffer.voxel_at_mut([
-                    pos_in_chunk.x as u32,
-                    (pos_in_chunk.y - 1.) as u32,
-                    pos_in_chunk.z as u32,
-                ].into()) = Void::into_voxel();
-            }).and_then(|_| {
-                dirty_chunks.mark_dirty(chunk_pos.as_ivec3());
-                Some(())
-            });
+        let remove_block = |hit: VoxelRayHit<Voxel>| {
+            let pos = hit.position;
+
+            journal.begin();
+            let touched = journal.fill_extent(
+                &mut chunks,
+                Extent::from_min_and_shape(ILIVec3::from(pos.to_array()), ILIVec3::ONE),
+                Void::into_voxel(),
+            );
+            journal.commit();
+            dirty_chunks.mark_all_dirty(touched);
         };
 
-        hits.and_then(in_range_hit)
-            .map(remove_block);
+        hit.map(remove_block);
+    }
+
+    // alt + z to undo the last edit, alt + y or alt + shift + z to redo it.
+    // not ctrl, left ctrl already moves the player down.
+    if window.cursor.grab_mode != CursorGrabMode::None
+        && keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight])
+    {
+        let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        if keys.just_pressed(KeyCode::Z) && !shift {
+            journal.undo(&mut chunks, &mut dirty_chunks);
+        } else if keys.just_pressed(KeyCode::Y) || (keys.just_pressed(KeyCode::Z) && shift) {
+            journal.redo(&mut chunks, &mut dirty_chunks);
+        }
     }
 
 }
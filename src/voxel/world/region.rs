@@ -0,0 +1,466 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bevy::{
+    log::warn,
+    math::{IVec2, IVec3},
+    utils::HashMap,
+};
+use once_cell::sync::Lazy;
+
+use super::{CHUNK_HEIGHT, CHUNK_LENGTH};
+
+/// Number of chunks stored along each horizontal axis of a region file.
+/// Region files are one chunk high, vertically stacked chunks are stored in different files.
+pub const REGION_LENGTH: i32 = 32;
+const REGION_CHUNKS: usize = (REGION_LENGTH * REGION_LENGTH) as usize;
+
+/// Region files are allocated in sectors of this many bytes.
+const SECTOR_SIZE: u64 = 4096;
+/// Each header entry is the first sector (u32) and byte length (u32) of a chunk, both little endian.
+const HEADER_ENTRY_SIZE: u64 = 8;
+const HEADER_SECTORS: u32 = (REGION_CHUNKS as u64 * HEADER_ENTRY_SIZE).div_ceil(SECTOR_SIZE) as u32;
+
+const REGION_EXTENSION: &str = "region";
+const LEGACY_CHUNK_EXTENSION: &str = "chunk";
+
+#[derive(Clone, Copy, Default, Debug)]
+struct RegionEntry {
+    sector: u32,
+    length: u32,
+}
+
+impl RegionEntry {
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    #[inline]
+    fn sector_count(&self) -> u32 {
+        sectors_for(self.length as u64)
+    }
+}
+
+#[inline]
+fn sectors_for(length: u64) -> u32 {
+    length.div_ceil(SECTOR_SIZE) as u32
+}
+
+/// A file holding the saved data of [`REGION_LENGTH`]x[`REGION_LENGTH`] chunks.
+///
+/// The file starts with a table of offsets / lengths indexed by the chunk position within the region,
+/// followed by the chunk payloads, each one occupying a run of consecutive sectors.
+/// Chunks are never rewritten in place: a new payload goes to the first free run of sectors large enough
+/// (or is appended at the end of the file) and is synced to disk before the table entry is switched over to it,
+/// so a crash in the middle of a write leaves the previous payload intact.
+pub struct RegionFile {
+    file: File,
+    entries: Box<[RegionEntry]>,
+    used_sectors: Vec<bool>,
+    /// Table entries pointing out of the file or at sectors already used by another chunk, ignored when reading.
+    /// Their chunks can't be written until they're cleared, which must only be done once their data was kept aside.
+    invalid_entries: Vec<(usize, RegionEntry)>,
+}
+
+impl RegionFile {
+    /// Opens the region file at the specified path, creating it if it doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let header_size = HEADER_SECTORS as u64 * SECTOR_SIZE;
+        if file.metadata()?.len() < header_size {
+            file.set_len(header_size)?;
+        }
+
+        Self::read_header(file, path)
+    }
+
+    /// Opens an existing region file without ever modifying it, e.g. to check it.
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() < HEADER_SECTORS as u64 * SECTOR_SIZE {
+            bail!("region file {:?} is shorter than its header", path);
+        }
+
+        Self::read_header(file, path)
+    }
+
+    fn read_header(mut file: File, path: &Path) -> Result<Self> {
+        let mut header = vec![0u8; REGION_CHUNKS * HEADER_ENTRY_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+
+        let file_sectors = sectors_for(file.metadata()?.len());
+        let mut used_sectors = vec![false; file_sectors as usize];
+        used_sectors[..HEADER_SECTORS as usize].fill(true);
+
+        let mut entries = vec![RegionEntry::default(); REGION_CHUNKS].into_boxed_slice();
+        let mut invalid_entries = Vec::new();
+        for (index, entry) in entries.iter_mut().enumerate() {
+            let raw = &header[index * HEADER_ENTRY_SIZE as usize..][..HEADER_ENTRY_SIZE as usize];
+            let read = RegionEntry {
+                sector: u32::from_le_bytes(raw[0..4].try_into().unwrap()),
+                length: u32::from_le_bytes(raw[4..8].try_into().unwrap()),
+            };
+
+            if read.is_empty() {
+                continue;
+            }
+
+            let sectors = read.sector..read.sector + read.sector_count();
+            if read.sector < HEADER_SECTORS
+                || sectors.end > file_sectors
+                || used_sectors[sectors.start as usize..sectors.end as usize].iter().any(|x| *x)
+            {
+                warn!("ignoring invalid chunk entry {} in region file {:?}", index, path);
+                invalid_entries.push((index, read));
+                continue;
+            }
+
+            used_sectors[sectors.start as usize..sectors.end as usize].fill(true);
+            *entry = read;
+        }
+
+        Ok(Self {
+            file,
+            entries,
+            used_sectors,
+            invalid_entries,
+        })
+    }
+
+    /// Returns the positions within the region of the chunks which were written.
+    pub fn chunks(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !entry.is_empty())
+            .map(|(index, _)| IVec2::new(index as i32 % REGION_LENGTH, index as i32 / REGION_LENGTH))
+    }
+
+    /// Returns the positions within the region of the table entries which were ignored when opening the file.
+    pub fn invalid_entries(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.invalid_entries
+            .iter()
+            .map(|(index, _)| IVec2::new(*index as i32 % REGION_LENGTH, *index as i32 / REGION_LENGTH))
+    }
+
+    /// Reads what's left of the payload of a table entry ignored when opening the file, the part of it lying past
+    /// the end of the file being left out. `None` if the entry at the specified position wasn't ignored.
+    pub fn read_invalid_chunk(&mut self, local: IVec2) -> Result<Option<Vec<u8>>> {
+        let index = Self::entry_index(local)?;
+        let Some((_, entry)) = self.invalid_entries.iter().find(|(x, _)| *x == index).copied() else {
+            return Ok(None);
+        };
+
+        let start = entry.sector as u64 * SECTOR_SIZE;
+        let end = (start + entry.length as u64).min(self.file.metadata()?.len());
+        let mut data = vec![0u8; end.saturating_sub(start) as usize];
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Clears a table entry ignored when opening the file, once its payload was kept aside,
+    /// so that the chunk at the specified position can be written again.
+    pub fn clear_invalid_entry(&mut self, local: IVec2) -> Result<()> {
+        let index = Self::entry_index(local)?;
+        if let Some(position) = self.invalid_entries.iter().position(|(x, _)| *x == index) {
+            self.invalid_entries.remove(position);
+            self.write_entry(index)?;
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the payload of the chunk at the specified position within the region, if it was ever written.
+    pub fn read_chunk(&mut self, local: IVec2) -> Result<Option<Vec<u8>>> {
+        let entry = self.entries[Self::entry_index(local)?];
+        if entry.is_empty() {
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; entry.length as usize];
+        self.file
+            .seek(SeekFrom::Start(entry.sector as u64 * SECTOR_SIZE))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Writes the payload of the chunk at the specified position within the region.
+    pub fn write_chunk(&mut self, local: IVec2, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return self.remove_chunk(local);
+        }
+
+        let index = self.writable_entry_index(local)?;
+        let previous = self.entries[index];
+        let required = sectors_for(data.len() as u64);
+
+        // the previous sectors are still in use at this point, so the new payload never overwrites them.
+        let sector = self.allocate(required);
+        self.file.seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))?;
+        self.file.write_all(data)?;
+        // pad the last sector so the file length always stays a multiple of the sector size.
+        let padding = (required as u64 * SECTOR_SIZE - data.len() as u64) as usize;
+        self.file.write_all(&vec![0u8; padding])?;
+        self.file.sync_data()?;
+
+        // entries are 8 bytes aligned on 8 bytes, their write can't be torn.
+        self.entries[index] = RegionEntry {
+            sector,
+            length: data.len() as u32,
+        };
+        self.write_entry(index)?;
+        self.file.sync_data()?;
+
+        self.release(previous.sector, previous.sector_count());
+        Ok(())
+    }
+
+    /// Removes the chunk at the specified position within the region, freeing its sectors.
+    pub fn remove_chunk(&mut self, local: IVec2) -> Result<()> {
+        let index = self.writable_entry_index(local)?;
+        let previous = self.entries[index];
+        if previous.is_empty() {
+            return Ok(());
+        }
+
+        self.release(previous.sector, previous.sector_count());
+        self.entries[index] = RegionEntry::default();
+        self.write_entry(index)
+    }
+
+    #[inline]
+    fn entry_index(local: IVec2) -> Result<usize> {
+        if local.cmplt(IVec2::ZERO).any() || local.cmpge(IVec2::splat(REGION_LENGTH)).any() {
+            bail!("chunk position {} is out of the region bounds", local);
+        }
+        Ok((local.y * REGION_LENGTH + local.x) as usize)
+    }
+
+    /// Index of an entry which isn't an ignored one, whose payload would be lost when writing over it.
+    fn writable_entry_index(&self, local: IVec2) -> Result<usize> {
+        let index = Self::entry_index(local)?;
+        if self.invalid_entries.iter().any(|(x, _)| *x == index) {
+            bail!("chunk {} has an invalid entry whose data wasn't kept aside yet", local);
+        }
+        Ok(index)
+    }
+
+    /// Finds the first run of free sectors large enough, growing the file when there isn't any.
+    fn allocate(&mut self, count: u32) -> u32 {
+        let count = count as usize;
+        let mut run_start = 0;
+        let mut run_length = 0;
+        for (sector, used) in self.used_sectors.iter().enumerate() {
+            if *used {
+                run_length = 0;
+                continue;
+            }
+            if run_length == 0 {
+                run_start = sector;
+            }
+            run_length += 1;
+            if run_length == count {
+                break;
+            }
+        }
+
+        if run_length < count {
+            // no large enough gap, extend the trailing free run (if any) past the end of the file.
+            run_start = self.used_sectors.len() - run_length;
+            self.used_sectors.resize(run_start + count, false);
+        }
+
+        self.used_sectors[run_start..run_start + count].fill(true);
+        run_start as u32
+    }
+
+    fn release(&mut self, sector: u32, count: u32) {
+        if count > 0 {
+            self.used_sectors[sector as usize..(sector + count) as usize].fill(false);
+        }
+    }
+
+    fn write_entry(&mut self, index: usize) -> Result<()> {
+        let entry = self.entries[index];
+        let mut raw = [0u8; HEADER_ENTRY_SIZE as usize];
+        raw[0..4].copy_from_slice(&entry.sector.to_le_bytes());
+        raw[4..8].copy_from_slice(&entry.length.to_le_bytes());
+
+        self.file
+            .seek(SeekFrom::Start(index as u64 * HEADER_ENTRY_SIZE))?;
+        self.file.write_all(&raw)?;
+        Ok(())
+    }
+}
+
+/// Returns the position of the region containing the specified chunk, and the chunk position within that region.
+#[inline]
+pub fn region_pos(chunk_key: IVec3) -> (IVec3, IVec2) {
+    let chunk_pos = IVec2::new(chunk_key.x, chunk_key.z) / CHUNK_LENGTH as i32;
+    (
+        IVec3::new(
+            chunk_pos.x.div_euclid(REGION_LENGTH),
+            chunk_key.y.div_euclid(CHUNK_HEIGHT as i32),
+            chunk_pos.y.div_euclid(REGION_LENGTH),
+        ),
+        IVec2::new(
+            chunk_pos.x.rem_euclid(REGION_LENGTH),
+            chunk_pos.y.rem_euclid(REGION_LENGTH),
+        ),
+    )
+}
+
+/// Returns the key of a chunk from the position of its region and its position within that region,
+/// the inverse of [`region_pos`].
+#[inline]
+pub fn chunk_key(region: IVec3, local: IVec2) -> IVec3 {
+    let chunk_pos = IVec2::new(region.x, region.z) * REGION_LENGTH + local;
+    IVec3::new(
+        chunk_pos.x * CHUNK_LENGTH as i32,
+        region.y * CHUNK_HEIGHT as i32,
+        chunk_pos.y * CHUNK_LENGTH as i32,
+    )
+}
+
+#[inline]
+pub fn region_path(saves_dir: &Path, region: IVec3) -> PathBuf {
+    saves_dir.join(format!("r.{}.{}.{}.{}", region.x, region.y, region.z, REGION_EXTENSION))
+}
+
+/// Returns the position of the region stored at the specified path, if it's named like [`region_path`] names them.
+pub fn parse_region_path(path: &Path) -> Option<IVec3> {
+    if path.extension().and_then(|x| x.to_str()) != Some(REGION_EXTENSION) {
+        return None;
+    }
+
+    let mut coords = path.file_stem()?.to_str()?.strip_prefix("r.")?.split('.');
+    let region = IVec3::new(
+        coords.next()?.parse().ok()?,
+        coords.next()?.parse().ok()?,
+        coords.next()?.parse().ok()?,
+    );
+    coords.next().is_none().then_some(region)
+}
+
+// region files are shared by all the terrain / meshing tasks, so they're kept open behind a lock each
+// until they're closed by [`close_region_files`].
+static OPEN_REGIONS: Lazy<Mutex<HashMap<PathBuf, Arc<Mutex<RegionFile>>>>> =
+    Lazy::new(Default::default);
+
+/// Runs the specified closure on the region file at the specified path, opening it if it isn't already.
+pub fn with_region_file<T>(path: &Path, f: impl FnOnce(&mut RegionFile) -> Result<T>) -> Result<T> {
+    let region = {
+        // the map itself is never left half updated.
+        let mut open_regions = OPEN_REGIONS.lock().unwrap_or_else(PoisonError::into_inner);
+        match open_regions.get(path) {
+            Some(region) => region.clone(),
+            None => {
+                let region = Arc::new(Mutex::new(RegionFile::open(path)?));
+                open_regions.insert(path.to_path_buf(), region.clone());
+                region
+            }
+        }
+    };
+
+    // the region may have been left midway through a write, it's not used again until it's closed and read anew.
+    let mut region = region
+        .lock()
+        .map_err(|_| anyhow!("region file {:?} is unusable, an earlier access to it panicked", path))?;
+    f(&mut region)
+}
+
+/// Closes the open region files of a saves directory which no task is using, except those `keep` returns true for.
+/// Returns the number of files closed.
+pub fn close_region_files(saves_dir: &Path, mut keep: impl FnMut(IVec3) -> bool) -> usize {
+    let mut open_regions = OPEN_REGIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    let count = open_regions.len();
+    // regions are only handed out while the map is locked, so unshared ones can't be picked up meanwhile.
+    open_regions.retain(|path, region| {
+        path.parent() != Some(saves_dir)
+            || Arc::strong_count(region) > 1
+            || parse_region_path(path).is_some_and(&mut keep)
+    });
+    count - open_regions.len()
+}
+
+/// Migrates the `{x}.{z}.chunk` files of saves made before region files into regions, removing them once copied.
+/// Returns the number of chunks migrated.
+pub fn convert_chunk_files(saves_dir: &Path) -> Result<usize> {
+    if !saves_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut converted = 0;
+    for dir_entry in std::fs::read_dir(saves_dir)? {
+        let path = dir_entry?.path();
+        if path.extension().and_then(|x| x.to_str()) != Some(LEGACY_CHUNK_EXTENSION) {
+            continue;
+        }
+
+        let Some(key) = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.split_once('.'))
+            .and_then(|(x, z)| Some(IVec3::new(x.parse().ok()?, 0, z.parse().ok()?)))
+        else {
+            warn!("skipping unrecognized chunk file {:?}", path);
+            continue;
+        };
+
+        // the payload format is unchanged, only the container is.
+        let data = std::fs::read(&path)?;
+        let (region, local) = region_pos(key);
+        with_region_file(&region_path(saves_dir, region), |region| {
+            region.write_chunk(local, &data)
+        })?;
+        std::fs::remove_file(&path)?;
+        converted += 1;
+    }
+
+    Ok(converted)
+}
+
+/// Renames the `r.{x}.{z}.region` files written before chunks were stacked vertically to the region layer at height 0.
+/// Returns the number of region files renamed.
+pub fn convert_flat_region_files(saves_dir: &Path) -> Result<usize> {
+    if !saves_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut converted = 0;
+    for dir_entry in std::fs::read_dir(saves_dir)? {
+        let path = dir_entry?.path();
+        if path.extension().and_then(|x| x.to_str()) != Some(REGION_EXTENSION) {
+            continue;
+        }
+
+        let Some(region) = path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_prefix("r."))
+            .and_then(|x| x.split_once('.'))
+            .and_then(|(x, z)| Some(IVec3::new(x.parse().ok()?, 0, z.parse().ok()?)))
+        else {
+            continue;
+        };
+
+        std::fs::rename(&path, region_path(saves_dir, region))?;
+        converted += 1;
+    }
+
+    Ok(converted)
+}
@@ -0,0 +1,294 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bevy::prelude::Resource;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    chunk_format::CHUNK_FORMAT_VERSION,
+    terrain::{quarantine_dir, saved_worlds_dir, write_file_atomically},
+    WorldSettings,
+};
+use crate::voxel::terraingen::{
+    caves::CaveSettings, dem::DemSettings, spline::TerrainSplines, TerrainMode, GENERATOR_ID, GENERATOR_VERSION,
+};
+
+/// Name of the metadata file of each world saves directory.
+pub const META_FILE_NAME: &str = "world.meta";
+/// Name of the file locked by the process a world is open in, see [`WorldLock`].
+pub const LOCK_FILE_NAME: &str = "world.lock";
+/// Version of the metadata written by [`WorldMeta::write`].
+pub const META_FORMAT_VERSION: u32 = 1;
+/// Where players appear in a new world.
+pub const DEFAULT_SPAWN: [f32; 3] = [2.0, 180.0, 2.0];
+
+/// What a world was generated with, along with its progress, saved as JSON in [`META_FILE_NAME`].
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+pub struct WorldMeta {
+    pub format_version: u32,
+    /// Version of the chunk format the world was last saved with.
+    pub chunk_format_version: u16,
+    pub seed: i32,
+    pub generator_id: String,
+    pub generator_version: u16,
+    pub min_height: i32,
+    pub max_height: i32,
+    /// Elevation image the terrain surface is taken from, worlds saved before heightmaps existed used noise.
+    #[serde(default)]
+    pub heightmap: Option<DemSettings>,
+    /// Worlds saved before the splines were recorded were shaped by the default ones.
+    #[serde(default)]
+    pub terrain_splines: TerrainSplines,
+    /// Worlds saved before terrain modes existed were generated from heights.
+    #[serde(default)]
+    pub terrain_mode: TerrainMode,
+    /// Worlds saved before caves existed have none.
+    #[serde(default)]
+    pub caves: Option<CaveSettings>,
+    /// Colours of the imported models which no material was close enough to, each one having been given a material
+    /// which is registered again whenever the world is opened.
+    #[serde(default)]
+    pub model_colors: Vec<[u8; 4]>,
+    /// Whether chunks were ever saved as deltas, which only apply over the terrain of [`WorldMeta::generator_version`].
+    /// Worlds saved before it was recorded may have been, deltas being the default save mode then.
+    #[serde(default = "saved_deltas_before_recorded")]
+    pub delta_saves: bool,
+    /// Unix timestamps, in seconds.
+    pub created: u64,
+    pub last_played: u64,
+    /// Where the player was when the world was last saved.
+    pub spawn: [f32; 3],
+    /// In-game time spent in the world, in seconds.
+    pub game_time: f64,
+}
+
+impl WorldMeta {
+    /// Makes the metadata of a new world generated with the current generator.
+    pub fn new(seed: i32, min_height: i32, max_height: i32) -> Self {
+        let now = unix_timestamp();
+        Self {
+            format_version: META_FORMAT_VERSION,
+            chunk_format_version: CHUNK_FORMAT_VERSION,
+            seed,
+            generator_id: GENERATOR_ID.to_string(),
+            generator_version: GENERATOR_VERSION,
+            min_height,
+            max_height,
+            heightmap: None,
+            terrain_splines: TerrainSplines::default(),
+            terrain_mode: TerrainMode::default(),
+            caves: Some(CaveSettings::default()),
+            model_colors: Vec::new(),
+            delta_saves: false,
+            created: now,
+            last_played: now,
+            spawn: DEFAULT_SPAWN,
+            game_time: 0.0,
+        }
+    }
+
+    /// Makes the metadata of a world from the settings it's generated with, for new worlds
+    /// and worlds saved before metadata files existed.
+    pub fn from_settings(world_settings: &WorldSettings) -> Self {
+        Self {
+            heightmap: world_settings.heightmap.clone(),
+            terrain_splines: world_settings.terrain_splines.clone(),
+            terrain_mode: world_settings.terrain_mode.clone(),
+            caves: world_settings.caves.clone(),
+            ..Self::new(world_settings.seed, world_settings.min_height, world_settings.max_height)
+        }
+    }
+
+    /// Reads the metadata file of a world saves directory, `None` if there's none.
+    pub fn read(saves_dir: &Path) -> Result<Option<Self>> {
+        let path = saves_dir.join(META_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let meta: Self = serde_json::from_slice(&std::fs::read(&path)?)?;
+        if meta.format_version == 0 || meta.format_version > META_FORMAT_VERSION {
+            bail!("unsupported world metadata format version {}", meta.format_version);
+        }
+        Ok(Some(meta))
+    }
+
+    /// Writes the metadata file of a world saves directory, with the current format versions.
+    pub fn write(&mut self, saves_dir: &Path) -> Result<()> {
+        self.format_version = META_FORMAT_VERSION;
+        self.chunk_format_version = CHUNK_FORMAT_VERSION;
+        write_file_atomically(&saves_dir.join(META_FILE_NAME), &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Checks whether the world was generated by another generator than the current one.
+    pub fn generator_changed(&self) -> bool {
+        self.generator_id != GENERATOR_ID || self.generator_version != GENERATOR_VERSION
+    }
+}
+
+/// An exclusive advisory lock on a world saves directory, held while the world is open so that other processes
+/// neither open nor rename, delete or repair it meanwhile. Released when dropped.
+#[derive(Resource)]
+pub struct WorldLock {
+    _file: File,
+}
+
+impl WorldLock {
+    /// Locks the world saved in a directory, failing if another process holds its lock.
+    pub fn acquire(saves_dir: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(saves_dir.join(LOCK_FILE_NAME))?;
+        file.try_lock_exclusive()
+            .map_err(|_| anyhow!("world {:?} is open in another process", saves_dir))?;
+        Ok(Self { _file: file })
+    }
+}
+
+fn saved_deltas_before_recorded() -> bool {
+    true
+}
+
+/// A world found in the saves directory, `meta` being `None` for worlds saved before metadata files existed
+/// or whose metadata can't be read.
+pub struct SavedWorld {
+    pub name: String,
+    pub meta: Option<WorldMeta>,
+}
+
+/// Lists the saved worlds, the most recently played first.
+pub fn list_worlds() -> Result<Vec<SavedWorld>> {
+    let saves_dir = saved_worlds_dir();
+    if !saves_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut worlds = Vec::new();
+    for dir_entry in std::fs::read_dir(saves_dir)? {
+        let dir_entry = dir_entry?;
+        let Some(name) = dir_entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        if dir_entry.file_type()?.is_dir() {
+            worlds.push(SavedWorld {
+                meta: WorldMeta::read(&dir_entry.path()).ok().flatten(),
+                name,
+            });
+        }
+    }
+
+    worlds.sort_by(|a, b| {
+        let last_played = |x: &SavedWorld| x.meta.as_ref().map_or(0, |x| x.last_played);
+        last_played(b).cmp(&last_played(a)).then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(worlds)
+}
+
+/// Creates an empty world with the specified seed, using the default world height limits.
+pub fn create_world(name: &str, seed: i32) -> Result<WorldMeta> {
+    let defaults = WorldSettings::default();
+    let saves_dir = new_world_dir(name)?;
+    std::fs::create_dir_all(&saves_dir)?;
+
+    let mut meta = WorldMeta::new(seed, defaults.min_height, defaults.max_height);
+    meta.write(&saves_dir)?;
+    Ok(meta)
+}
+
+/// Renames a world, failing if it's open.
+pub fn rename_world(name: &str, new_name: &str) -> Result<()> {
+    let saves_dir = world_dir(name)?;
+    let new_saves_dir = new_world_dir(new_name)?;
+    // released before moving the directory, which can't be done with the lock file open on some platforms.
+    drop(WorldLock::acquire(&saves_dir)?);
+    std::fs::rename(saves_dir, new_saves_dir)?;
+    Ok(())
+}
+
+/// Copies a world and all of its saved chunks under another name, the copy being a new world with the same seed.
+pub fn duplicate_world(name: &str, new_name: &str) -> Result<()> {
+    let saves_dir = world_dir(name)?;
+    let new_saves_dir = new_world_dir(new_name)?;
+    let _lock = WorldLock::acquire(&saves_dir)?;
+    copy_dir(&saves_dir, &new_saves_dir)?;
+
+    if let Some(mut meta) = WorldMeta::read(&new_saves_dir)? {
+        meta.created = unix_timestamp();
+        meta.write(&new_saves_dir)?;
+    }
+    Ok(())
+}
+
+/// Deletes a world and all of its saved chunks, failing if it's open.
+pub fn delete_world(name: &str) -> Result<()> {
+    let saves_dir = world_dir(name)?;
+    // released before deleting the directory, which can't be done with the lock file open on some platforms.
+    drop(WorldLock::acquire(&saves_dir)?);
+    std::fs::remove_dir_all(saves_dir)?;
+    Ok(())
+}
+
+/// Moves the metadata file of a world saves directory to its quarantine directory, returning its new path.
+pub fn quarantine_meta(saves_dir: &Path) -> Result<PathBuf> {
+    let path = quarantine_dir(saves_dir)?.join(format!("{}.{}", META_FILE_NAME, unix_timestamp()));
+    std::fs::rename(saves_dir.join(META_FILE_NAME), &path)?;
+    Ok(path)
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Returns the saves directory of an existing world.
+pub(super) fn world_dir(name: &str) -> Result<PathBuf> {
+    check_world_name(name)?;
+    let saves_dir = saved_worlds_dir().join(name);
+    if !saves_dir.is_dir() {
+        bail!("there's no saved world named {:?}", name);
+    }
+    Ok(saves_dir)
+}
+
+/// Returns the saves directory of a world which doesn't exist yet.
+fn new_world_dir(name: &str) -> Result<PathBuf> {
+    check_world_name(name)?;
+    let saves_dir = saved_worlds_dir().join(name);
+    if saves_dir.exists() {
+        bail!("a world named {:?} already exists", name);
+    }
+    Ok(saves_dir)
+}
+
+/// World names are directory names, they can't hold path separators or characters most file systems forbid.
+pub(super) fn check_world_name(name: &str) -> Result<()> {
+    let forbidden = |x: char| x.is_control() || matches!(x, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|');
+    if name.trim().is_empty() || name == "." || name == ".." || name.ends_with('.') || name.contains(forbidden) {
+        bail!("invalid world name {:?}", name);
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for dir_entry in std::fs::read_dir(from)? {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+        if dir_entry.file_name() == LOCK_FILE_NAME {
+            continue;
+        }
+        if dir_entry.file_type()?.is_dir() {
+            copy_dir(&path, &to.join(dir_entry.file_name()))?;
+        } else {
+            std::fs::copy(&path, to.join(dir_entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
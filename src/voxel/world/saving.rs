@@ -0,0 +1,352 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    tasks::{IoTaskPool, Task},
+    utils::{HashMap, HashSet},
+};
+use futures_lite::future;
+
+use super::{
+    chunk_format::MaterialNames,
+    chunks::{ChunkEntities, DirtyChunks},
+    player::PlayerController,
+    region,
+    saved_worlds::{quarantine_meta, unix_timestamp, WorldLock, WorldMeta},
+    terrain::{save_chunk_delta_to_disk, save_chunk_to_disk, setup_terrain_generator, world_saves_dir},
+    ChunkShape, SaveMode, Voxel, WorldSettings,
+};
+use crate::voxel::terraingen::{GENERATOR_ID, GENERATOR_VERSION};
+use crate::{voxel::storage::{ChunkMap, SectionedVoxelBuffer}, AppState};
+
+/// Chunks waiting to be written to disk, or being written on the IO task pool.
+///
+/// A chunk is written by a single task at a time: snapshots queued while it's being written replace each other,
+/// and only the latest one is written once the previous write is done.
+#[derive(Default, Resource)]
+pub struct ChunkSaveQueue {
+    pending: HashMap<IVec3, SectionedVoxelBuffer<Voxel, ChunkShape>>,
+    in_flight: HashMap<IVec3, (SectionedVoxelBuffer<Voxel, ChunkShape>, Task<Result<()>>)>,
+    /// Snapshots whose write failed, queued again on the next autosave.
+    failed: HashMap<IVec3, SectionedVoxelBuffer<Voxel, ChunkShape>>,
+    /// Chunks whose saved data couldn't be read, writing them would overwrite it.
+    unsaveable: HashSet<IVec3>,
+}
+
+impl ChunkSaveQueue {
+    /// Queues the voxels of a chunk to be written, replacing any snapshot of it which isn't being written yet.
+    /// Chunks marked as unsaveable are left out.
+    pub fn queue(&mut self, key: IVec3, buffer: SectionedVoxelBuffer<Voxel, ChunkShape>) {
+        if !self.unsaveable.contains(&key) {
+            self.pending.insert(key, buffer);
+        }
+    }
+
+    /// Keeps a chunk from ever being written, e.g. because its saved data couldn't be read.
+    pub fn mark_unsaveable(&mut self, key: IVec3) {
+        self.pending.remove(&key);
+        self.failed.remove(&key);
+        self.unsaveable.insert(key);
+    }
+
+    /// Returns the latest voxels queued for a chunk, which may not be on disk yet.
+    pub fn unsaved(&self, key: IVec3) -> Option<&SectionedVoxelBuffer<Voxel, ChunkShape>> {
+        self.pending
+            .get(&key)
+            .or_else(|| self.in_flight.get(&key).map(|(buffer, _)| buffer))
+            .or_else(|| self.failed.get(&key))
+    }
+
+    /// Returns the number of chunks waiting to be written or being written.
+    pub fn len(&self) -> usize {
+        self.pending.len() + self.in_flight.len() + self.failed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.in_flight.is_empty() && self.failed.is_empty()
+    }
+
+    /// Returns the chunks waiting to be written or being written.
+    pub fn keys(&self) -> impl Iterator<Item = &IVec3> {
+        self.pending.keys().chain(self.in_flight.keys()).chain(self.failed.keys())
+    }
+
+    /// Queues the snapshots whose write failed again, unless a newer one was queued since.
+    fn retry_failed(&mut self) {
+        for (key, buffer) in self.failed.drain() {
+            self.pending.entry(key).or_insert(buffer);
+        }
+    }
+
+    /// Queues the loaded chunks modified since they were last saved, and retries the failed writes.
+    fn queue_modified(&mut self, chunks: &ChunkMap<Voxel, ChunkShape>, dirty_chunks: &mut DirtyChunks) {
+        self.retry_failed();
+        for key in dirty_chunks.drain_modified() {
+            if let Some(buffer) = chunks.buffer_at(key) {
+                self.queue(key, buffer.clone());
+            }
+        }
+    }
+
+    /// Writes every queued chunk, blocking until they're all on disk.
+    fn flush(&mut self, target: &SaveTarget, materials: &MaterialNames) {
+        for (key, (buffer, task)) in self.in_flight.drain() {
+            if let Err(e) = future::block_on(task) {
+                error!("failed to save chunk {} of world {}, retrying: {}", key, target.name, e);
+                self.failed.insert(key, buffer);
+            }
+        }
+        self.retry_failed();
+
+        for (key, buffer) in self.pending.drain() {
+            if let Err(e) = target.save(&buffer, key, materials) {
+                error!("failed to save chunk {} of world {}: {}", key, target.name, e);
+            }
+        }
+    }
+}
+
+/// The world settings chunks are saved with, copied into the saving tasks.
+#[derive(Clone)]
+pub(super) struct SaveTarget {
+    name: String,
+    seed: i32,
+    min_height: i32,
+    mode: SaveMode,
+}
+
+impl SaveTarget {
+    pub(super) fn new(world_settings: &WorldSettings) -> Self {
+        Self {
+            name: world_settings.name.clone(),
+            seed: world_settings.seed,
+            min_height: world_settings.min_height,
+            mode: world_settings.save_mode,
+        }
+    }
+
+    pub(super) fn save(&self, buffer: &SectionedVoxelBuffer<Voxel, ChunkShape>, key: IVec3, materials: &MaterialNames) -> Result<()> {
+        match self.mode {
+            SaveMode::Full => save_chunk_to_disk(&buffer.to_buffer(), key, &self.name, materials),
+            SaveMode::Delta => {
+                save_chunk_delta_to_disk(&buffer.to_buffer(), key, &self.name, self.seed, self.min_height, materials)
+            }
+        }
+    }
+}
+
+/// Interval at which the modified chunks are saved while they're loaded.
+#[derive(Resource)]
+pub struct AutosaveTimer(pub Timer);
+
+impl Default for AutosaveTimer {
+    fn default() -> Self {
+        Self(Timer::new(Duration::from_secs(60), TimerMode::Repeating))
+    }
+}
+
+/// Opens the world named in the settings before the app starts: the seed, height limits and generator settings
+/// of its metadata take over the settings, which the terrain generator is then set up from, and the metadata is written for worlds
+/// which don't have any yet.
+///
+/// The world stays locked against other processes until the returned lock is dropped.
+///
+/// Fails rather than write over metadata which couldn't be read nor kept aside, or when the terrain generator can't be set up.
+pub fn open_world(world_settings: &mut WorldSettings) -> Result<(WorldMeta, WorldLock)> {
+    let lock = WorldLock::acquire(&world_saves_dir(&world_settings.name)?)?;
+    let mut meta = match read_world_meta(&world_settings.name)? {
+        Some(mut meta) => {
+            if meta.generator_changed() && meta.delta_saves {
+                bail!(
+                    "world {} has chunks saved as deltas over the terrain of {} version {}, they can't be applied \
+                    over the terrain of {} version {}",
+                    world_settings.name, meta.generator_id, meta.generator_version, GENERATOR_ID, GENERATOR_VERSION
+                );
+            }
+            if meta.generator_changed() {
+                warn!(
+                    "world {} was generated by {} version {}, the terrain is now generated by {} version {}",
+                    world_settings.name, meta.generator_id, meta.generator_version, GENERATOR_ID, GENERATOR_VERSION
+                );
+            }
+            // the chunks generated from now on come from the current generator, the world is upgraded to it.
+            meta.generator_id = GENERATOR_ID.to_string();
+            meta.generator_version = GENERATOR_VERSION;
+            world_settings.seed = meta.seed;
+            world_settings.min_height = meta.min_height;
+            world_settings.max_height = meta.max_height;
+            world_settings.heightmap = meta.heightmap.clone();
+            world_settings.terrain_splines = meta.terrain_splines.clone();
+            world_settings.terrain_mode = meta.terrain_mode.clone();
+            world_settings.caves = meta.caves.clone();
+            meta
+        }
+        None => WorldMeta::from_settings(world_settings),
+    };
+    world_settings.terrain_splines.check()?;
+    setup_terrain_generator(world_settings)?;
+
+    meta.last_played = unix_timestamp();
+    meta.delta_saves |= world_settings.save_mode == SaveMode::Delta;
+    meta.write(&world_saves_dir(&world_settings.name)?)?;
+    Ok((meta, lock))
+}
+
+fn read_world_meta(world_name: &str) -> Result<Option<WorldMeta>> {
+    let saves_dir = world_saves_dir(world_name)?;
+    WorldMeta::read(&saves_dir).or_else(|e| {
+        // kept aside rather than overwritten, it may be the only record of the seed.
+        let path = quarantine_meta(&saves_dir)
+            .map_err(|x| anyhow!("its metadata is damaged ({}) and couldn't be moved aside: {}", e, x))?;
+        error!("the metadata of world {} is damaged ({}), it was moved to {:?}", world_name, e, path);
+        Ok(None)
+    })
+}
+
+/// Records where the player is as the spawn of the world, which is where they'll appear when it's played again.
+fn record_spawn(mut meta: ResMut<WorldMeta>, player: Query<&Transform, With<PlayerController>>) {
+    if let Ok(transform) = player.get_single() {
+        meta.spawn = transform.translation.to_array();
+    }
+}
+
+/// Writes the metadata of the world, marking it as played now.
+/// Also records whether the chunks are being saved as deltas, the metadata being written before the chunks are.
+fn write_world_meta(meta: &mut WorldMeta, world_settings: &WorldSettings) {
+    meta.last_played = unix_timestamp();
+    meta.delta_saves |= world_settings.save_mode == SaveMode::Delta;
+    if let Err(e) = world_saves_dir(&world_settings.name).and_then(|dir| meta.write(&dir)) {
+        error!("failed to write the metadata of world {}: {}", world_settings.name, e);
+    }
+}
+
+fn advance_game_time(time: Res<Time>, mut meta: ResMut<WorldMeta>) {
+    meta.game_time += time.delta_seconds_f64();
+}
+
+/// Queues the modified chunks to be saved and writes the world metadata every time the autosave timer finishes.
+fn autosave(
+    mut timer: ResMut<AutosaveTimer>,
+    time: Res<Time>,
+    chunks: Res<ChunkMap<Voxel, ChunkShape>>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut save_queue: ResMut<ChunkSaveQueue>,
+    mut meta: ResMut<WorldMeta>,
+    world_settings: Res<WorldSettings>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        save_queue.queue_modified(&chunks, &mut dirty_chunks);
+        write_world_meta(&mut meta, &world_settings);
+    }
+}
+
+/// Polls the chunk writes and starts writing the queued chunks which aren't already being written.
+fn write_queued_chunks(
+    mut save_queue: ResMut<ChunkSaveQueue>,
+    world_settings: Res<WorldSettings>,
+    materials: Res<MaterialNames>,
+) {
+    let target = SaveTarget::new(&world_settings);
+    let ChunkSaveQueue {
+        pending,
+        in_flight,
+        failed,
+        ..
+    } = &mut *save_queue;
+
+    let finished: Vec<(IVec3, Result<()>)> = in_flight
+        .iter_mut()
+        .filter_map(|(key, (_, task))| future::block_on(future::poll_once(task)).map(|result| (*key, result)))
+        .collect();
+    for (key, result) in finished {
+        let (buffer, _) = in_flight.remove(&key).unwrap();
+        if let Err(e) = result {
+            // the snapshot is kept until it's written, the chunk may not be loaded anymore.
+            error!("failed to save chunk {} of world {}, retrying on the next autosave: {}", key, target.name, e);
+            failed.insert(key, buffer);
+        }
+    }
+
+    let task_pool = IoTaskPool::get();
+    let ready: Vec<IVec3> = pending.keys().filter(|key| !in_flight.contains_key(*key)).copied().collect();
+    for key in ready {
+        let buffer = pending.remove(&key).unwrap();
+        let snapshot = buffer.clone();
+        let materials = materials.clone();
+        let target = target.clone();
+        let task = task_pool.spawn(async move { target.save(&snapshot, key, &materials) });
+        in_flight.insert(key, (buffer, task));
+    }
+}
+
+/// Saves every modified chunk and the world metadata before the app exits, blocking until they're written.
+fn flush_on_exit(
+    mut exit: EventReader<AppExit>,
+    chunks: Res<ChunkMap<Voxel, ChunkShape>>,
+    mut dirty_chunks: ResMut<DirtyChunks>,
+    mut save_queue: ResMut<ChunkSaveQueue>,
+    mut meta: ResMut<WorldMeta>,
+    world_settings: Res<WorldSettings>,
+    materials: Res<MaterialNames>,
+) {
+    if exit.read().last().is_none() {
+        return;
+    }
+
+    write_world_meta(&mut meta, &world_settings);
+
+    save_queue.queue_modified(&chunks, &mut dirty_chunks);
+    if !save_queue.is_empty() {
+        let count = save_queue.len();
+        save_queue.flush(&SaveTarget::new(&world_settings), &materials);
+        info!("saved {} chunks of world {} before exiting", count, world_settings.name);
+    }
+
+    if let Ok(saves_dir) = world_saves_dir(&world_settings.name) {
+        region::close_region_files(&saves_dir, |_| false);
+    }
+}
+
+/// Closes the region files none of the loaded chunks nor the chunks waiting to be written are stored in,
+/// e.g. once the last of their chunks was unloaded.
+fn close_unused_regions(
+    chunk_entities: Res<ChunkEntities>,
+    save_queue: Res<ChunkSaveQueue>,
+    world_settings: Res<WorldSettings>,
+) {
+    let Ok(saves_dir) = world_saves_dir(&world_settings.name) else {
+        return;
+    };
+
+    let used: HashSet<IVec3> = chunk_entities
+        .iter_keys()
+        .chain(save_queue.keys())
+        .map(|key| region::region_pos(*key).0)
+        .collect();
+    region::close_region_files(&saves_dir, |region| used.contains(&region));
+}
+
+/// Handles writing the modified chunks to disk in the background.
+pub struct VoxelWorldSavingPlugin;
+
+impl Plugin for VoxelWorldSavingPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<ChunkSaveQueue>()
+            .init_resource::<AutosaveTimer>()
+            .add_systems(
+                Update,
+                (
+                    advance_game_time,
+                    record_spawn,
+                    autosave,
+                    write_queued_chunks,
+                    close_unused_regions.run_if(resource_changed::<ChunkEntities>()),
+                )
+                    .chain()
+                    .run_if(in_state(AppState::InGame)),
+            )
+            .add_systems(Last, flush_on_exit);
+    }
+}
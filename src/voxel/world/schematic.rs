@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use bevy::{
+    math::{IVec3, UVec3},
+    utils::HashSet,
+};
+use directories::BaseDirs;
+use ilattice::{extent::Extent, glam::IVec3 as ILIVec3};
+use ndshape::{RuntimeShape, Shape};
+
+use super::{
+    chunk_format::{self, MaterialNames, VoxelPayload},
+    journal::EditJournal,
+    terrain::write_file_atomically,
+    ChunkShape,
+};
+use crate::voxel::{storage::{ChunkMap, VoxelBuffer}, Voxel};
+
+const SCHEMATIC_MAGIC: [u8; 4] = *b"YVSC";
+/// Version of the schematic files written by [`Schematic::save`].
+pub const SCHEMATIC_FORMAT_VERSION: u16 = 1;
+const SCHEMATIC_EXTENSION: &str = "schematic";
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    #[inline]
+    const fn index(self) -> usize {
+        match self {
+            Self::X => 0,
+            Self::Y => 1,
+            Self::Z => 2,
+        }
+    }
+
+    /// The two other axes, in the order a positive quarter turn around this axis sends the first onto the second.
+    #[inline]
+    const fn plane(self) -> (usize, usize) {
+        match self {
+            Self::X => (1, 2),
+            Self::Y => (2, 0),
+            Self::Z => (0, 1),
+        }
+    }
+}
+
+/// Whether pasting a schematic overwrites the world with its empty voxels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PasteMode {
+    /// Empty voxels of the schematic leave the world untouched.
+    #[default]
+    IgnoreAir,
+    /// The whole box covered by the schematic is overwritten.
+    WriteAir,
+}
+
+/// A standalone copy of a box of the world, which can be transformed and pasted elsewhere.
+/// Voxel states are copied as is, they aren't transformed along with the voxels.
+#[derive(Clone)]
+pub struct Schematic {
+    voxels: VoxelBuffer<Voxel, RuntimeShape<u32, 3>>,
+}
+
+#[allow(dead_code)]
+impl Schematic {
+    /// Copies the voxels of a world-space extent. Voxels in chunks which aren't loaded are copied as empty.
+    pub fn extract(chunks: &ChunkMap<Voxel, ChunkShape>, extent: Extent<ILIVec3>) -> Self {
+        let shape = RuntimeShape::<u32, 3>::new(extent.shape.max(ILIVec3::ZERO).as_uvec3().to_array());
+        let mut voxels = VoxelBuffer::new_empty(shape);
+
+        for pos in extent.iter3() {
+            let local = (pos - extent.minimum).as_uvec3();
+            *voxels.voxel_at_mut(local) = chunks
+                .voxel_at(IVec3::from(pos.to_array()))
+                .unwrap_or_default();
+        }
+
+        Self { voxels }
+    }
+
+    #[inline]
+    pub fn from_buffer(voxels: VoxelBuffer<Voxel, RuntimeShape<u32, 3>>) -> Self {
+        Self { voxels }
+    }
+
+    #[inline]
+    pub fn buffer(&self) -> &VoxelBuffer<Voxel, RuntimeShape<u32, 3>> {
+        &self.voxels
+    }
+
+    #[inline]
+    pub fn dims(&self) -> UVec3 {
+        UVec3::from(self.voxels.shape().as_array())
+    }
+
+    /// Rotates the schematic by the specified number of quarter turns around an axis, counter clockwise
+    /// when looking from the positive side of the axis. Negative turns rotate clockwise.
+    pub fn rotate(&mut self, axis: Axis, quarter_turns: i32) {
+        let (u, v) = axis.plane();
+        for _ in 0..quarter_turns.rem_euclid(4) {
+            let dims = self.voxels.shape().as_array();
+            let mut rotated_dims = dims;
+            rotated_dims.swap(u, v);
+
+            self.remap(rotated_dims, |pos| {
+                let mut rotated = pos;
+                rotated[u] = dims[v] - 1 - pos[v];
+                rotated[v] = pos[u];
+                rotated
+            });
+        }
+    }
+
+    /// Mirrors the schematic along an axis.
+    pub fn mirror(&mut self, axis: Axis) {
+        let axis = axis.index();
+        let dims = self.voxels.shape().as_array();
+
+        self.remap(dims, |pos| {
+            let mut mirrored = pos;
+            mirrored[axis] = dims[axis] - 1 - pos[axis];
+            mirrored
+        });
+    }
+
+    /// Pastes the schematic with its minimum corner at the specified world position, recording the edit in the journal.
+    /// Voxels falling in chunks which aren't loaded are skipped.
+    /// Returns the keys of the chunks in which at least one voxel changed.
+    pub fn paste(
+        &self,
+        chunks: &mut ChunkMap<Voxel, ChunkShape>,
+        journal: &mut EditJournal,
+        origin: IVec3,
+        mode: PasteMode,
+    ) -> HashSet<IVec3> {
+        journal.apply_extent(chunks, self.extent_at(origin), self.paste_voxel(origin, mode))
+    }
+
+    /// Same as [`Schematic::paste`] without recording the edit, for worlds which aren't being played.
+    pub fn paste_unrecorded(
+        &self,
+        chunks: &mut ChunkMap<Voxel, ChunkShape>,
+        origin: IVec3,
+        mode: PasteMode,
+    ) -> HashSet<IVec3> {
+        chunks.apply_extent(self.extent_at(origin), self.paste_voxel(origin, mode))
+    }
+
+    /// The world-space box covered by the schematic when pasted at the specified position.
+    pub fn extent_at(&self, origin: IVec3) -> Extent<ILIVec3> {
+        Extent::from_min_and_shape(
+            ILIVec3::from(origin.to_array()),
+            ILIVec3::from(self.dims().as_ivec3().to_array()),
+        )
+    }
+
+    /// Writes the voxel of the schematic pasted at `origin` over a voxel of the world.
+    fn paste_voxel(&self, origin: IVec3, mode: PasteMode) -> impl Fn(IVec3, &mut Voxel) + '_ {
+        move |pos, voxel| {
+            let val = self.voxels.voxel_at((pos - origin).as_uvec3().to_array().into());
+            if mode == PasteMode::WriteAir || val != Voxel::EMPTY_VOXEL {
+                *voxel = val;
+            }
+        }
+    }
+
+    /// Saves the schematic with the same container as chunks, see [`chunk_format`].
+    pub fn save(&self, path: &Path, materials: &MaterialNames) -> Result<()> {
+        let payload = VoxelPayload::from_voxels(self.voxels.shape().as_array(), self.voxels.slice(), materials);
+        let bytes = chunk_format::write_container(SCHEMATIC_MAGIC, SCHEMATIC_FORMAT_VERSION, &payload)?;
+        write_file_atomically(path, &bytes)?;
+        Ok(())
+    }
+
+    /// Loads a schematic, remapping its voxels to the current material IDs.
+    pub fn load(path: &Path, materials: &MaterialNames) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let Some((version, payload)) = chunk_format::read_container(&bytes, SCHEMATIC_MAGIC)? else {
+            bail!("{:?} isn't a schematic file", path);
+        };
+        if version == 0 || version > SCHEMATIC_FORMAT_VERSION {
+            bail!("unsupported schematic format version {}", version);
+        }
+
+        let payload: VoxelPayload = bincode::deserialize(&payload)?;
+        Ok(Self {
+            voxels: payload.into_buffer(materials)?,
+        })
+    }
+
+    /// Moves every voxel to the position returned by `f`, in a buffer of the specified dimensions.
+    fn remap(&mut self, dims: [u32; 3], f: impl Fn([u32; 3]) -> [u32; 3]) {
+        let shape = RuntimeShape::<u32, 3>::new(dims);
+        let mut remapped = VoxelBuffer::new_empty(shape.clone());
+
+        for (index, voxel) in self.voxels.slice().iter().enumerate() {
+            let pos = self.voxels.shape().delinearize(index as u32);
+            remapped.slice_mut()[shape.linearize(f(pos)) as usize] = *voxel;
+        }
+
+        self.voxels = remapped;
+    }
+}
+
+/// Returns the path of the schematic with the specified name, creating the schematics directory if needed.
+#[allow(dead_code)]
+pub fn schematic_path(name: &str) -> Result<PathBuf> {
+    if let Some(base_dirs) = BaseDirs::new() {
+        let dir = base_dirs.data_dir().join(".yavafg").join("schematics");
+        std::fs::create_dir_all(dir.as_path())?;
+        Ok(dir.join(format!("{}.{}", name, SCHEMATIC_EXTENSION)))
+    } else {
+        bail!("No valid directory path could be retrieved from the operating system.");
+    }
+}
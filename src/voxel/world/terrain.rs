@@ -1,109 +1,301 @@
-use std::path::PathBuf;
-use anyhow::Result;
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use anyhow::{anyhow, bail, Result};
 
 use super::{
     chunks::{ChunkLoadingSet, DirtyChunks},
-    Chunk, ChunkShape, WorldSettings,
+    chunk_format::{self, ChunkDelta, MaterialNames, SavedChunk},
+    region, saved_worlds::check_world_name, saving::ChunkSaveQueue, Chunk, ChunkShape, WorldSettings,
 };
 use crate::{voxel::{
-    storage::{ChunkMap, VoxelBuffer},
-    terraingen::TERRAIN_GENERATOR,
+    material::VoxelMaterialRegistry,
+    storage::{ChunkMap, SectionedVoxelBuffer, VoxelBuffer},
+    terraingen::{dem::DemHeightmap, SurfaceSource, GENERATOR_VERSION, TERRAIN_GENERATOR},
     Voxel,
 }, AppState};
 use bevy::{
     prelude::{
         Added, Commands, Component, Entity, IntoSystemConfigs, IntoSystemSetConfigs,
-        Plugin, Query, ResMut, SystemSet, Update,
+        Plugin, Query, ResMut, Startup, SystemSet, Update,
     },
-    tasks::{AsyncComputeTaskPool, Task}, ecs::{system::Res, schedule::common_conditions::in_state}, log::info, math::IVec3,
+    tasks::{AsyncComputeTaskPool, Task}, ecs::{system::Res, schedule::common_conditions::{in_state, resource_changed}}, log::{error, info}, math::IVec3,
 };
 use directories::BaseDirs;
+use ndshape::ConstShape;
 use futures_lite::future;
 
+/// Returns the directory holding the saved data of the specified world, creating it if needed.
+pub fn world_saves_dir(world_name: &str) -> Result<PathBuf> {
+    // names are joined onto the saves directory, they must not lead out of it.
+    check_world_name(world_name)?;
+    // creating the saved_worlds + world name directory, nothing happens if it already exists.
+    let saves_dir = saved_worlds_dir().join(world_name);
+    std::fs::create_dir_all(saves_dir.as_path())?;
+    Ok(saves_dir)
+}
+
+/// Returns the directory all worlds are saved in, without creating it.
+pub fn saved_worlds_dir() -> PathBuf {
+    if let Some(base_dirs) = BaseDirs::new() {
+        base_dirs.data_dir().join(".yavafg").join("saved_worlds")
+    } else {
+        panic!("No valid directory path could be retrieved from the operating system.");
+    }
+}
+
+/// Writes a whole file so that it's either fully written or left untouched if the process dies midway:
+/// the data goes to a temporary file next to it which is synced to disk, then renamed over the destination.
+pub fn write_file_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let file_name = path.file_name().ok_or_else(|| anyhow!("invalid file path {:?}", path))?;
+    let mut temp_name = file_name.to_os_string();
+    temp_name.push(TEMP_FILE_SUFFIX);
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temp_path, path)?;
+
+    // persist the rename itself, directories can't be opened for this on every platform.
+    if let Some(dir) = path.parent().and_then(|x| File::open(x).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Suffix of the temporary files written by [`write_file_atomically`], leftovers are removed by fsck.
+pub const TEMP_FILE_SUFFIX: &str = ".tmp";
+
+/// Returns the directory damaged chunks of a world are moved to, creating it if needed.
+pub fn quarantine_dir(saves_dir: &Path) -> Result<PathBuf> {
+    let dir = saves_dir.join("quarantine");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Keeps a copy of the saved data of a damaged chunk in the quarantine directory, returning its path.
+pub fn quarantine_chunk(saves_dir: &Path, key: IVec3, bytes: &[u8]) -> Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = quarantine_dir(saves_dir)?.join(format!("c.{}.{}.{}.{}.chunk", key.x, key.y, key.z, timestamp));
+    write_file_atomically(&path, bytes)?;
+    Ok(path)
+}
+
 pub fn save_chunk_to_disk(
     chunk_data: &VoxelBuffer<Voxel, ChunkShape>,
     key: IVec3,
-    world_name: &'static str,
+    world_name: &str,
+    materials: &MaterialNames,
 ) -> Result<()> {
-    // getting the directory
-    if let Some(base_dirs) = BaseDirs::new() {
-        // creating the saved_worlds + world name directory, nothing happens if it already exists.
-        let saves_dir = base_dirs.data_dir().join(".yavafg").join("saved_worlds").join(world_name);
-        std::fs::create_dir_all(saves_dir.as_path())?;
-
-        // chunk isn't already saved on disk, so we generate it and save it.
-        let encoded_chunk_data: Vec<u8> = bincode::serialize(&chunk_data)?;
-        let mut tmpcursor = std::io::Cursor::new(encoded_chunk_data);
-        let compressed_chunk_data = zstd::encode_all(&mut tmpcursor, 3)?;
-        let chunk_path = saves_dir.join(format!("{}.{}.chunk", key.x, key.z));
-        // info!("saving chunk to {:?}", chunk_path);
-        std::fs::write(chunk_path, compressed_chunk_data)?;
-        Ok(())
-    } else {
-        panic!("No valid directory path could be retrieved from the operating system.");
+    let saves_dir = world_saves_dir(world_name)?;
+    let encoded_chunk_data = chunk_format::encode_chunk(chunk_data, materials)?;
+
+    let (region, local) = region::region_pos(key);
+    region::with_region_file(&region::region_path(&saves_dir, region), |region| {
+        region.write_chunk(local, &encoded_chunk_data)
+    })
+}
+
+/// Chunks with more voxels than this differing from the generated terrain are saved in full.
+const MAX_DELTA_VOXELS: usize = ChunkShape::USIZE / 8;
+
+/// Saves the voxels of a chunk which differ from the terrain generated for it,
+/// or the whole chunk if too many of them do. Chunks which don't differ at all are removed from the saves.
+pub fn save_chunk_delta_to_disk(
+    chunk_data: &VoxelBuffer<Voxel, ChunkShape>,
+    key: IVec3,
+    world_name: &str,
+    seed: i32,
+    min_height: i32,
+    materials: &MaterialNames,
+) -> Result<()> {
+    let generated = generate_chunk(key, seed, min_height).to_buffer();
+    let delta = ChunkDelta::between(generated.slice(), chunk_data.slice(), GENERATOR_VERSION);
+    if delta.len() > MAX_DELTA_VOXELS {
+        return save_chunk_to_disk(chunk_data, key, world_name, materials);
     }
+
+    let saves_dir = world_saves_dir(world_name)?;
+    let (region, local) = region::region_pos(key);
+    let region_path = region::region_path(&saves_dir, region);
+    if delta.is_empty() {
+        if !region_path.exists() {
+            return Ok(());
+        }
+        return region::with_region_file(&region_path, |region| region.remove_chunk(local));
+    }
+
+    let encoded_delta = chunk_format::encode_delta(&delta, materials)?;
+    region::with_region_file(&region_path, |region| region.write_chunk(local, &encoded_delta))
 }
 
+/// Generates the terrain of a chunk, as if it was never saved.
+pub fn generate_chunk(key: IVec3, seed: i32, min_height: i32) -> SectionedVoxelBuffer<Voxel, ChunkShape> {
+    let mut chunk_data = SectionedVoxelBuffer::<Voxel, ChunkShape>::new_empty(ChunkShape {});
+    TERRAIN_GENERATOR
+        .read()
+        .unwrap()
+        .generate(key, &mut chunk_data, seed, min_height);
+    chunk_data
+}
+
+/// Reads the saved data of a chunk, `None` if it was never saved, or if its data was damaged or saved over another
+/// version of the terrain generator and moved aside.
+/// Errors mean the saved data is still there but couldn't be read, the chunk must not be saved over it.
 pub fn load_chunk_from_disk(
     key: IVec3,
-    world_name: &'static str,
-) -> Result<Option<VoxelBuffer<Voxel, ChunkShape>>> {
-    // getting the directory
-    if let Some(base_dirs) = BaseDirs::new() {
-        // creating the saved_worlds + world name directory, nothing happens if it already exists.
-        let saves_dir = base_dirs.data_dir().join(".yavafg").join("saved_worlds").join(world_name);
-        std::fs::create_dir_all(saves_dir.as_path())?;
-
-        // checking if the chunk file exists
-        let chunk_path = saves_dir.join(format!("{}.{}.chunk", key.x, key.z));
-        if chunk_path.exists() {
-            // info!("loading chunk from {:?}", chunk_path);
-            let encoded_chunk_data = std::fs::read(chunk_path)?;
-            let mut tmpcursor = std::io::Cursor::new(encoded_chunk_data);
-            let decoded_chunk_data = zstd::decode_all(&mut tmpcursor)?;
-            let chunk_data: VoxelBuffer<Voxel, ChunkShape> = bincode::deserialize(&decoded_chunk_data)?;
-            Ok(Some(chunk_data))
-        } else {
-            Ok(None)
+    world_name: &str,
+    materials: &MaterialNames,
+) -> Result<Option<SavedChunk>> {
+    let saves_dir = world_saves_dir(world_name)?;
+
+    let (region, local) = region::region_pos(key);
+    let region_path = region::region_path(&saves_dir, region);
+    // avoid creating empty region files for chunks which were never saved.
+    if !region_path.exists() {
+        return Ok(None);
+    }
+
+    let encoded_chunk_data = region::with_region_file(&region_path, |region| {
+        // the entry was ignored when opening the region, what's left of the chunk is kept before its slot is reused.
+        if let Some(bytes) = region.read_invalid_chunk(local)? {
+            let quarantined = quarantine_chunk(&saves_dir, key, &bytes)?;
+            region.clear_invalid_entry(local)?;
+            error!(
+                "chunk {} of world {} has an invalid region entry, its data was moved to {:?} and it will be regenerated",
+                key, world_name, quarantined
+            );
+            return Ok(None);
         }
-    } else {
-        panic!("No valid directory path could be retrieved from the operating system.");
+        region.read_chunk(local)
+    })?;
+    let Some(encoded_chunk_data) = encoded_chunk_data else {
+        return Ok(None);
+    };
+
+    let reason = match chunk_format::decode_chunk(&encoded_chunk_data, materials) {
+        // deltas only make sense over the terrain they were taken from, worlds holding deltas of another generator
+        // version aren't opened so the chunk is left as it is for the version it belongs to.
+        Ok(SavedChunk::Delta(delta)) if delta.generator_version() != GENERATOR_VERSION => bail!(
+            "it was saved over terrain generator version {}, the current one is {}",
+            delta.generator_version(),
+            GENERATOR_VERSION
+        ),
+        Ok(chunk_data) => return Ok(Some(chunk_data)),
+        Err(e) => format!("it is damaged ({})", e),
+    };
+
+    // the data is moved aside rather than lost when the regenerated chunk gets saved over it.
+    let quarantined = quarantine_chunk(&saves_dir, key, &encoded_chunk_data)?;
+    region::with_region_file(&region_path, |region| region.remove_chunk(local))?;
+    error!(
+        "chunk {} of world {} can't be loaded, {}: it was moved to {:?} and will be regenerated",
+        key, world_name, reason, quarantined
+    );
+    Ok(None)
+}
+
+/// The voxels of a chunk, loaded from the saves or generated.
+pub struct LoadedChunk {
+    pub voxels: SectionedVoxelBuffer<Voxel, ChunkShape>,
+    /// Whether the chunk may be saved, which it can't when its saved data couldn't be read: it would be overwritten.
+    pub saveable: bool,
+}
+
+/// Loads a chunk from the saves of a world, generating the chunks which were never saved.
+pub fn load_chunk(key: IVec3, world_name: &str, seed: i32, min_height: i32, materials: &MaterialNames) -> LoadedChunk {
+    let voxels = match load_chunk_from_disk(key, world_name, materials) {
+        Ok(Some(SavedChunk::Full(chunk_data))) => SectionedVoxelBuffer::from(chunk_data),
+        Ok(Some(SavedChunk::Delta(delta))) => {
+            let mut chunk_data = generate_chunk(key, seed, min_height);
+            delta.apply(&mut chunk_data);
+            chunk_data
+        }
+        Ok(None) => generate_chunk(key, seed, min_height),
+        Err(e) => {
+            error!(
+                "failed to load chunk {} of world {} ({}), it's generated instead and won't be saved over its data",
+                key, world_name, e
+            );
+            return LoadedChunk {
+                voxels: generate_chunk(key, seed, min_height),
+                saveable: false,
+            };
+        }
+    };
+
+    LoadedChunk { voxels, saveable: true }
+}
+
+/// Moves the chunks of saves predating region files into regions, and renames the region files predating vertical chunks.
+fn migrate_chunk_files(world_settings: Res<WorldSettings>) {
+    let migrated = world_saves_dir(&world_settings.name).and_then(|dir| {
+        Ok(region::convert_flat_region_files(&dir)? + region::convert_chunk_files(&dir)?)
+    });
+
+    match migrated {
+        Ok(0) => {}
+        Ok(count) => info!("migrated {} files of world {} to the current region format", count, world_settings.name),
+        Err(e) => error!("failed to migrate the saves of world {}: {}", world_settings.name, e),
     }
 }
 
+/// Sets the terrain surface source, splines and mode of the generator from the world settings.
+/// Fails if the heightmap can't be loaded, the world would be generated from something else than what it's made of.
+pub fn setup_terrain_generator(world_settings: &WorldSettings) -> Result<()> {
+    let source = match &world_settings.heightmap {
+        None => SurfaceSource::Noise,
+        Some(settings) => {
+            let dem = DemHeightmap::load(settings)
+                .map_err(|e| anyhow!("failed to load heightmap {:?}: {}", settings.path, e))?;
+            info!("generating the terrain surface of world {} from {:?}", world_settings.name, settings.path);
+            SurfaceSource::Dem(dem)
+        }
+    };
+
+    let mut generator = TERRAIN_GENERATOR.write().unwrap();
+    generator
+        .set_terrain_mode(world_settings.terrain_mode.clone())?
+        .set_caves(world_settings.caves.clone())?
+        .set_surface_source(source)
+        .set_terrain_splines(world_settings.terrain_splines.clone());
+    Ok(())
+}
 
 /// Queues the terrain gen async tasks for the newly created chunks.
 fn queue_terrain_gen(
     mut commands: Commands,
     new_chunks: Query<(Entity, &Chunk), Added<Chunk>>,
     world_settings: Res<WorldSettings>,
+    materials: Res<MaterialNames>,
+    save_queue: Res<ChunkSaveQueue>,
 ) {
     let task_pool = AsyncComputeTaskPool::get();
 
     let seed = world_settings.seed;
-    let name = world_settings.name;
-
-    let task_gen = |key, seed, name| {
-        load_chunk_from_disk(key, name).ok()
-            .flatten()
-            .unwrap_or_else(|| {
-                let mut chunk_data = VoxelBuffer::<Voxel, ChunkShape>::new_empty(ChunkShape {});
-                TERRAIN_GENERATOR
-                    .read()
-                    .unwrap()
-                    .generate(key, &mut chunk_data, seed);
-                chunk_data
-            })
-    };
+    let name = world_settings.name.clone();
+    let min_height = world_settings.min_height;
 
     new_chunks
         .iter()
         .map(|(entity, key)| (entity, key.0))
         .map(|(entity, key)| {
+            let materials = materials.clone();
+            let name = name.clone();
+            // the chunk may have been unloaded before its last changes were written.
+            let unsaved = save_queue.unsaved(key).cloned();
             (
                 entity,
                 (TerrainGenTask(task_pool.spawn(async move {
-                    task_gen(key, seed.clone(), name)
+                    match unsaved {
+                        Some(voxels) => LoadedChunk { voxels, saveable: true },
+                        None => load_chunk(key, &name, seed, min_height, &materials),
+                    }
                 }))),
             )
         })
@@ -116,12 +308,16 @@ fn wrap_up(
     mut chunk_data: ResMut<ChunkMap<Voxel, ChunkShape>>,
     mut commands: Commands,
     mut dirty_chunks: ResMut<DirtyChunks>,
+    mut save_queue: ResMut<ChunkSaveQueue>,
     mut generated_chunks: Query<(Entity, &Chunk, &mut TerrainGenTask)>,
 ) {
     generated_chunks.for_each_mut(|(entity, chunk, mut gen_task)| {
-        if let Some(data) = future::block_on(future::poll_once(&mut gen_task.0)) {
-            chunk_data.insert(chunk.0, data);
-            dirty_chunks.mark_dirty(chunk.0);
+        if let Some(loaded) = future::block_on(future::poll_once(&mut gen_task.0)) {
+            if !loaded.saveable {
+                save_queue.mark_unsaveable(chunk.0);
+            }
+            chunk_data.insert(chunk.0, loaded.voxels);
+            dirty_chunks.mark_loaded(chunk.0);
             commands.entity(entity).remove::<TerrainGenTask>();
         }
     });
@@ -136,7 +332,8 @@ pub struct TerrainGenSet;
 
 impl Plugin for VoxelWorldTerrainGenPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.configure_sets(
+        app.add_systems(Startup, (chunk_format::init_material_names, migrate_chunk_files))
+        .configure_sets(
             Update,
             TerrainGenSet
                 .after(ChunkLoadingSet),
@@ -147,9 +344,15 @@ impl Plugin for VoxelWorldTerrainGenPlugin {
                 .chain()
                 .in_set(TerrainGenSet)
                 .run_if(in_state(AppState::InGame)),
+        )
+        .add_systems(
+            Update,
+            chunk_format::update_material_names
+                .run_if(resource_changed::<VoxelMaterialRegistry>())
+                .before(TerrainGenSet),
         );
     }
 }
 
 #[derive(Component)]
-pub struct TerrainGenTask(Task<VoxelBuffer<Voxel, ChunkShape>>);
\ No newline at end of file
+pub struct TerrainGenTask(Task<LoadedChunk>);
\ No newline at end of file